@@ -38,7 +38,8 @@
 
         sadmadbotladArtifacts = craneLib.buildDepsOnly ({
           pname = "sadmadbotlad";
-          src = craneLib.cleanCargoSource ./sadmadbotlad;
+          src = craneLib.cleanCargoSource ./.;
+          cargoExtraArgs = "-p sadmadbotlad";
           inherit buildInputs nativeBuildInputs;
 
           LD_LIBRARY_PATH = "${libPath}";
@@ -52,14 +53,16 @@
         frontendArtifacts = frontendCraneLib.buildDepsOnly ({
           pname = "frontend";
 
-          src = frontendCraneLib.cleanCargoSource ./frontend;
+          src = frontendCraneLib.cleanCargoSource ./.;
+          cargoExtraArgs = "-p frontend";
+          CARGO_BUILD_TARGET = "wasm32-unknown-unknown";
           inherit buildInputs nativeBuildInputs;
           doCheck = false;
         });
 
         frontendPackage = with pkgs; frontendCraneLib.buildTrunkPackage {
           src = lib.cleanSourceWith {
-              src = ./frontend;
+              src = ./.;
               filter = path: type:
                 (lib.hasSuffix "\.html" path) ||
                 (lib.hasSuffix "\.css" path) ||
@@ -69,6 +72,9 @@
               ;
             };
 
+          trunkIndexPath = "frontend/index.html";
+          cargoExtraArgs = "-p frontend";
+
           inherit buildInputs nativeBuildInputs;
 
           cargoArtifacts = frontendArtifacts;
@@ -100,7 +106,8 @@
             sadmadbotlad = craneLib.buildPackage {
               LD_LIBRARY_PATH = "${libPath}";
 
-              src = craneLib.path ./sadmadbotlad;
+              src = craneLib.path ./.;
+              cargoExtraArgs = "-p sadmadbotlad";
 
               inherit buildInputs nativeBuildInputs;
 
@@ -113,7 +120,7 @@
                   --prefix PATH : ${pkgs.lib.makeBinPath [ pkgs.playerctl pkgs.yt-dlp ]}
 
                 mkdir -p $out/share
-                cp -r commands $out/share
+                cp -r sadmadbotlad/commands $out/share
               '';
             };
 
@@ -1,21 +1,106 @@
 use futures::{channel::mpsc::Sender, stream::SplitStream, SinkExt, StreamExt};
 use gloo::console;
 use gloo_net::websocket::{futures::WebSocket, Message};
+use sadmadbotlad_protocol::{
+    ClientMessage, EventFilter, EventRecord, HistoryPage, HistoryRequest, ServerMessage,
+    PROTOCOL_VERSION,
+};
 use wasm_bindgen_futures::spawn_local;
-use yew::{html::Scope, prelude::*};
+use yew::{
+    html::Scope,
+    prelude::*,
+    web_sys::{Element, HtmlInputElement},
+};
 
-use crate::{components::event::Event, Alert, AlertEventType};
+use crate::{
+    components::event::Event, non_empty, start_of_day, ws_token, ws_url, Alert, AlertEventType,
+    ALERTS_WS_PATH,
+};
+
+const HISTORY_PAGE_SIZE: u32 = 50;
+
+/// How close to the bottom of the feed, in pixels, the next page starts loading
+const LOAD_MORE_THRESHOLD: i32 = 200;
 
 pub enum Msg {
     Event(Alert),
-    ReplayEvent(Alert),
-    Db(String),
+    ReplayEvent(Entry),
+    History(HistoryPage),
+    LoadMore,
+    Filter(FilterChange),
+    Hide(i32, bool),
+    Delete(i32),
+    EventUpdated(EventRecord),
+    EventDeleted(i32),
     Nothing,
 }
 
+pub enum FilterChange {
+    Kind(Option<&'static str>),
+    User(String),
+    Search(String),
+    Since(String),
+    Until(String),
+    IncludeHidden(bool),
+}
+
+/// An event in the feed, `id` is only known for events that came from the history
+#[derive(Clone)]
+pub struct Entry {
+    id: Option<i32>,
+    alert_type: AlertEventType,
+    hidden: bool,
+}
+
+impl From<EventRecord> for Entry {
+    fn from(event: EventRecord) -> Self {
+        Entry {
+            id: Some(event.id),
+            alert_type: event.alert_type,
+            hidden: event.hidden,
+        }
+    }
+}
+
 pub struct Activity {
     sender: Sender<Message>,
-    alerts: Vec<Alert>,
+    /// newest first
+    alerts: Vec<Entry>,
+    next_cursor: Option<i32>,
+    filter: EventFilter,
+    /// a page has been requested and hasn't arrived yet
+    loading: bool,
+    /// pages still on their way for a filter that has since changed
+    stale_pages: u32,
+}
+
+impl Activity {
+    fn send(&self, message: ClientMessage) {
+        let mut senderc = self.sender.clone();
+        spawn_local(async move {
+            let message = serde_json::to_string(&message).expect("client message");
+            senderc.send(Message::Text(message)).await.expect("send");
+        });
+    }
+
+    fn request_history(&mut self, before: Option<i32>) {
+        self.loading = true;
+        self.send(ClientMessage::History(HistoryRequest {
+            before,
+            limit: HISTORY_PAGE_SIZE,
+            filter: self.filter.clone(),
+        }));
+    }
+
+    /// Starts the feed over with the current filter
+    fn reload(&mut self) {
+        if self.loading {
+            self.stale_pages += 1;
+        }
+        self.alerts.clear();
+        self.next_cursor = None;
+        self.request_history(None);
+    }
 }
 
 impl Component for Activity {
@@ -23,7 +108,7 @@ impl Component for Activity {
     type Properties = ();
 
     fn create(ctx: &Context<Self>) -> Self {
-        let ws = WebSocket::open("ws://localhost:4000").expect("Ws");
+        let ws = WebSocket::open(&ws_url(ALERTS_WS_PATH)).expect("Ws");
 
         let (mut ws_sender, ws_receiver) = ws.split();
 
@@ -35,53 +120,114 @@ impl Component for Activity {
             }
         });
 
-        let mut senderc = sender.clone();
-        spawn_local(async move {
-            senderc
-                .send(Message::Text(String::from("db")))
-                .await
-                .expect("send");
-        });
-
         let scope = ctx.link().clone();
 
         spawn_local(async move {
             handle_alert(ws_receiver, scope).await;
         });
 
-        Self {
+        let mut activity = Self {
             sender,
             alerts: Vec::new(),
+            next_cursor: None,
+            filter: EventFilter::default(),
+            loading: false,
+            stale_pages: 0,
+        };
+
+        activity.send(ClientMessage::Hello {
+            version: PROTOCOL_VERSION,
+        });
+        if let Some(token) = ws_token() {
+            activity.send(ClientMessage::Auth { token });
         }
+        activity.request_history(None);
+
+        activity
     }
 
     fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
-            Msg::ReplayEvent(event) => {
-                let mut senderc = self.sender.clone();
-                spawn_local(async move {
-                    let alert = serde_json::to_string(&event).expect("alert");
-                    senderc
-                        .send(Message::Text(alert))
-                        .await
-                        .expect("send alert");
-                });
+            Msg::ReplayEvent(entry) => {
+                match entry.id {
+                    Some(id) => self.send(ClientMessage::Replay { id }),
+                    None => self.send(ClientMessage::Alert(Alert {
+                        new: false,
+                        r#type: entry.alert_type,
+                    })),
+                }
+                false
+            }
+            Msg::Event(alert) => {
+                // there's no telling if a live event matches the search or time range
+                if !alert.new || !self.filter.is_empty() {
+                    return false;
+                }
+
+                self.alerts.insert(
+                    0,
+                    Entry {
+                        id: None,
+                        alert_type: alert.r#type,
+                        hidden: false,
+                    },
+                );
                 true
             }
-            Msg::Event(mut alert) => {
-                if !alert.new {
+            Msg::History(page) => {
+                if self.stale_pages > 0 {
+                    self.stale_pages -= 1;
                     return false;
                 }
 
-                alert.new = false;
-                self.alerts.push(alert);
-                console::log!(serde_json::to_string(&self.alerts).expect("events"));
+                self.loading = false;
+                self.alerts.extend(page.events.into_iter().map(Entry::from));
+                self.next_cursor = page.next_cursor;
+                true
+            }
+            Msg::LoadMore => {
+                if self.next_cursor.is_some() && !self.loading {
+                    self.request_history(self.next_cursor);
+                }
+                false
+            }
+            Msg::Filter(change) => {
+                match change {
+                    FilterChange::Kind(kind) => self.filter.kind = kind.map(String::from),
+                    FilterChange::User(user) => self.filter.user = non_empty(user),
+                    FilterChange::Search(search) => self.filter.search = non_empty(search),
+                    FilterChange::Since(date) => self.filter.since = start_of_day(&date, 0),
+                    // the whole "until" day is included
+                    FilterChange::Until(date) => self.filter.until = start_of_day(&date, 1),
+                    FilterChange::IncludeHidden(include) => self.filter.include_hidden = include,
+                }
+                self.reload();
+                true
+            }
+            Msg::Hide(id, hidden) => {
+                self.send(ClientMessage::HideEvent { id, hidden });
+                false
+            }
+            Msg::Delete(id) => {
+                let confirmed = gloo::dialogs::confirm("Delete this event for good?");
+                if confirmed {
+                    self.send(ClientMessage::DeleteEvent { id });
+                }
+                false
+            }
+            Msg::EventUpdated(event) => {
+                let include_hidden = self.filter.include_hidden;
+                let id = event.id;
+
+                if event.hidden && !include_hidden {
+                    self.alerts.retain(|entry| entry.id != Some(id));
+                } else if let Some(entry) = self.alerts.iter_mut().find(|e| e.id == Some(id)) {
+                    *entry = event.into();
+                }
                 true
             }
-            Msg::Db(db) => {
-                let (_, db) = db.split_once("::").expect("split db");
-                let db_alerts: Vec<Alert> = serde_json::from_str(&db).expect("db alerts");
-                self.alerts.extend(db_alerts);
+            Msg::EventDeleted(id) => {
+                self.alerts.retain(|entry| entry.id != Some(id));
                 true
             }
             Msg::Nothing => false,
@@ -89,105 +235,171 @@ impl Component for Activity {
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
-        let cb = ctx.link().callback(|message: Msg| message);
+        let link = ctx.link();
+
+        let onscroll = link.callback(|e: yew::Event| {
+            let list: Element = e.target_unchecked_into();
+            if list.scroll_top() + list.client_height() + LOAD_MORE_THRESHOLD
+                >= list.scroll_height()
+            {
+                Msg::LoadMore
+            } else {
+                Msg::Nothing
+            }
+        });
+
         html! {
-            <div class="event-list">
-                {
-                    self.alerts.clone()
-                    .into_iter().map(|s| {
-                        let sc = s.clone();
-                        let on_click = {
-                            let cbc = cb.clone();
-                            move |_| {cbc.emit(Msg::ReplayEvent(sc.clone()))}
-                        };
-                        match s.r#type {
-                            AlertEventType::Follow { follower } => {
-                                html! {
-                                    < Event
-                                        text={format!("{follower} followed")}
-                                        on_click={on_click}
-                                    />
-                                }
-                            },
-                            AlertEventType::Raid { from, viewers } => {
-                                html! {
-                                    < Event
-                                        text={format!("{from} raided with {viewers} viewers")}
-                                        on_click={on_click}
-                                    />
-                                }
-                            },
-                            AlertEventType::Subscribe { subscriber, tier } => {
-                                html! {
-                                    < Event
-                                        text={format!("{subscriber} subscribed tier {tier}")}
-                                        on_click={on_click}
-                                    />
-                                }
-                            },
-                            AlertEventType::GiftSub { gifter, total, tier } => {
-                                html! {
-                                    < Event
-                                        text={format!("{gifter} gifted {total} tier {tier} subs")}
-                                        on_click={on_click}
-                                    />
-                                }
-                            },
-                            AlertEventType::ReSubscribe { subscriber, tier, subscribed_for: _, streak } => {
-                                html! {
-                                    < Event
-                                        text={
-                                                if streak > 1 {
-                                                    format!("{subscriber} resubscribed {streak} months streak")
-                                                } else {
-                                                    format!("{subscriber} resubscribed tier {tier}")
-                                                }
-                                            }
-                                        on_click={on_click}
-                                    />
-                                }
-                            },
-                            AlertEventType::GiftedSub { gifted, tier } => {
-                                html! {
-                                    < Event
-                                        text={format!("{gifted} got gifted a tier {tier} sub")}
-                                        on_click={on_click}
-                                    />
-                                }
-                            },
-                            AlertEventType::Bits { is_anonymous, cheerer, bits, ..} => {
-                                let message = if is_anonymous {
-                                    format!("Anonymous cheered {bits} bits")
-                                } else {
-                                    format!("{cheerer} cheered {bits} bits")
-                                };
-                                html! {
-                                    < Event
-                                        text={message}
-                                        on_click={on_click}
-                                    />
-                                }
-                            },
-                            AlertEventType::Nothing => {html!{}},
-                        }
-                    }).collect::<Html>()
-                }
+            <div class="activity">
+                { self.filter_bar(ctx) }
+                <div class="event-list" {onscroll}>
+                    {
+                        self.alerts.iter().map(|entry| {
+                            let on_click = {
+                                let entry = entry.clone();
+                                link.callback(move |_| Msg::ReplayEvent(entry.clone()))
+                            };
+                            let on_hide = entry.id.map(|id| {
+                                let hidden = !entry.hidden;
+                                link.callback(move |_| Msg::Hide(id, hidden))
+                            });
+                            let on_delete = entry.id.map(|id| link.callback(move |_| Msg::Delete(id)));
+
+                            html! {
+                                < Event
+                                    text={event_text(&entry.alert_type)}
+                                    {on_click}
+                                    {on_hide}
+                                    {on_delete}
+                                    hidden={entry.hidden}
+                                />
+                            }
+                        }).collect::<Html>()
+                    }
+                    if self.next_cursor.is_some() {
+                        <button class="load-more" onclick={link.callback(|_| Msg::LoadMore)}>
+                            {"Load more"}
+                        </button>
+                    }
+                </div>
+            </div>
+        }
+    }
+}
+
+impl Activity {
+    fn filter_bar(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+
+        let input = |change: fn(String) -> FilterChange| {
+            link.callback(move |e: yew::Event| {
+                let input: HtmlInputElement = e.target_unchecked_into();
+                Msg::Filter(change(input.value()))
+            })
+        };
+
+        let kind_button = |label: &'static str, kind: Option<&'static str>| {
+            let selected = self.filter.kind.as_deref() == kind;
+            html! {
+                <button
+                    class={classes!("filter-kind", selected.then_some("selected"))}
+                    onclick={link.callback(move |_| Msg::Filter(FilterChange::Kind(kind)))}
+                >
+                    { label }
+                </button>
+            }
+        };
+
+        html! {
+            <div class="filter-bar">
+                <div class="filter-kinds">
+                    { kind_button("All", None) }
+                    {
+                        AlertEventType::KINDS
+                            .into_iter()
+                            .map(|kind| kind_button(kind, Some(kind)))
+                            .collect::<Html>()
+                    }
+                </div>
+                <input type="text" placeholder="user" onchange={input(FilterChange::User)}/>
+                <input type="text" placeholder="search cheers" onchange={input(FilterChange::Search)}/>
+                <label>
+                    {"from "}
+                    <input type="date" onchange={input(FilterChange::Since)}/>
+                </label>
+                <label>
+                    {"to "}
+                    <input type="date" onchange={input(FilterChange::Until)}/>
+                </label>
+                <label>
+                    <input
+                        type="checkbox"
+                        checked={self.filter.include_hidden}
+                        onchange={link.callback(|e: yew::Event| {
+                            let input: HtmlInputElement = e.target_unchecked_into();
+                            Msg::Filter(FilterChange::IncludeHidden(input.checked()))
+                        })}
+                    />
+                    {" show hidden"}
+                </label>
             </div>
         }
     }
 }
 
+fn event_text(alert_type: &AlertEventType) -> String {
+    match alert_type {
+        AlertEventType::Follow { follower } => format!("{follower} followed"),
+        AlertEventType::Raid { from, viewers } => format!("{from} raided with {viewers} viewers"),
+        AlertEventType::Subscribe { subscriber, tier } => {
+            format!("{subscriber} subscribed tier {tier}")
+        }
+        AlertEventType::GiftSub {
+            gifter,
+            total,
+            tier,
+        } => format!("{gifter} gifted {total} tier {tier} subs"),
+        AlertEventType::ReSubscribe {
+            subscriber,
+            tier,
+            streak,
+            ..
+        } => {
+            if *streak > 1 {
+                format!("{subscriber} resubscribed {streak} months streak")
+            } else {
+                format!("{subscriber} resubscribed tier {tier}")
+            }
+        }
+        AlertEventType::GiftedSub { gifted, tier } => {
+            format!("{gifted} got gifted a tier {tier} sub")
+        }
+        AlertEventType::Bits {
+            is_anonymous: true,
+            bits,
+            ..
+        } => format!("Anonymous cheered {bits} bits"),
+        AlertEventType::Bits { cheerer, bits, .. } => format!("{cheerer} cheered {bits} bits"),
+    }
+}
+
 async fn handle_alert(mut ws_receiver: SplitStream<WebSocket>, scope: Scope<Activity>) {
     while let Some(ws_msg) = ws_receiver.next().await {
         match ws_msg {
             Ok(Message::Text(msg)) => {
-                if msg.starts_with("db") {
-                    scope.send_message(Msg::Db(msg));
-                    return;
-                }
-                let alert = serde_json::from_str::<Alert>(&msg).expect("event");
                 console::log!("got ", &msg);
-                scope.send_message(Msg::Event(alert));
+                match serde_json::from_str::<ServerMessage>(&msg) {
+                    Ok(ServerMessage::Alert(alert)) => scope.send_message(Msg::Event(alert)),
+                    Ok(ServerMessage::History(page)) => scope.send_message(Msg::History(page)),
+                    Ok(ServerMessage::EventUpdated(event)) => {
+                        scope.send_message(Msg::EventUpdated(event))
+                    }
+                    Ok(ServerMessage::EventDeleted { id }) => {
+                        scope.send_message(Msg::EventDeleted(id))
+                    }
+                    Ok(ServerMessage::Error(e)) => console::log!(format!("{e:?}")),
+                    Ok(_) => {}
+                    Err(e) => console::log!(format!("{e:?}")),
+                }
             }
             Ok(msg) => {
                 console::log!(format!("{msg:?}"));
@@ -3,10 +3,15 @@ use std::{cell::RefCell, rc::Rc};
 use futures::{stream::SplitStream, StreamExt};
 use gloo::console;
 use gloo_net::websocket::{futures::WebSocket, Message};
+use wasm_bindgen_futures::spawn_local;
 use yew::prelude::*;
-use yew_router::scope_ext::RouterScopeExt;
 
-use crate::{components::alert::Alert, Alert as AlertEnum, AlertEventType};
+use sadmadbotlad_protocol::ServerMessage;
+
+use crate::{
+    authenticate, components::alert::Alert, ws_url, Alert as AlertEnum, AlertEventType,
+    ALERTS_WS_PATH,
+};
 
 pub enum Msg {
     Event(AlertEnum),
@@ -25,20 +30,13 @@ impl Component for Alerts {
     type Properties = ();
 
     fn create(ctx: &Context<Self>) -> Self {
-        let location = ctx.link().location().unwrap();
-        let query = location.query_str();
-
-        let ws = if query.starts_with("?port=") {
-            WebSocket::open(&format!(
-                "ws://localhost:{}",
-                &query[query.find("?port=").unwrap()..query.find("?").unwrap_or(query.len())]
-            ))
-            .expect("Ws")
-        } else {
-            WebSocket::open("ws://localhost:4000").expect("Ws")
-        };
+        let ws = WebSocket::open(&ws_url(ALERTS_WS_PATH)).expect("Ws");
 
-        let (_, ws_receiver) = ws.split();
+        let (mut ws_sender, ws_receiver) = ws.split();
+
+        spawn_local(async move {
+            authenticate(&mut ws_sender).await;
+        });
 
         let ws_receiver = Rc::new(RefCell::new(ws_receiver));
         ctx.link().send_future(handle_alert(ws_receiver.clone()));
@@ -128,7 +126,6 @@ impl Component for Alerts {
                             html! {<>{format!("{cheerer} cheered {bits} bits!")} <br/> {message}</>},
                         );
                     }
-                    AlertEventType::Nothing => {}
                 }
                 true
             }
@@ -166,10 +163,14 @@ impl Component for Alerts {
 }
 
 async fn handle_alert(ws_receiver: Rc<RefCell<SplitStream<WebSocket>>>) -> Msg {
-    if let Some(ws_msg) = ws_receiver.borrow_mut().next().await {
+    while let Some(ws_msg) = ws_receiver.borrow_mut().next().await {
         match ws_msg {
             Ok(Message::Text(msg)) => {
-                let event = serde_json::from_str::<AlertEnum>(&msg).expect("event");
+                // other overlays' messages (e.g. credits) share this socket
+                let Ok(ServerMessage::Alert(event)) = serde_json::from_str::<ServerMessage>(&msg)
+                else {
+                    continue;
+                };
                 console::log!("got {}", &msg);
                 return Msg::Event(event);
             }
@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+
+use futures::{stream::SplitStream, SinkExt, StreamExt};
+use gloo::{console, timers::callback::Timeout};
+use gloo_net::websocket::{futures::WebSocket, Message};
+use sadmadbotlad_protocol::{ChatEvent, ChatMessage, ClientMessage, EventCategory, ServerMessage};
+use wasm_bindgen_futures::spawn_local;
+use yew::{html::Scope, prelude::*};
+
+use crate::{authenticate, ws_url, ALERTS_WS_PATH};
+
+/// How long a message stays on screen, the last second of it fading out
+const MESSAGE_LIFETIME_MS: u32 = 30_000;
+
+/// Older messages are dropped once there are more than this on screen
+const MAX_MESSAGES: usize = 50;
+
+pub enum Msg {
+    Chat(ChatEvent),
+    Expire(String),
+    Nothing,
+}
+
+/// Live chat overlay
+pub struct Chat {
+    /// oldest first
+    messages: VecDeque<ChatMessage>,
+}
+
+impl Component for Chat {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let ws = WebSocket::open(&ws_url(ALERTS_WS_PATH)).expect("Ws");
+
+        let (mut ws_sender, ws_receiver) = ws.split();
+
+        spawn_local(async move {
+            authenticate(&mut ws_sender).await;
+
+            let subscribe = serde_json::to_string(&ClientMessage::Subscribe {
+                categories: vec![EventCategory::Chat],
+            })
+            .expect("subscribe message");
+
+            if let Err(e) = ws_sender.send(Message::Text(subscribe)).await {
+                console::log!(format!("{e:?}"));
+            }
+        });
+
+        let scope = ctx.link().clone();
+
+        spawn_local(async move {
+            handle_chat(ws_receiver, scope).await;
+        });
+
+        Self {
+            messages: VecDeque::new(),
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Chat(ChatEvent::Message(message)) => {
+                let link = ctx.link().clone();
+                let id = message.id.clone();
+                Timeout::new(MESSAGE_LIFETIME_MS, move || {
+                    link.send_message(Msg::Expire(id));
+                })
+                .forget();
+
+                self.messages.push_back(message);
+                if self.messages.len() > MAX_MESSAGES {
+                    self.messages.pop_front();
+                }
+                true
+            }
+            Msg::Chat(ChatEvent::MessageDeleted { id }) | Msg::Expire(id) => {
+                self.messages.retain(|message| message.id != id);
+                true
+            }
+            Msg::Chat(ChatEvent::Cleared {
+                user_id: Some(user_id),
+            }) => {
+                self.messages.retain(|message| message.user_id != user_id);
+                true
+            }
+            Msg::Chat(ChatEvent::Cleared { user_id: None }) => {
+                self.messages.clear();
+                true
+            }
+            Msg::Nothing => false,
+        }
+    }
+
+    fn view(&self, _ctx: &Context<Self>) -> Html {
+        let fade = format!(
+            "animation-delay: {}ms",
+            MESSAGE_LIFETIME_MS.saturating_sub(1000)
+        );
+
+        html! {
+            <div class="chat">
+                {
+                    self.messages.iter().map(|message| html! {
+                        <div class="chat-message" key={message.id.clone()} style={fade.clone()}>
+                            {
+                                message.badges.iter().filter_map(|badge| {
+                                    let url = badge.image_url.clone()?;
+                                    Some(html! {
+                                        <img class="chat-badge" src={url} alt={badge.set_id.clone()}/>
+                                    })
+                                }).collect::<Html>()
+                            }
+                            <span class="chat-name" style={message.color.as_ref().map(|color| format!("color: {color}"))}>
+                                { &message.display_name }
+                            </span>
+                            {": "}
+                            { message_html(message) }
+                        </div>
+                    }).collect::<Html>()
+                }
+            </div>
+        }
+    }
+}
+
+/// The message text with its twitch emotes swapped for images
+fn message_html(message: &ChatMessage) -> Html {
+    let chars = message.message.chars().collect::<Vec<_>>();
+
+    let mut parts = Vec::new();
+    let mut position = 0;
+
+    for emote in &message.emotes {
+        // twitch's positions can't be trusted to fit, or to not overlap
+        if emote.start < position || emote.end >= chars.len() || emote.end < emote.start {
+            continue;
+        }
+
+        if emote.start > position {
+            let text = chars[position..emote.start].iter().collect::<String>();
+            parts.push(html! { <span>{ text }</span> });
+        }
+
+        let name = chars[emote.start..=emote.end].iter().collect::<String>();
+        parts.push(html! { <img class="chat-emote" src={emote.image_url()} alt={name.clone()} title={name}/> });
+
+        position = emote.end + 1;
+    }
+
+    if position < chars.len() {
+        let text = chars[position..].iter().collect::<String>();
+        parts.push(html! { <span>{ text }</span> });
+    }
+
+    parts.into_iter().collect::<Html>()
+}
+
+async fn handle_chat(mut ws_receiver: SplitStream<WebSocket>, scope: Scope<Chat>) {
+    while let Some(ws_msg) = ws_receiver.next().await {
+        match ws_msg {
+            Ok(Message::Text(msg)) => match serde_json::from_str::<ServerMessage>(&msg) {
+                Ok(ServerMessage::Chat(event)) => scope.send_message(Msg::Chat(event)),
+                Ok(ServerMessage::Error(e)) => console::log!(format!("{e:?}")),
+                Ok(_) => {}
+                Err(e) => console::log!(format!("{e:?}")),
+            },
+            Ok(msg) => {
+                console::log!(format!("{msg:?}"));
+                scope.send_message(Msg::Nothing);
+            }
+            Err(e) => {
+                console::log!(format!("{e:?}"));
+                scope.send_message(Msg::Nothing);
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use futures::{channel::mpsc::Sender, stream::SplitStream, SinkExt, StreamExt};
+use gloo::console;
+use gloo_net::websocket::{futures::WebSocket, Message};
+use sadmadbotlad_protocol::{
+    ChatDeletion, ChatLogMessage, ChatLogPage, ChatLogRequest, ClientMessage, ServerMessage,
+    PROTOCOL_VERSION,
+};
+use wasm_bindgen_futures::spawn_local;
+use yew::{
+    html::Scope,
+    prelude::*,
+    web_sys::{Element, HtmlInputElement},
+};
+
+use crate::{non_empty, start_of_day, ws_token, ws_url, ALERTS_WS_PATH};
+
+const CHAT_LOG_PAGE_SIZE: u32 = 100;
+
+/// How close to the bottom of the log, in pixels, the next page starts loading
+const LOAD_MORE_THRESHOLD: i32 = 200;
+
+pub enum Msg {
+    Page(ChatLogPage),
+    LoadMore,
+    Filter(FilterChange),
+    Error(String),
+    Nothing,
+}
+
+pub enum FilterChange {
+    User(String),
+    Search(String),
+    Since(String),
+    Until(String),
+}
+
+/// Searchable chat history, for mods going over what happened in chat
+pub struct ChatLog {
+    sender: Sender<Message>,
+    /// newest first
+    messages: Vec<ChatLogMessage>,
+    next_cursor: Option<i64>,
+    filter: ChatLogRequest,
+    /// a page has been requested and hasn't arrived yet
+    loading: bool,
+    /// pages still on their way for a filter that has since changed
+    stale_pages: u32,
+    error: Option<String>,
+}
+
+impl ChatLog {
+    fn send(&self, message: ClientMessage) {
+        let mut senderc = self.sender.clone();
+        spawn_local(async move {
+            let message = serde_json::to_string(&message).expect("client message");
+            senderc.send(Message::Text(message)).await.expect("send");
+        });
+    }
+
+    fn request_page(&mut self, before: Option<i64>) {
+        self.loading = true;
+        self.send(ClientMessage::ChatLog(ChatLogRequest {
+            before,
+            ..self.filter.clone()
+        }));
+    }
+
+    /// Starts the log over with the current filter
+    fn reload(&mut self) {
+        if self.loading {
+            self.stale_pages += 1;
+        }
+        self.messages.clear();
+        self.next_cursor = None;
+        self.request_page(None);
+    }
+}
+
+impl Component for ChatLog {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let ws = WebSocket::open(&ws_url(ALERTS_WS_PATH)).expect("Ws");
+
+        let (mut ws_sender, ws_receiver) = ws.split();
+
+        let (sender, mut in_rx) = futures::channel::mpsc::channel::<Message>(1000);
+
+        spawn_local(async move {
+            while let Some(msg) = in_rx.next().await {
+                ws_sender.send(msg).await.expect("send");
+            }
+        });
+
+        let scope = ctx.link().clone();
+
+        spawn_local(async move {
+            handle_messages(ws_receiver, scope).await;
+        });
+
+        let mut chat_log = Self {
+            sender,
+            messages: Vec::new(),
+            next_cursor: None,
+            filter: ChatLogRequest {
+                before: None,
+                limit: CHAT_LOG_PAGE_SIZE,
+                user: None,
+                since: None,
+                until: None,
+                search: None,
+            },
+            loading: false,
+            stale_pages: 0,
+            error: None,
+        };
+
+        chat_log.send(ClientMessage::Hello {
+            version: PROTOCOL_VERSION,
+        });
+        if let Some(token) = ws_token() {
+            chat_log.send(ClientMessage::Auth { token });
+        }
+        // nothing here needs the alerts or credits broadcasts
+        chat_log.send(ClientMessage::Subscribe {
+            categories: Vec::new(),
+        });
+        chat_log.request_page(None);
+
+        chat_log
+    }
+
+    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Page(page) => {
+                if self.stale_pages > 0 {
+                    self.stale_pages -= 1;
+                    return false;
+                }
+
+                self.loading = false;
+                self.error = None;
+                self.messages.extend(page.messages);
+                self.next_cursor = page.next_cursor;
+                true
+            }
+            Msg::LoadMore => {
+                if self.next_cursor.is_some() && !self.loading {
+                    self.request_page(self.next_cursor);
+                }
+                false
+            }
+            Msg::Filter(change) => {
+                match change {
+                    FilterChange::User(user) => self.filter.user = non_empty(user),
+                    FilterChange::Search(search) => self.filter.search = non_empty(search),
+                    FilterChange::Since(date) => self.filter.since = start_of_day(&date, 0),
+                    // the whole "until" day is included
+                    FilterChange::Until(date) => self.filter.until = start_of_day(&date, 1),
+                }
+                self.reload();
+                true
+            }
+            Msg::Error(error) => {
+                self.loading = false;
+                self.error = Some(error);
+                true
+            }
+            Msg::Nothing => false,
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+
+        let onscroll = link.callback(|e: yew::Event| {
+            let list: Element = e.target_unchecked_into();
+            if list.scroll_top() + list.client_height() + LOAD_MORE_THRESHOLD
+                >= list.scroll_height()
+            {
+                Msg::LoadMore
+            } else {
+                Msg::Nothing
+            }
+        });
+
+        let input = |change: fn(String) -> FilterChange| {
+            link.callback(move |e: yew::Event| {
+                let input: HtmlInputElement = e.target_unchecked_into();
+                Msg::Filter(change(input.value()))
+            })
+        };
+
+        html! {
+            <div class="chat-log">
+                <div class="filter-bar">
+                    <input type="text" placeholder="user" onchange={input(FilterChange::User)}/>
+                    <input type="text" placeholder="search messages" onchange={input(FilterChange::Search)}/>
+                    <label>
+                        {"from "}
+                        <input type="date" onchange={input(FilterChange::Since)}/>
+                    </label>
+                    <label>
+                        {"to "}
+                        <input type="date" onchange={input(FilterChange::Until)}/>
+                    </label>
+                </div>
+                if let Some(error) = &self.error {
+                    <p class="chat-log-error">{ error }</p>
+                }
+                <div class="event-list" {onscroll}>
+                    { self.messages.iter().map(chat_line).collect::<Html>() }
+                    if self.next_cursor.is_some() {
+                        <button class="load-more" onclick={link.callback(|_| Msg::LoadMore)}>
+                            {"Load more"}
+                        </button>
+                    }
+                </div>
+            </div>
+        }
+    }
+}
+
+fn chat_line(message: &ChatLogMessage) -> Html {
+    let deletion = message.deletion.map(|deletion| match deletion {
+        ChatDeletion::Deleted => "deleted",
+        ChatDeletion::Timeout => "timed out",
+        ChatDeletion::Ban => "banned",
+        ChatDeletion::Clear => "chat cleared",
+    });
+
+    html! {
+        <div class={classes!("chat-line", deletion.is_some().then_some("deleted"))}>
+            <span class="chat-time">{ message.ctime.format("%Y-%m-%d %H:%M:%S").to_string() }</span>
+            <span class="chat-user" title={message.login.clone()}>{ &message.display_name }</span>
+            <span class="chat-text">{ &message.message }</span>
+            if let Some(deletion) = deletion {
+                <span class="chat-deletion">{ deletion }</span>
+            }
+        </div>
+    }
+}
+
+async fn handle_messages(mut ws_receiver: SplitStream<WebSocket>, scope: Scope<ChatLog>) {
+    while let Some(ws_msg) = ws_receiver.next().await {
+        match ws_msg {
+            Ok(Message::Text(msg)) => match serde_json::from_str::<ServerMessage>(&msg) {
+                Ok(ServerMessage::ChatLog(page)) => scope.send_message(Msg::Page(page)),
+                Ok(ServerMessage::Error(e)) => scope.send_message(Msg::Error(e.message)),
+                Ok(_) => {}
+                Err(e) => console::log!(format!("{e:?}")),
+            },
+            Ok(msg) => {
+                console::log!(format!("{msg:?}"));
+                scope.send_message(Msg::Nothing);
+            }
+            Err(e) => {
+                console::log!(format!("{e:?}"));
+                scope.send_message(Msg::Nothing);
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use yew::{function_component, html, Html, Properties, Callback, MouseEvent, AttrValue};
+use yew::{function_component, html, Html, Properties, Callback, MouseEvent, AttrValue, classes};
+use yew_icons::{Icon, IconId};
 
 use crate::components::replay::ReplayBtn;
 
@@ -6,16 +7,35 @@ use crate::components::replay::ReplayBtn;
 pub struct Props {
     pub text: AttrValue,
     pub on_click: Callback<MouseEvent>,
+    /// only stored events can be hidden or deleted
+    #[prop_or_default]
+    pub on_hide: Option<Callback<MouseEvent>>,
+    #[prop_or_default]
+    pub on_delete: Option<Callback<MouseEvent>>,
+    #[prop_or_default]
+    pub hidden: bool,
 }
 
 #[function_component]
 pub fn Event(props: &Props) -> Html {
     html! {
-        <div class="event">
+        <div class={classes!("event", props.hidden.then_some("hidden"))}>
             <div class="event-args">
                 { props.text.clone() }
             </div>
-            < ReplayBtn on_click={props.on_click.clone()}/>
+            <div class="event-actions">
+                < ReplayBtn on_click={props.on_click.clone()}/>
+                if let Some(on_hide) = props.on_hide.clone() {
+                    <Icon
+                        class="event-btn"
+                        onclick={on_hide}
+                        icon_id={if props.hidden { IconId::FontAwesomeSolidEye } else { IconId::FontAwesomeSolidEyeSlash }}
+                    />
+                }
+                if let Some(on_delete) = props.on_delete.clone() {
+                    <Icon class="event-btn" onclick={on_delete} icon_id={IconId::FontAwesomeSolidTrash}/>
+                }
+            </div>
         </div>
     }
 }
@@ -0,0 +1,109 @@
+use futures::{stream::SplitStream, StreamExt};
+use gloo::console;
+use gloo_net::websocket::{futures::WebSocket, Message};
+use wasm_bindgen_futures::spawn_local;
+use yew::{html::Scope, prelude::*};
+
+use sadmadbotlad_protocol::ServerMessage;
+
+use crate::{authenticate, ws_url, Credits, ALERTS_WS_PATH};
+
+pub enum Msg {
+    Roll(Credits),
+    Done,
+}
+
+pub struct CreditsRoll {
+    credits: Option<Credits>,
+}
+
+impl Component for CreditsRoll {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let ws = WebSocket::open(&ws_url(ALERTS_WS_PATH)).expect("Ws");
+
+        let (mut ws_sender, ws_receiver) = ws.split();
+
+        let scope = ctx.link().clone();
+
+        spawn_local(async move {
+            authenticate(&mut ws_sender).await;
+        });
+
+        spawn_local(async move {
+            handle_credits(ws_receiver, scope).await;
+        });
+
+        Self { credits: None }
+    }
+
+    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Roll(credits) => {
+                self.credits = Some(credits);
+                true
+            }
+            Msg::Done => {
+                self.credits = None;
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let Some(credits) = &self.credits else {
+            return html! {
+                <></>
+            };
+        };
+
+        let onanimationend = ctx.link().callback(|_: AnimationEvent| Msg::Done);
+
+        html! {
+            <div class="credits">
+                <div class="credits-roll" {onanimationend}>
+                    <h1>{"Thanks for watching!"}</h1>
+                    { section("Followers", &credits.followers) }
+                    { section("Subscribers", &credits.subscribers) }
+                    { section("Gifters", &credits.gifters) }
+                    { section("Cheerers", &credits.cheerers) }
+                    { section("Raiders", &credits.raiders) }
+                    { section("Song requests", &credits.song_requesters) }
+                </div>
+            </div>
+        }
+    }
+}
+
+fn section(title: &str, names: &[String]) -> Html {
+    if names.is_empty() {
+        return html! {};
+    }
+
+    html! {
+        <div class="credits-section">
+            <h2>{title}</h2>
+            { names.iter().map(|name| html! { <p>{name}</p> }).collect::<Html>() }
+        </div>
+    }
+}
+
+async fn handle_credits(mut ws_receiver: SplitStream<WebSocket>, scope: Scope<CreditsRoll>) {
+    while let Some(ws_msg) = ws_receiver.next().await {
+        match ws_msg {
+            Ok(Message::Text(msg)) => match serde_json::from_str::<ServerMessage>(&msg) {
+                Ok(ServerMessage::Credits(credits)) => scope.send_message(Msg::Roll(credits)),
+                Ok(_) => {}
+                Err(e) => console::log!(format!("{e:?}")),
+            },
+            Ok(msg) => {
+                console::log!(format!("{msg:?}"));
+            }
+            Err(e) => {
+                console::log!(format!("{e:?}"));
+            }
+        }
+    }
+}
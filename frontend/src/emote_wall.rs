@@ -0,0 +1,189 @@
+use futures::{stream::SplitStream, SinkExt, StreamExt};
+use gloo::{console, timers::callback::Timeout};
+use gloo_net::websocket::{futures::WebSocket, Message};
+use sadmadbotlad_protocol::{ClientMessage, Emote, EmoteEvent, EventCategory, ServerMessage};
+use wasm_bindgen_futures::spawn_local;
+use yew::{html::Scope, prelude::*};
+
+use crate::{authenticate, ws_url, ALERTS_WS_PATH};
+
+/// How long an emote takes to fly across the screen, matches `.flying-emote`
+const FLIGHT_MS: u32 = 6_000;
+
+/// How long a combo stays up after its last message
+const COMBO_MS: u32 = 4_000;
+
+/// Emotes in the air at once, any more are dropped
+const MAX_FLYING: usize = 100;
+
+pub enum Msg {
+    Emotes(EmoteEvent),
+    Landed(u64),
+    ComboOver(u64),
+    Nothing,
+}
+
+struct FlyingEmote {
+    key: u64,
+    emote: Emote,
+    /// percent of the screen height
+    top: f64,
+}
+
+struct Combo {
+    emote: Emote,
+    count: u32,
+    /// only the latest timeout may end the combo
+    generation: u64,
+}
+
+/// Chat's emotes flying across the screen, with a counter while one is spammed
+pub struct EmoteWall {
+    flying: Vec<FlyingEmote>,
+    combo: Option<Combo>,
+    next_key: u64,
+}
+
+impl EmoteWall {
+    fn next_key(&mut self) -> u64 {
+        self.next_key += 1;
+        self.next_key
+    }
+}
+
+impl Component for EmoteWall {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let ws = WebSocket::open(&ws_url(ALERTS_WS_PATH)).expect("Ws");
+
+        let (mut ws_sender, ws_receiver) = ws.split();
+
+        spawn_local(async move {
+            authenticate(&mut ws_sender).await;
+
+            let subscribe = serde_json::to_string(&ClientMessage::Subscribe {
+                categories: vec![EventCategory::Emotes],
+            })
+            .expect("subscribe message");
+
+            if let Err(e) = ws_sender.send(Message::Text(subscribe)).await {
+                console::log!(format!("{e:?}"));
+            }
+        });
+
+        let scope = ctx.link().clone();
+
+        spawn_local(async move {
+            handle_emotes(ws_receiver, scope).await;
+        });
+
+        Self {
+            flying: Vec::new(),
+            combo: None,
+            next_key: 0,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Emotes(EmoteEvent::Used { emotes }) => {
+                for emote in emotes {
+                    if self.flying.len() >= MAX_FLYING {
+                        break;
+                    }
+
+                    let key = self.next_key();
+                    let link = ctx.link().clone();
+                    Timeout::new(FLIGHT_MS, move || link.send_message(Msg::Landed(key))).forget();
+
+                    self.flying.push(FlyingEmote {
+                        key,
+                        emote,
+                        // kept off the very edges so the whole emote shows
+                        top: 5.0 + js_sys::Math::random() * 85.0,
+                    });
+                }
+                true
+            }
+            Msg::Emotes(EmoteEvent::Combo { emote, count }) => {
+                let generation = self.next_key();
+                let link = ctx.link().clone();
+                Timeout::new(COMBO_MS, move || {
+                    link.send_message(Msg::ComboOver(generation))
+                })
+                .forget();
+
+                self.combo = Some(Combo {
+                    emote,
+                    count,
+                    generation,
+                });
+                true
+            }
+            Msg::Landed(key) => {
+                self.flying.retain(|flying| flying.key != key);
+                true
+            }
+            Msg::ComboOver(generation) => {
+                if self
+                    .combo
+                    .as_ref()
+                    .is_some_and(|combo| combo.generation == generation)
+                {
+                    self.combo = None;
+                    return true;
+                }
+                false
+            }
+            Msg::Nothing => false,
+        }
+    }
+
+    fn view(&self, _ctx: &Context<Self>) -> Html {
+        html! {
+            <div class="emote-wall">
+                {
+                    self.flying.iter().map(|flying| html! {
+                        <img
+                            key={flying.key}
+                            class="flying-emote"
+                            style={format!("top: {}%", flying.top)}
+                            src={flying.emote.image_url()}
+                            alt={flying.emote.name.clone()}
+                        />
+                    }).collect::<Html>()
+                }
+                if let Some(combo) = &self.combo {
+                    // keyed on the count so the pop animation plays on every hit
+                    <div class="emote-combo" key={combo.count}>
+                        <img src={combo.emote.image_url()} alt={combo.emote.name.clone()}/>
+                        <span>{ format!("x{} COMBO", combo.count) }</span>
+                    </div>
+                }
+            </div>
+        }
+    }
+}
+
+async fn handle_emotes(mut ws_receiver: SplitStream<WebSocket>, scope: Scope<EmoteWall>) {
+    while let Some(ws_msg) = ws_receiver.next().await {
+        match ws_msg {
+            Ok(Message::Text(msg)) => match serde_json::from_str::<ServerMessage>(&msg) {
+                Ok(ServerMessage::Emotes(event)) => scope.send_message(Msg::Emotes(event)),
+                Ok(ServerMessage::Error(e)) => console::log!(format!("{e:?}")),
+                Ok(_) => {}
+                Err(e) => console::log!(format!("{e:?}")),
+            },
+            Ok(msg) => {
+                console::log!(format!("{msg:?}"));
+                scope.send_message(Msg::Nothing);
+            }
+            Err(e) => {
+                console::log!(format!("{e:?}"));
+                scope.send_message(Msg::Nothing);
+            }
+        }
+    }
+}
@@ -1,12 +1,24 @@
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::{Sink, SinkExt};
+use gloo::console;
+use gloo_net::websocket::Message;
+use sadmadbotlad_protocol::ClientMessage;
 use yew_router::Routable;
 
+pub use sadmadbotlad_protocol::{
+    Alert, AlertEventType, Credits, Queue, SongRequest, StreamSession, StreamStats, StreamSummary,
+};
+
 pub mod activity_feed;
 pub mod alerts;
-pub mod code;
+pub mod chat;
+pub mod chat_log;
 pub mod components;
+pub mod credits;
+pub mod emote_wall;
 pub mod heat;
 pub mod songs;
+pub mod stats;
 
 #[derive(Clone, Routable, PartialEq)]
 pub enum Route {
@@ -16,75 +28,76 @@ pub enum Route {
     Activity,
     #[at("/")]
     Songs,
-    #[at("/code")]
-    Code,
     #[at("/heat")]
     Heat,
+    #[at("/stats")]
+    Stats,
+    #[at("/credits")]
+    Credits,
+    #[at("/chat")]
+    Chat,
+    #[at("/chatlog")]
+    ChatLog,
+    #[at("/emotes")]
+    Emotes,
     #[not_found]
     #[at("/404")]
     NotFound,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct SongRequest {
-    pub title: String,
-    pub url: String,
-    pub id: String,
-    pub user: String,
+pub const ALERTS_WS_PATH: &str = "/ws/alerts";
+pub const SONGS_WS_PATH: &str = "/ws/songs";
+
+/// The url of one of the bot's websockets, on the same host the page was served from
+pub fn ws_url(path: &str) -> String {
+    let location = gloo::utils::window().location();
+
+    let scheme = match location.protocol().as_deref() {
+        Ok("https:") => "wss",
+        _ => "ws",
+    };
+    let host = location.host().unwrap_or_else(|_| String::from("localhost"));
+
+    format!("{scheme}://{host}{path}")
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct Queue {
-    pub queue: [Option<SongRequest>; 20],
-    pub rear: usize,
+/// The websocket token the page was opened with, as `?token=...`
+pub fn ws_token() -> Option<String> {
+    let search = gloo::utils::window().location().search().ok()?;
+
+    search
+        .trim_start_matches('?')
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+        .map(String::from)
 }
 
-impl Queue {
-    pub fn current_song(&self) -> Option<SongRequest> {
-        self.queue[0].clone()
+/// Sends the page's token, if it has one, so the server lets this client in
+pub async fn authenticate<S>(ws_sender: &mut S)
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::fmt::Debug,
+{
+    let Some(token) = ws_token() else {
+        return;
+    };
+
+    let auth = serde_json::to_string(&ClientMessage::Auth { token }).expect("auth message");
+
+    if let Err(e) = ws_sender.send(Message::Text(auth)).await {
+        console::log!(format!("{e:?}"));
     }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub enum AlertEventType {
-    #[default]
-    Nothing,
-    Follow {
-        follower: String,
-    },
-    Raid {
-        from: String,
-        viewers: u64,
-    },
-    Subscribe {
-        subscriber: String,
-        tier: String,
-    },
-    ReSubscribe {
-        subscriber: String,
-        tier: String,
-        subscribed_for: u64,
-        streak: u64,
-    },
-    GiftSub {
-        gifter: String,
-        total: u64,
-        tier: String,
-    },
-    GiftedSub {
-        gifted: String,
-        tier: String,
-    },
-    Bits {
-        message: String,
-        is_anonymous: bool,
-        cheerer: String,
-        bits: u64,
-    },
+pub(crate) fn non_empty(value: String) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
 }
 
-#[derive(Default, Debug, Clone, Deserialize, Serialize)]
-pub struct Alert {
-    new: bool,
-    r#type: AlertEventType,
+/// Midnight UTC, `days_after` the `YYYY-MM-DD` value of a date input
+pub(crate) fn start_of_day(date: &str, days_after: u64) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let date = date.checked_add_days(chrono::Days::new(days_after))?;
+
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc())
 }
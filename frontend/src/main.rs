@@ -1,8 +1,12 @@
 use frontend::activity_feed::Activity;
 use frontend::alerts::Alerts;
-use frontend::code::Code;
+use frontend::chat::Chat;
+use frontend::chat_log::ChatLog;
+use frontend::credits::CreditsRoll;
+use frontend::emote_wall::EmoteWall;
 use frontend::heat::Heat;
 use frontend::songs::Songs;
+use frontend::stats::Stats;
 use frontend::Route;
 use yew::prelude::*;
 use yew_router::prelude::*;
@@ -26,8 +30,12 @@ impl Component for App {
             Route::Alerts => html! { <Alerts /> },
             Route::Songs => html! { <Songs /> },
             Route::Activity => html! { <Activity /> },
-            Route::Code => html! { <Code /> },
             Route::Heat => html! { <Heat /> },
+            Route::Stats => html! { <Stats /> },
+            Route::Credits => html! { <CreditsRoll /> },
+            Route::Chat => html! { <Chat /> },
+            Route::ChatLog => html! { <ChatLog /> },
+            Route::Emotes => html! { <EmoteWall /> },
             Route::NotFound => html! { <h1>{ "404" }</h1> },
         };
 
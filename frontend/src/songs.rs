@@ -2,9 +2,8 @@ use futures::StreamExt;
 use gloo::console;
 use gloo_net::websocket::{futures::WebSocket, Message};
 use yew::prelude::*;
-use yew_router::prelude::*;
 
-use crate::Queue;
+use crate::{authenticate, ws_url, Queue, SONGS_WS_PATH};
 
 pub enum Msg {
     SongsResponse(String),
@@ -20,17 +19,7 @@ impl Component for Songs {
     type Properties = ();
 
     fn create(ctx: &Context<Self>) -> Self {
-        let location = ctx.link().location().unwrap();
-        let query = location.query_str();
-        let ws = if query.starts_with("?port=") {
-            WebSocket::open(&format!(
-                "ws://localhost:{}",
-                query.split_once('=').unwrap().1
-            ))
-            .expect("Ws")
-        } else {
-            WebSocket::open("wss://ws.bksalman.com").expect("Ws")
-        };
+        let ws = WebSocket::open(&ws_url(SONGS_WS_PATH)).expect("Ws");
 
         ctx.link().send_future(do_stuff(ws));
 
@@ -108,6 +97,8 @@ impl Component for Songs {
 }
 
 async fn do_stuff(mut ws: WebSocket) -> Msg {
+    authenticate(&mut ws).await;
+
     let mut yew_msg = Msg::Nothing;
 
     while let Some(ws_msg) = ws.next().await {
@@ -0,0 +1,134 @@
+use futures::{SinkExt, StreamExt};
+use gloo::console;
+use gloo_net::websocket::{futures::WebSocket, Message};
+use yew::prelude::*;
+
+use sadmadbotlad_protocol::{ClientMessage, ServerMessage};
+
+use crate::{authenticate, ws_url, StreamStats, StreamSummary, ALERTS_WS_PATH};
+
+pub enum Msg {
+    Stats(StreamStats),
+    Nothing,
+}
+
+pub struct Stats {
+    stats: Option<StreamStats>,
+}
+
+impl Component for Stats {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let ws = WebSocket::open(&ws_url(ALERTS_WS_PATH)).expect("Ws");
+
+        ctx.link().send_future(request_stats(ws));
+
+        Self { stats: None }
+    }
+
+    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Stats(stats) => {
+                self.stats = Some(stats);
+                true
+            }
+            Msg::Nothing => false,
+        }
+    }
+
+    fn view(&self, _ctx: &Context<Self>) -> Html {
+        let Some(stats) = &self.stats else {
+            return html! {
+                <div class="stats">{"Loading..."}</div>
+            };
+        };
+
+        html! {
+            <div class="stats">
+                <h2>{"Last 7 days"}</h2>
+                { summary(&stats.last_7_days) }
+                <h2>{"Last 30 days"}</h2>
+                { summary(&stats.last_30_days) }
+                <h2>{"Streams"}</h2>
+                {
+                    stats.streams.iter().map(|stream| {
+                        let header = match &stream.session {
+                            Some(session) => format!(
+                                "{} - {} ({}) - peak {} viewers",
+                                session.started_at, session.title, session.game_name, session.peak_viewers
+                            ),
+                            None => String::new(),
+                        };
+                        html! {
+                            <div class="stream">
+                                <h3>{header}</h3>
+                                { summary(stream) }
+                            </div>
+                        }
+                    }).collect::<Html>()
+                }
+            </div>
+        }
+    }
+}
+
+fn summary(summary: &StreamSummary) -> Html {
+    let subs = summary
+        .subs_by_tier
+        .iter()
+        .map(|(tier, count)| format!("tier {tier}: {count}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    html! {
+        <div class="stats-summary">
+            <div class="event">{format!("New followers: {}", summary.new_followers)}</div>
+            <div class="event">{format!("Subs: {}", if subs.is_empty() { String::from("0") } else { subs })}</div>
+            <div class="event">{format!("Gifted subs: {}", summary.gifted_subs)}</div>
+            <div class="event">{format!("Bits: {}", summary.bits)}</div>
+            <div class="event">{format!("Raids: {} ({} viewers)", summary.raids, summary.raid_viewers)}</div>
+            <div class="event">{format!("Songs requested: {}", summary.songs_requested)}</div>
+            <div class="event">{format!("Chat messages: {} from {} chatters", summary.chat_messages, summary.chatters)}</div>
+        </div>
+    }
+}
+
+async fn request_stats(mut ws: WebSocket) -> Msg {
+    authenticate(&mut ws).await;
+
+    let request = serde_json::to_string(&ClientMessage::Stats).expect("stats request");
+
+    if let Err(e) = ws.send(Message::Text(request)).await {
+        console::log!(format!("{e:?}"));
+        return Msg::Nothing;
+    }
+
+    let mut yew_msg = Msg::Nothing;
+
+    while let Some(ws_msg) = ws.next().await {
+        match ws_msg {
+            Ok(Message::Text(msg)) => match serde_json::from_str::<ServerMessage>(&msg) {
+                Ok(ServerMessage::Stats(stats)) => {
+                    yew_msg = Msg::Stats(*stats);
+                    break;
+                }
+                Ok(ServerMessage::Error(e)) => {
+                    console::log!(format!("{e:?}"));
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => console::log!(format!("{e:?}")),
+            },
+            Ok(_) => {}
+            Err(e) => {
+                console::log!(format!("{e:?}"));
+                break;
+            }
+        }
+    }
+
+    ws.close(Some(1000), None).expect("close ws");
+    yew_msg
+}
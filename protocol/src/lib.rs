@@ -0,0 +1,664 @@
+//! Types shared between the bot and the frontend, and the websocket
+//! protocol they use to talk to each other.
+//!
+//! Every websocket frame is a JSON envelope of the form
+//! `{"op": "<operation>", "data": <payload>}`.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever a change to [`ClientMessage`] or [`ServerMessage`]
+/// breaks older clients
+pub const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum AlertEventType {
+    Follow {
+        follower: String,
+    },
+    Raid {
+        from: String,
+        viewers: u64,
+    },
+    Subscribe {
+        subscriber: String,
+        tier: String,
+    },
+    ReSubscribe {
+        subscriber: String,
+        tier: String,
+        subscribed_for: u64,
+        streak: u64,
+    },
+    GiftSub {
+        gifter: String,
+        total: u64,
+        tier: String,
+    },
+    GiftedSub {
+        gifted: String,
+        tier: String,
+    },
+    Bits {
+        message: String,
+        is_anonymous: bool,
+        cheerer: String,
+        bits: u64,
+    },
+}
+
+impl AlertEventType {
+    /// Every variant's [`kind`](AlertEventType::kind)
+    pub const KINDS: [&'static str; 7] = [
+        "Follow",
+        "Raid",
+        "Subscribe",
+        "ReSubscribe",
+        "GiftSub",
+        "GiftedSub",
+        "Bits",
+    ];
+
+    /// The variant's name, same as its tag on the wire
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AlertEventType::Follow { .. } => "Follow",
+            AlertEventType::Raid { .. } => "Raid",
+            AlertEventType::Subscribe { .. } => "Subscribe",
+            AlertEventType::ReSubscribe { .. } => "ReSubscribe",
+            AlertEventType::GiftSub { .. } => "GiftSub",
+            AlertEventType::GiftedSub { .. } => "GiftedSub",
+            AlertEventType::Bits { .. } => "Bits",
+        }
+    }
+
+    /// Who the event is about, `None` for anonymous cheers
+    pub fn user(&self) -> Option<&str> {
+        match self {
+            AlertEventType::Follow { follower } => Some(follower),
+            AlertEventType::Raid { from, .. } => Some(from),
+            AlertEventType::Subscribe { subscriber, .. }
+            | AlertEventType::ReSubscribe { subscriber, .. } => Some(subscriber),
+            AlertEventType::GiftSub { gifter, .. } => Some(gifter),
+            AlertEventType::GiftedSub { gifted, .. } => Some(gifted),
+            AlertEventType::Bits {
+                is_anonymous: true, ..
+            } => None,
+            AlertEventType::Bits { cheerer, .. } => Some(cheerer),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Alert {
+    pub new: bool,
+    pub r#type: AlertEventType,
+}
+
+impl Alert {
+    pub fn raid_test() -> Self {
+        Alert {
+            new: true,
+            r#type: AlertEventType::Raid {
+                from: String::from("lmao"),
+                viewers: 9999,
+            },
+        }
+    }
+    pub fn follow_test() -> Self {
+        Alert {
+            new: true,
+            r#type: AlertEventType::Follow {
+                follower: String::from("lmao"),
+            },
+        }
+    }
+    pub fn sub_test() -> Self {
+        Alert {
+            new: true,
+            r#type: AlertEventType::Subscribe {
+                subscriber: String::from("lmao"),
+                tier: String::from("3"),
+            },
+        }
+    }
+    pub fn resub_test() -> Self {
+        Alert {
+            new: true,
+            r#type: AlertEventType::ReSubscribe {
+                subscriber: String::from("lmao"),
+                tier: String::from("3"),
+                subscribed_for: 4,
+                streak: 2,
+            },
+        }
+    }
+    pub fn giftsub_test() -> Self {
+        Alert {
+            new: true,
+            r#type: AlertEventType::GiftSub {
+                gifter: String::from("lmao"),
+                total: 9999,
+                tier: String::from("3"),
+            },
+        }
+    }
+    pub fn asd_test() -> Self {
+        Alert {
+            new: true,
+            r#type: AlertEventType::Raid {
+                from: String::from("asd"),
+                viewers: 9999,
+            },
+        }
+    }
+}
+
+/// An alert as it was stored in the database
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EventRecord {
+    pub id: i32,
+    pub alert_type: AlertEventType,
+    pub ctime: DateTime<Utc>,
+    /// hidden events are left out of the history, stats and credits
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StreamSession {
+    pub id: i32,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub title: String,
+    pub game_name: String,
+    pub peak_viewers: u32,
+    pub discord_msg_id: Option<String>,
+}
+
+/// What happened during a stream, or over a span of time
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StreamSummary {
+    pub session: Option<StreamSession>,
+    pub new_followers: u64,
+    /// subs and resubs, keyed by tier ("1", "2", "3" or "Prime")
+    pub subs_by_tier: BTreeMap<String, u64>,
+    pub gifted_subs: u64,
+    pub bits: u64,
+    pub raids: u64,
+    pub raid_viewers: u64,
+    pub songs_requested: u64,
+    pub chat_messages: u64,
+    pub chatters: u64,
+}
+
+impl StreamSummary {
+    pub fn add_event(&mut self, event: &AlertEventType) {
+        match event {
+            AlertEventType::Follow { .. } => self.new_followers += 1,
+            AlertEventType::Raid { viewers, .. } => {
+                self.raids += 1;
+                self.raid_viewers += viewers;
+            }
+            AlertEventType::Subscribe { tier, .. } | AlertEventType::ReSubscribe { tier, .. } => {
+                *self.subs_by_tier.entry(tier.clone()).or_default() += 1;
+            }
+            AlertEventType::GiftSub { total, .. } => self.gifted_subs += total,
+            // already counted in the gifter's total
+            AlertEventType::GiftedSub { .. } => {}
+            AlertEventType::Bits { bits, .. } => self.bits += bits,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StreamStats {
+    /// most recent streams first
+    pub streams: Vec<StreamSummary>,
+    pub last_7_days: StreamSummary,
+    pub last_30_days: StreamSummary,
+}
+
+/// Everyone who showed up in the current (or last) stream, in order of appearance
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Credits {
+    pub followers: Vec<String>,
+    pub subscribers: Vec<String>,
+    pub gifters: Vec<String>,
+    pub cheerers: Vec<String>,
+    pub raiders: Vec<String>,
+    pub song_requesters: Vec<String>,
+}
+
+impl Credits {
+    pub fn add_event(&mut self, event: &AlertEventType) {
+        let (list, name) = match event {
+            AlertEventType::Follow { follower } => (&mut self.followers, follower),
+            AlertEventType::Raid { from, .. } => (&mut self.raiders, from),
+            AlertEventType::Subscribe { subscriber, .. }
+            | AlertEventType::ReSubscribe { subscriber, .. } => (&mut self.subscribers, subscriber),
+            AlertEventType::GiftedSub { gifted, .. } => (&mut self.subscribers, gifted),
+            AlertEventType::GiftSub { gifter, .. } => (&mut self.gifters, gifter),
+            AlertEventType::Bits {
+                cheerer,
+                is_anonymous: false,
+                ..
+            } => (&mut self.cheerers, cheerer),
+            AlertEventType::Bits { .. } => return,
+        };
+
+        if !list.contains(name) {
+            list.push(name.clone());
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SongRequest {
+    pub title: String,
+    pub user: String,
+    pub url: String,
+    pub id: String,
+}
+
+pub const QUEUE_SIZE: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueFull;
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "queue is full")
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+/// The song request queue, the first song is the one currently playing
+#[derive(Default, Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Queue {
+    pub queue: [Option<SongRequest>; QUEUE_SIZE],
+    pub rear: usize,
+}
+
+impl Queue {
+    pub fn enqueue(&mut self, item: &SongRequest) -> Result<(), QueueFull> {
+        if self.rear >= QUEUE_SIZE {
+            return Err(QueueFull);
+        }
+
+        self.queue[self.rear] = Some(item.clone());
+
+        self.rear += 1;
+
+        Ok(())
+    }
+
+    pub fn dequeue(&mut self) {
+        if self.rear == 0 {
+            return;
+        }
+
+        self.queue[..self.rear].rotate_left(1);
+        self.queue[self.rear - 1] = None;
+
+        self.rear -= 1;
+    }
+
+    pub fn current_song(&self) -> Option<SongRequest> {
+        self.queue[0].clone()
+    }
+}
+
+/// Broadcasts a client can choose to receive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventCategory {
+    Alerts,
+    Credits,
+    Chat,
+    Emotes,
+}
+
+impl EventCategory {
+    pub const ALL: [EventCategory; 4] = [
+        EventCategory::Alerts,
+        EventCategory::Credits,
+        EventCategory::Chat,
+        EventCategory::Emotes,
+    ];
+}
+
+/// A chat badge, like `subscriber/12`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ChatBadge {
+    pub set_id: String,
+    pub version: String,
+    /// `None` when twitch didn't tell us about this badge
+    pub image_url: Option<String>,
+}
+
+/// Where an emote comes from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmoteProvider {
+    #[default]
+    Twitch,
+    #[serde(rename = "7tv")]
+    SevenTv,
+    Bttv,
+    Ffz,
+}
+
+impl EmoteProvider {
+    pub fn image_url(&self, id: &str) -> String {
+        match self {
+            EmoteProvider::Twitch => {
+                format!("https://static-cdn.jtvnw.net/emoticons/v2/{id}/default/dark/1.0")
+            }
+            EmoteProvider::SevenTv => format!("https://cdn.7tv.app/emote/{id}/1x.webp"),
+            EmoteProvider::Bttv => format!("https://cdn.betterttv.net/emote/{id}/1x"),
+            EmoteProvider::Ffz => format!("https://cdn.frankerfacez.com/emote/{id}/1"),
+        }
+    }
+
+    /// The emote's page on the provider's site, or a big image of it for twitch emotes
+    pub fn page_url(&self, id: &str) -> String {
+        match self {
+            EmoteProvider::Twitch => {
+                format!("https://static-cdn.jtvnw.net/emoticons/v2/{id}/default/dark/3.0")
+            }
+            EmoteProvider::SevenTv => format!("https://7tv.app/emotes/{id}"),
+            EmoteProvider::Bttv => format!("https://betterttv.com/emotes/{id}"),
+            EmoteProvider::Ffz => format!("https://www.frankerfacez.com/emoticon/{id}"),
+        }
+    }
+}
+
+/// An emote that can be used by name in chat
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Emote {
+    pub id: String,
+    pub name: String,
+    pub provider: EmoteProvider,
+}
+
+impl Emote {
+    pub fn image_url(&self) -> String {
+        self.provider.image_url(&self.id)
+    }
+}
+
+/// Where an emote sits in a chat message
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ChatEmote {
+    pub id: String,
+    #[serde(default)]
+    pub provider: EmoteProvider,
+    /// index of the first char, counted in chars rather than bytes
+    pub start: usize,
+    /// index of the last char, inclusive
+    pub end: usize,
+}
+
+impl ChatEmote {
+    pub fn image_url(&self) -> String {
+        self.provider.image_url(&self.id)
+    }
+}
+
+/// A chat message as the chat overlay shows it
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ChatMessage {
+    /// twitch's message id, deletions refer to it
+    pub id: String,
+    pub user_id: String,
+    pub display_name: String,
+    /// `#RRGGBB`, `None` when the chatter never picked a color
+    pub color: Option<String>,
+    pub badges: Vec<ChatBadge>,
+    pub message: String,
+    /// ordered by position
+    pub emotes: Vec<ChatEmote>,
+}
+
+impl ChatMessage {
+    /// Every emote in the message, in order, named the way they were typed
+    pub fn used_emotes(&self) -> Vec<Emote> {
+        let chars = self.message.chars().collect::<Vec<_>>();
+
+        self.emotes
+            .iter()
+            .filter_map(|emote| {
+                Some(Emote {
+                    id: emote.id.clone(),
+                    name: chars.get(emote.start..=emote.end)?.iter().collect(),
+                    provider: emote.provider,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Emotes showing up in chat, for the emote wall
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EmoteEvent {
+    /// the emotes in one chat message
+    Used { emotes: Vec<Emote> },
+    /// the same emote in `count` messages in a short while, sent again
+    /// for every message that keeps the combo going
+    Combo { emote: Emote, count: u32 },
+}
+
+/// Live chat, for the chat overlay
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    Message(ChatMessage),
+    /// a mod deleted a single message
+    MessageDeleted {
+        id: String,
+    },
+    /// someone was timed out or banned, or the whole chat was cleared when
+    /// there is no `user_id`
+    Cleared {
+        user_id: Option<String>,
+    },
+}
+
+/// Narrows down the event history, every field that is set has to match
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EventFilter {
+    /// one of [`AlertEventType::KINDS`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// case insensitive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+    /// full-text search on cheer messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_hidden: bool,
+}
+
+impl EventFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == EventFilter::default()
+    }
+}
+
+/// A page of the event history, newest first
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct HistoryRequest {
+    /// only return events older than this id
+    pub before: Option<i32>,
+    pub limit: u32,
+    #[serde(flatten)]
+    pub filter: EventFilter,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct HistoryPage {
+    pub events: Vec<EventRecord>,
+    /// pass this as `before` to get the next page, `None` when there is no more history
+    pub next_cursor: Option<i32>,
+}
+
+/// Why a chat message was taken down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatDeletion {
+    /// a mod deleted this one message
+    Deleted,
+    Timeout,
+    Ban,
+    /// the whole chat was cleared
+    Clear,
+}
+
+/// A chat message as it was logged
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ChatLogMessage {
+    pub id: i64,
+    /// twitch user id
+    pub user_id: String,
+    pub login: String,
+    pub display_name: String,
+    pub message: String,
+    pub ctime: DateTime<Utc>,
+    pub deletion: Option<ChatDeletion>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// A page of the chat log, newest first. Every filter that is set has to match
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ChatLogRequest {
+    /// only return messages older than this id
+    pub before: Option<i64>,
+    pub limit: u32,
+    /// login or display name, case insensitive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+    /// full-text search on the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ChatLogPage {
+    pub messages: Vec<ChatLogMessage>,
+    /// pass this as `before` to get the next page, `None` when there is no more history
+    pub next_cursor: Option<i64>,
+}
+
+/// What an authenticated client is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// receive broadcasts and read history/stats, for overlays
+    Read,
+    /// also inject and replay alerts, for dashboards
+    Control,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "op", content = "data", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Hello {
+        version: u32,
+    },
+    /// has to be sent before anything else when the server has tokens configured
+    Auth {
+        token: String,
+    },
+    History(HistoryRequest),
+    /// replay a stored event on the alerts overlay
+    Replay {
+        id: i32,
+    },
+    /// replace the set of broadcasts this client receives
+    Subscribe {
+        categories: Vec<EventCategory>,
+    },
+    Stats,
+    /// show an alert that didn't come from twitch (tests, manual replays)
+    Alert(Alert),
+    DeleteEvent {
+        id: i32,
+    },
+    /// hide or unhide a stored event, e.g. one that was only a test
+    HideEvent {
+        id: i32,
+        hidden: bool,
+    },
+    /// search the chat log, for mods
+    ChatLog(ChatLogRequest),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    MalformedMessage,
+    UnsupportedVersion,
+    /// the client has not authenticated, or used an unknown token
+    Unauthorized,
+    /// the client's token doesn't allow this
+    Forbidden,
+    NotFound,
+    Internal,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ProtocolError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "op", content = "data", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Hello { version: u32 },
+    Authenticated { capability: Capability },
+    Alert(Alert),
+    History(HistoryPage),
+    Stats(Box<StreamStats>),
+    Credits(Credits),
+    EventDeleted { id: i32 },
+    EventUpdated(EventRecord),
+    ChatLog(ChatLogPage),
+    Chat(ChatEvent),
+    Emotes(EmoteEvent),
+    Error(ProtocolError),
+}
+
+impl ServerMessage {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        ServerMessage::Error(ProtocolError {
+            code,
+            message: message.into(),
+        })
+    }
+
+    /// The broadcast category this message belongs to, if it is a broadcast
+    pub fn category(&self) -> Option<EventCategory> {
+        match self {
+            ServerMessage::Alert(_) => Some(EventCategory::Alerts),
+            ServerMessage::Credits(_) => Some(EventCategory::Credits),
+            ServerMessage::Chat(_) => Some(EventCategory::Chat),
+            ServerMessage::Emotes(_) => Some(EventCategory::Emotes),
+            _ => None,
+        }
+    }
+}
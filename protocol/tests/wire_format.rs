@@ -0,0 +1,560 @@
+use std::collections::BTreeMap;
+
+use chrono::{TimeZone, Utc};
+use sadmadbotlad_protocol::*;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+/// Serializes `value`, checks it against the expected JSON, and checks that
+/// the expected JSON deserializes back into `value`
+fn assert_wire<T>(value: T, expected: Value)
+where
+    T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    let serialized = serde_json::to_value(&value).expect("serialize");
+    assert_eq!(serialized, expected);
+
+    let deserialized = serde_json::from_value::<T>(expected).expect("deserialize");
+    assert_eq!(deserialized, value);
+}
+
+fn assert_round_trip<T>(value: T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    let serialized = serde_json::to_string(&value).expect("serialize");
+    let deserialized = serde_json::from_str::<T>(&serialized).expect("deserialize");
+    assert_eq!(deserialized, value);
+}
+
+fn song(title: &str) -> SongRequest {
+    SongRequest {
+        title: title.into(),
+        user: "lmao".into(),
+        url: format!("https://youtube.com/watch/{title}"),
+        id: title.into(),
+    }
+}
+
+fn session() -> StreamSession {
+    StreamSession {
+        id: 3,
+        started_at: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+        ended_at: None,
+        title: "coding".into(),
+        game_name: "Software and Game Development".into(),
+        peak_viewers: 12,
+        discord_msg_id: Some("123".into()),
+    }
+}
+
+#[test]
+fn alert_event_types() {
+    let events = [
+        (
+            AlertEventType::Follow {
+                follower: "a".into(),
+            },
+            json!({ "Follow": { "follower": "a" } }),
+        ),
+        (
+            AlertEventType::Raid {
+                from: "a".into(),
+                viewers: 5,
+            },
+            json!({ "Raid": { "from": "a", "viewers": 5 } }),
+        ),
+        (
+            AlertEventType::Subscribe {
+                subscriber: "a".into(),
+                tier: "1".into(),
+            },
+            json!({ "Subscribe": { "subscriber": "a", "tier": "1" } }),
+        ),
+        (
+            AlertEventType::ReSubscribe {
+                subscriber: "a".into(),
+                tier: "2".into(),
+                subscribed_for: 4,
+                streak: 2,
+            },
+            json!({ "ReSubscribe": { "subscriber": "a", "tier": "2", "subscribed_for": 4, "streak": 2 } }),
+        ),
+        (
+            AlertEventType::GiftSub {
+                gifter: "a".into(),
+                total: 5,
+                tier: "3".into(),
+            },
+            json!({ "GiftSub": { "gifter": "a", "total": 5, "tier": "3" } }),
+        ),
+        (
+            AlertEventType::GiftedSub {
+                gifted: "a".into(),
+                tier: "1".into(),
+            },
+            json!({ "GiftedSub": { "gifted": "a", "tier": "1" } }),
+        ),
+        (
+            AlertEventType::Bits {
+                message: "hi".into(),
+                is_anonymous: false,
+                cheerer: "a".into(),
+                bits: 100,
+            },
+            json!({ "Bits": { "message": "hi", "is_anonymous": false, "cheerer": "a", "bits": 100 } }),
+        ),
+    ];
+
+    assert_eq!(
+        events
+            .iter()
+            .map(|(event, _)| event.kind())
+            .collect::<Vec<_>>(),
+        AlertEventType::KINDS
+    );
+
+    for (event, expected) in events {
+        // the kind is stored next to the event, it has to match the tag
+        assert_eq!(
+            expected.as_object().unwrap().keys().next().unwrap(),
+            event.kind()
+        );
+        assert_wire(event, expected);
+    }
+}
+
+#[test]
+fn alert() {
+    assert_wire(
+        Alert::follow_test(),
+        json!({ "new": true, "type": { "Follow": { "follower": "lmao" } } }),
+    );
+}
+
+#[test]
+fn event_record() {
+    assert_wire(
+        EventRecord {
+            id: 7,
+            alert_type: AlertEventType::Follow {
+                follower: "a".into(),
+            },
+            ctime: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+            hidden: false,
+        },
+        json!({
+            "id": 7,
+            "alert_type": { "Follow": { "follower": "a" } },
+            "ctime": "2024-01-02T03:04:05Z",
+            "hidden": false,
+        }),
+    );
+
+    // records from before events could be hidden
+    let record = serde_json::from_value::<EventRecord>(json!({
+        "id": 7,
+        "alert_type": { "Follow": { "follower": "a" } },
+        "ctime": "2024-01-02T03:04:05Z",
+    }))
+    .expect("deserialize");
+    assert!(!record.hidden);
+}
+
+#[test]
+fn song_request_and_queue() {
+    assert_wire(
+        song("x"),
+        json!({ "title": "x", "user": "lmao", "url": "https://youtube.com/watch/x", "id": "x" }),
+    );
+
+    let mut queue = Queue::default();
+    queue.enqueue(&song("x")).unwrap();
+
+    let mut expected = vec![Value::Null; QUEUE_SIZE];
+    expected[0] = serde_json::to_value(song("x")).unwrap();
+
+    assert_wire(queue, json!({ "queue": expected, "rear": 1 }));
+}
+
+#[test]
+fn queue_is_bounded() {
+    let mut queue = Queue::default();
+
+    for i in 0..QUEUE_SIZE {
+        queue.enqueue(&song(&i.to_string())).unwrap();
+    }
+    assert_eq!(queue.enqueue(&song("one too many")), Err(QueueFull));
+
+    queue.dequeue();
+    assert_eq!(queue.current_song(), Some(song("1")));
+    assert_eq!(queue.rear, QUEUE_SIZE - 1);
+    assert_eq!(queue.queue[QUEUE_SIZE - 1], None);
+
+    for _ in 0..QUEUE_SIZE {
+        queue.dequeue();
+    }
+    assert_eq!(queue, Queue::default());
+}
+
+#[test]
+fn stats_and_credits() {
+    let summary = StreamSummary {
+        session: Some(session()),
+        new_followers: 1,
+        subs_by_tier: BTreeMap::from([("1".to_string(), 2)]),
+        ..Default::default()
+    };
+
+    assert_round_trip(ServerMessage::Stats(Box::new(StreamStats {
+        streams: vec![summary.clone()],
+        last_7_days: summary,
+        last_30_days: StreamSummary::default(),
+    })));
+
+    assert_wire(
+        session(),
+        json!({
+            "id": 3,
+            "started_at": "2024-01-02T03:04:05Z",
+            "ended_at": null,
+            "title": "coding",
+            "game_name": "Software and Game Development",
+            "peak_viewers": 12,
+            "discord_msg_id": "123",
+        }),
+    );
+
+    assert_wire(
+        Credits {
+            followers: vec!["a".into()],
+            ..Default::default()
+        },
+        json!({
+            "followers": ["a"],
+            "subscribers": [],
+            "gifters": [],
+            "cheerers": [],
+            "raiders": [],
+            "song_requesters": [],
+        }),
+    );
+}
+
+#[test]
+fn client_messages() {
+    assert_wire(
+        ClientMessage::Hello {
+            version: PROTOCOL_VERSION,
+        },
+        json!({ "op": "hello", "data": { "version": PROTOCOL_VERSION } }),
+    );
+    assert_wire(
+        ClientMessage::Auth {
+            token: "secret".into(),
+        },
+        json!({ "op": "auth", "data": { "token": "secret" } }),
+    );
+    assert_wire(
+        ClientMessage::History(HistoryRequest {
+            before: Some(10),
+            limit: 50,
+            filter: EventFilter::default(),
+        }),
+        json!({ "op": "history", "data": { "before": 10, "limit": 50 } }),
+    );
+    assert_wire(
+        ClientMessage::History(HistoryRequest {
+            before: None,
+            limit: 50,
+            filter: EventFilter {
+                kind: Some("Bits".into()),
+                user: Some("a".into()),
+                since: Some(Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap()),
+                until: None,
+                search: Some("hello".into()),
+                include_hidden: true,
+            },
+        }),
+        json!({
+            "op": "history",
+            "data": {
+                "before": null,
+                "limit": 50,
+                "kind": "Bits",
+                "user": "a",
+                "since": "2024-01-02T00:00:00Z",
+                "search": "hello",
+                "include_hidden": true,
+            },
+        }),
+    );
+    assert_wire(
+        ClientMessage::DeleteEvent { id: 4 },
+        json!({ "op": "delete_event", "data": { "id": 4 } }),
+    );
+    assert_wire(
+        ClientMessage::HideEvent {
+            id: 4,
+            hidden: true,
+        },
+        json!({ "op": "hide_event", "data": { "id": 4, "hidden": true } }),
+    );
+    assert_wire(
+        ClientMessage::ChatLog(ChatLogRequest {
+            before: Some(10),
+            limit: 50,
+            user: Some("a".into()),
+            since: None,
+            until: None,
+            search: Some("https://".into()),
+        }),
+        json!({
+            "op": "chat_log",
+            "data": { "before": 10, "limit": 50, "user": "a", "search": "https://" },
+        }),
+    );
+    assert_wire(
+        ClientMessage::Replay { id: 4 },
+        json!({ "op": "replay", "data": { "id": 4 } }),
+    );
+    assert_wire(
+        ClientMessage::Subscribe {
+            categories: EventCategory::ALL.to_vec(),
+        },
+        json!({ "op": "subscribe", "data": { "categories": ["alerts", "credits", "chat", "emotes"] } }),
+    );
+    assert_wire(ClientMessage::Stats, json!({ "op": "stats" }));
+    assert_wire(
+        ClientMessage::Alert(Alert::raid_test()),
+        json!({
+            "op": "alert",
+            "data": { "new": true, "type": { "Raid": { "from": "lmao", "viewers": 9999 } } },
+        }),
+    );
+}
+
+#[test]
+fn server_messages() {
+    assert_wire(
+        ServerMessage::Hello {
+            version: PROTOCOL_VERSION,
+        },
+        json!({ "op": "hello", "data": { "version": PROTOCOL_VERSION } }),
+    );
+    assert_wire(
+        ServerMessage::Authenticated {
+            capability: Capability::Control,
+        },
+        json!({ "op": "authenticated", "data": { "capability": "control" } }),
+    );
+    assert_wire(
+        ServerMessage::error(ErrorCode::Forbidden, "read-only token"),
+        json!({ "op": "error", "data": { "code": "forbidden", "message": "read-only token" } }),
+    );
+    assert_wire(
+        ServerMessage::History(HistoryPage {
+            events: Vec::new(),
+            next_cursor: None,
+        }),
+        json!({ "op": "history", "data": { "events": [], "next_cursor": null } }),
+    );
+    assert_wire(
+        ServerMessage::EventDeleted { id: 4 },
+        json!({ "op": "event_deleted", "data": { "id": 4 } }),
+    );
+    assert_wire(
+        ServerMessage::ChatLog(ChatLogPage {
+            messages: vec![ChatLogMessage {
+                id: 9,
+                user_id: "123".into(),
+                login: "a".into(),
+                display_name: "A".into(),
+                message: "hi".into(),
+                ctime: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+                deletion: Some(ChatDeletion::Timeout),
+                deleted_at: Some(Utc.with_ymd_and_hms(2024, 1, 2, 3, 5, 0).unwrap()),
+            }],
+            next_cursor: None,
+        }),
+        json!({
+            "op": "chat_log",
+            "data": {
+                "messages": [{
+                    "id": 9,
+                    "user_id": "123",
+                    "login": "a",
+                    "display_name": "A",
+                    "message": "hi",
+                    "ctime": "2024-01-02T03:04:05Z",
+                    "deletion": "timeout",
+                    "deleted_at": "2024-01-02T03:05:00Z",
+                }],
+                "next_cursor": null,
+            },
+        }),
+    );
+    assert_wire(
+        ServerMessage::error(ErrorCode::NotFound, "no event with id 4"),
+        json!({
+            "op": "error",
+            "data": { "code": "not_found", "message": "no event with id 4" },
+        }),
+    );
+    assert_eq!(
+        ServerMessage::Alert(Alert::sub_test()).category(),
+        Some(EventCategory::Alerts)
+    );
+    assert_eq!(
+        ServerMessage::Credits(Credits::default()).category(),
+        Some(EventCategory::Credits)
+    );
+    assert_eq!(
+        ServerMessage::Chat(ChatEvent::Cleared { user_id: None }).category(),
+        Some(EventCategory::Chat)
+    );
+    assert_eq!(ServerMessage::Hello { version: 1 }.category(), None);
+}
+
+#[test]
+fn chat_events() {
+    let emote = ChatEmote {
+        id: "25".into(),
+        provider: EmoteProvider::Twitch,
+        start: 3,
+        end: 7,
+    };
+    assert_eq!(
+        emote.image_url(),
+        "https://static-cdn.jtvnw.net/emoticons/v2/25/default/dark/1.0"
+    );
+
+    // emotes from before third-party ones were relayed
+    let twitch_emote =
+        serde_json::from_value::<ChatEmote>(json!({ "id": "25", "start": 3, "end": 7 }))
+            .expect("deserialize");
+    assert_eq!(twitch_emote, emote);
+
+    assert_wire(
+        ServerMessage::Chat(ChatEvent::Message(ChatMessage {
+            id: "abc".into(),
+            user_id: "123".into(),
+            display_name: "A".into(),
+            color: Some("#FF0000".into()),
+            badges: vec![ChatBadge {
+                set_id: "subscriber".into(),
+                version: "12".into(),
+                image_url: None,
+            }],
+            message: "hi Kappa".into(),
+            emotes: vec![emote],
+        })),
+        json!({
+            "op": "chat",
+            "data": {
+                "type": "message",
+                "id": "abc",
+                "user_id": "123",
+                "display_name": "A",
+                "color": "#FF0000",
+                "badges": [{ "set_id": "subscriber", "version": "12", "image_url": null }],
+                "message": "hi Kappa",
+                "emotes": [{ "id": "25", "provider": "twitch", "start": 3, "end": 7 }],
+            },
+        }),
+    );
+    assert_wire(
+        ServerMessage::Chat(ChatEvent::MessageDeleted { id: "abc".into() }),
+        json!({ "op": "chat", "data": { "type": "message_deleted", "id": "abc" } }),
+    );
+    assert_wire(
+        ServerMessage::Chat(ChatEvent::Cleared {
+            user_id: Some("123".into()),
+        }),
+        json!({ "op": "chat", "data": { "type": "cleared", "user_id": "123" } }),
+    );
+}
+
+#[test]
+fn emotes() {
+    assert_wire(
+        Emote {
+            id: "60ae958e229664e8667aea38".into(),
+            name: "catJAM".into(),
+            provider: EmoteProvider::SevenTv,
+        },
+        json!({ "id": "60ae958e229664e8667aea38", "name": "catJAM", "provider": "7tv" }),
+    );
+
+    for (provider, expected) in [
+        (EmoteProvider::Bttv, json!("bttv")),
+        (EmoteProvider::Ffz, json!("ffz")),
+    ] {
+        assert_wire(provider, expected);
+    }
+
+    let emote = |id: &str, name: &str, provider| Emote {
+        id: id.into(),
+        name: name.into(),
+        provider,
+    };
+
+    assert_wire(
+        ServerMessage::Emotes(EmoteEvent::Combo {
+            emote: emote("25", "Kappa", EmoteProvider::Twitch),
+            count: 5,
+        }),
+        json!({
+            "op": "emotes",
+            "data": {
+                "type": "combo",
+                "emote": { "id": "25", "name": "Kappa", "provider": "twitch" },
+                "count": 5,
+            },
+        }),
+    );
+
+    let message = ChatMessage {
+        id: "abc".into(),
+        user_id: "123".into(),
+        display_name: "A".into(),
+        color: None,
+        badges: Vec::new(),
+        message: "é Kappa catJAM".into(),
+        emotes: vec![
+            ChatEmote {
+                id: "25".into(),
+                provider: EmoteProvider::Twitch,
+                start: 2,
+                end: 6,
+            },
+            ChatEmote {
+                id: "7".into(),
+                provider: EmoteProvider::SevenTv,
+                start: 8,
+                end: 13,
+            },
+            // past the end of the message
+            ChatEmote {
+                id: "1".into(),
+                provider: EmoteProvider::Twitch,
+                start: 20,
+                end: 24,
+            },
+        ],
+    };
+    assert_eq!(
+        message.used_emotes(),
+        vec![
+            emote("25", "Kappa", EmoteProvider::Twitch),
+            emote("7", "catJAM", EmoteProvider::SevenTv),
+        ]
+    );
+}
+
+#[test]
+fn malformed_messages_are_errors() {
+    assert!(serde_json::from_str::<ClientMessage>("db").is_err());
+    assert!(serde_json::from_str::<ClientMessage>(r#"{"op":"nope"}"#).is_err());
+    assert!(serde_json::from_str::<ClientMessage>(r#"{"op":"replay","data":{}}"#).is_err());
+}
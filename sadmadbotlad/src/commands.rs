@@ -1,7 +1,9 @@
 use std::{fs, process, sync::Arc};
 
+use chrono::Utc;
 use hebi::prelude::*;
 use libmpv::Mpv;
+use sadmadbotlad_protocol::{Credits, Emote, EmoteProvider};
 use tokio::sync::{
     broadcast,
     mpsc::{self, Sender},
@@ -11,9 +13,13 @@ use tokio_tungstenite::tungstenite::Message;
 
 use crate::{
     APP, Alert,
+    db::{Db, Viewer},
+    emotes::Emotes,
+    helix::{AnnouncementColor, ChannelInformation, ChatSettingsUpdate, HelixClient, TwitchUser},
     irc::{Tags, TwitchIrcMessage, to_irc_message},
+    moderation::ChatFilters,
     song_requests::{QueueMessages, SongRequest},
-    twitch::TwitchTokenMessages,
+    supervisor::Health,
 };
 
 const RUST_WARRANTY: &str = include_str!("../rust_warranty.txt");
@@ -132,6 +138,96 @@ impl SongRequetsClient {
     }
 }
 
+struct CreditsClient {
+    db: Db,
+    credits_sender: broadcast::Sender<Credits>,
+}
+
+impl CreditsClient {
+    async fn roll(_scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        let credits = this.db.get_credits().await.map_err(hebi::Error::user)?;
+
+        this.credits_sender
+            .send(credits)
+            .map_err(hebi::Error::user)?;
+
+        Ok(())
+    }
+}
+
+struct ViewersClient(Db);
+
+impl ViewersClient {
+    async fn get<'a>(scope: Scope<'a>, this: This<'_, Self>) -> hebi::Result<Option<Value<'a>>> {
+        let name = scope.param::<Str>(0)?;
+
+        let Some(viewer) = this
+            .0
+            .get_viewer(name.as_str().to_string())
+            .await
+            .map_err(hebi::Error::user)?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(scope.new_instance(viewer)?))
+    }
+}
+
+struct EmotesClient(Emotes);
+
+impl EmotesClient {
+    fn get<'a>(scope: Scope<'a>, this: This<'_, Self>) -> hebi::Result<Option<Value<'a>>> {
+        let name = scope.param::<Str>(0)?;
+
+        let Some(emote) = this.0.get(name.as_str()) else {
+            return Ok(None);
+        };
+
+        Ok(Some(scope.new_instance(emote)?))
+    }
+}
+
+struct ModerationClient(Arc<ChatFilters>);
+
+impl ModerationClient {
+    /// Lets someone post a link, returns for how many seconds
+    fn permit(scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<i32> {
+        let login = scope.param::<Str>(0)?;
+        let seconds = this.0.config.permit_seconds;
+
+        this.0
+            .permits
+            .grant(login.as_str(), std::time::Duration::from_secs(seconds));
+
+        Ok(i32::try_from(seconds).unwrap_or(i32::MAX))
+    }
+}
+
+/// e.g. `2d 3h`, `3h 12m` or `5m`
+fn format_duration(duration: chrono::Duration) -> String {
+    let (days, hours, minutes) = (
+        duration.num_days(),
+        duration.num_hours() % 24,
+        duration.num_minutes() % 60,
+    );
+
+    match (days, hours) {
+        (0, 0) if minutes == 0 => String::from("less than a minute"),
+        (0, 0) => format!("{minutes}m"),
+        (0, _) => format!("{hours}h {minutes}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
+
+struct HealthClient(Health);
+
+impl HealthClient {
+    fn status(_scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<String> {
+        Ok(this.0.summary())
+    }
+}
+
 struct SpotifyClient;
 
 impl SpotifyClient {
@@ -150,13 +246,11 @@ impl SpotifyClient {
     }
 }
 
-struct TwitchClient(mpsc::UnboundedSender<TwitchTokenMessages>);
+struct TwitchClient(HelixClient);
 
 impl TwitchClient {
     async fn get_title(_scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<String> {
-        let title = crate::twitch::get_title(this.0.clone())
-            .await
-            .map_err(hebi::Error::user)?;
+        let title = this.0.get_title().await.map_err(hebi::Error::user)?;
 
         Ok(title)
     }
@@ -164,12 +258,241 @@ impl TwitchClient {
     async fn set_title(scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
         let title = scope.param::<Str>(0)?;
 
-        crate::twitch::set_title(title.as_str(), this.0.clone())
+        this.0
+            .set_title(title.as_str())
             .await
             .map_err(hebi::Error::user)?;
 
         Ok(())
     }
+
+    async fn get_game(_scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<String> {
+        let game = this.0.get_game().await.map_err(hebi::Error::user)?;
+
+        Ok(game)
+    }
+
+    /// The category it was set to, `None` if none matched
+    async fn set_game<'a>(
+        scope: Scope<'a>,
+        this: This<'_, Self>,
+    ) -> hebi::Result<Option<Value<'a>>> {
+        let name = scope.param::<Str>(0)?;
+
+        let Some(game) = this
+            .0
+            .set_game_by_name(name.as_str())
+            .await
+            .map_err(hebi::Error::user)?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(game.name.into_value(scope.global())?))
+    }
+
+    async fn get_user<'a>(
+        scope: Scope<'a>,
+        this: This<'_, Self>,
+    ) -> hebi::Result<Option<Value<'a>>> {
+        let login = scope.param::<Str>(0)?;
+
+        let Some(user) = this
+            .0
+            .get_user(login.as_str())
+            .await
+            .map_err(hebi::Error::user)?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(scope.new_instance(user)?))
+    }
+
+    async fn get_channel<'a>(
+        scope: Scope<'a>,
+        this: This<'_, Self>,
+    ) -> hebi::Result<Option<Value<'a>>> {
+        let user_id = scope.param::<Str>(0)?;
+
+        let Some(channel) = this
+            .0
+            .get_channel_information(user_id.as_str())
+            .await
+            .map_err(hebi::Error::user)?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(scope.new_instance(channel)?))
+    }
+
+    async fn timeout(scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        let user_id = scope.param::<Str>(0)?;
+        let seconds = scope.param::<i32>(1)?;
+        let reason = scope.param::<Str>(2)?;
+
+        this.0
+            .timeout_user(user_id.as_str(), seconds.max(1) as u32, reason.as_str())
+            .await
+            .map_err(hebi::Error::user)
+    }
+
+    async fn ban(scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        let user_id = scope.param::<Str>(0)?;
+        let reason = scope.param::<Str>(1)?;
+
+        this.0
+            .ban_user(user_id.as_str(), reason.as_str())
+            .await
+            .map_err(hebi::Error::user)
+    }
+
+    async fn unban(scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        let user_id = scope.param::<Str>(0)?;
+
+        this.0
+            .unban_user(user_id.as_str())
+            .await
+            .map_err(hebi::Error::user)
+    }
+
+    async fn delete_message(scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        let msg_id = scope.param::<Str>(0)?;
+
+        this.0
+            .delete_chat_message(msg_id.as_str())
+            .await
+            .map_err(hebi::Error::user)
+    }
+
+    async fn announce(scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        let message = scope.param::<Str>(0)?;
+
+        this.0
+            .send_announcement(message.as_str(), AnnouncementColor::Primary)
+            .await
+            .map_err(hebi::Error::user)
+    }
+
+    async fn shoutout(scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        let user_id = scope.param::<Str>(0)?;
+
+        this.0
+            .send_shoutout(user_id.as_str())
+            .await
+            .map_err(hebi::Error::user)
+    }
+
+    /// How far into the stream the marker went, `None` while offline
+    async fn marker<'a>(scope: Scope<'a>, this: This<'_, Self>) -> hebi::Result<Option<Value<'a>>> {
+        let description = scope.param::<Str>(0)?;
+
+        let Some(marker) = this
+            .0
+            .create_stream_marker(description.as_str())
+            .await
+            .map_err(hebi::Error::user)?
+        else {
+            return Ok(None);
+        };
+
+        let position = format_duration(chrono::Duration::seconds(marker.position_seconds.into()));
+
+        Ok(Some(position.into_value(scope.global())?))
+    }
+
+    /// The clip's url, `None` while offline
+    async fn clip<'a>(scope: Scope<'a>, this: This<'_, Self>) -> hebi::Result<Option<Value<'a>>> {
+        let Some(clip) = this.0.create_clip().await.map_err(hebi::Error::user)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(clip.url().into_value(scope.global())?))
+    }
+
+    async fn slow(scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        let seconds = scope.param::<i32>(0)?;
+
+        Self::update_chat_settings(
+            this,
+            ChatSettingsUpdate {
+                slow_mode: Some(true),
+                slow_mode_wait_time: Some(seconds.clamp(3, 120) as u32),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    async fn slow_off(_scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        Self::update_chat_settings(
+            this,
+            ChatSettingsUpdate {
+                slow_mode: Some(false),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    async fn emote_only(_scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        Self::update_chat_settings(
+            this,
+            ChatSettingsUpdate {
+                emote_mode: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    async fn emote_only_off(_scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        Self::update_chat_settings(
+            this,
+            ChatSettingsUpdate {
+                emote_mode: Some(false),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    async fn followers_only(scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        let minutes = scope.param::<i32>(0)?;
+
+        Self::update_chat_settings(
+            this,
+            ChatSettingsUpdate {
+                follower_mode: Some(true),
+                // twitch allows up to three months
+                follower_mode_duration: Some(minutes.clamp(0, 129_600) as u32),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    async fn followers_only_off(_scope: Scope<'_>, this: This<'_, Self>) -> hebi::Result<()> {
+        Self::update_chat_settings(
+            this,
+            ChatSettingsUpdate {
+                follower_mode: Some(false),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    async fn update_chat_settings(
+        this: This<'_, Self>,
+        settings: ChatSettingsUpdate,
+    ) -> hebi::Result<()> {
+        this.0
+            .update_chat_settings(&settings)
+            .await
+            .map_err(hebi::Error::user)
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -266,12 +589,18 @@ impl Context {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run_hebi(
     irc_sender: Sender<Message>,
     alert_sender: broadcast::Sender<Alert>,
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
+    helix: HelixClient,
     mpv: Arc<Mpv>,
     queue_sender: mpsc::UnboundedSender<QueueMessages>,
+    credits_sender: broadcast::Sender<Credits>,
+    db: Db,
+    emotes: Emotes,
+    filters: Arc<ChatFilters>,
+    health: Health,
 ) -> Result<Hebi, hebi::Error> {
     let mut vm = Hebi::new();
 
@@ -291,7 +620,7 @@ pub async fn run_hebi(
 
     vm.global().set(
         vm.new_string("twitch_client"),
-        vm.new_instance(TwitchClient(token_sender))?,
+        vm.new_instance(TwitchClient(helix))?,
     );
 
     vm.global().set(
@@ -328,6 +657,34 @@ pub async fn run_hebi(
         vm.new_instance(SpotifyClient)?,
     );
 
+    vm.global().set(
+        vm.new_string("credits_client"),
+        vm.new_instance(CreditsClient {
+            db: db.clone(),
+            credits_sender,
+        })?,
+    );
+
+    vm.global().set(
+        vm.new_string("viewers_client"),
+        vm.new_instance(ViewersClient(db))?,
+    );
+
+    vm.global().set(
+        vm.new_string("emotes_client"),
+        vm.new_instance(EmotesClient(emotes))?,
+    );
+
+    vm.global().set(
+        vm.new_string("moderation_client"),
+        vm.new_instance(ModerationClient(filters))?,
+    );
+
+    vm.global().set(
+        vm.new_string("health_client"),
+        vm.new_instance(HealthClient(health))?,
+    );
+
     vm.eval_async(
         r#"
 ws_sender.send("YEP")
@@ -350,6 +707,41 @@ fn get_module() -> NativeModule {
             class
                 .async_method("get_title", TwitchClient::get_title)
                 .async_method("set_title", TwitchClient::set_title)
+                .async_method("get_game", TwitchClient::get_game)
+                .async_method("set_game", TwitchClient::set_game)
+                .async_method("get_user", TwitchClient::get_user)
+                .async_method("get_channel", TwitchClient::get_channel)
+                .async_method("timeout", TwitchClient::timeout)
+                .async_method("ban", TwitchClient::ban)
+                .async_method("unban", TwitchClient::unban)
+                .async_method("delete_message", TwitchClient::delete_message)
+                .async_method("announce", TwitchClient::announce)
+                .async_method("shoutout", TwitchClient::shoutout)
+                .async_method("marker", TwitchClient::marker)
+                .async_method("clip", TwitchClient::clip)
+                .async_method("slow", TwitchClient::slow)
+                .async_method("slow_off", TwitchClient::slow_off)
+                .async_method("emote_only", TwitchClient::emote_only)
+                .async_method("emote_only_off", TwitchClient::emote_only_off)
+                .async_method("followers_only", TwitchClient::followers_only)
+                .async_method("followers_only_off", TwitchClient::followers_only_off)
+                .finish()
+        })
+        .class::<TwitchUser>("TwitchUser", |class| {
+            class
+                .method("id", |_scope, this| this.id.clone())
+                .method("login", |_scope, this| this.login.clone())
+                .method("name", |_scope, this| this.display_name.clone())
+                .finish()
+        })
+        .class::<ChannelInformation>("ChannelInformation", |class| {
+            class
+                .method("name", |_scope, this| this.broadcaster_name.clone())
+                .method("game", |_scope, this| this.game_name.clone())
+                .method("title", |_scope, this| this.title.clone())
+                .method("url", |_scope, this| {
+                    format!("https://twitch.tv/{}", this.broadcaster_login)
+                })
                 .finish()
         })
         .class::<Context>("Context", |class| {
@@ -411,6 +803,53 @@ fn get_module() -> NativeModule {
                 .method("id", |_scope, this| this.id.clone())
                 .finish()
         })
+        .class::<CreditsClient>("CreditsClient", |class| {
+            class.async_method("roll", CreditsClient::roll).finish()
+        })
+        .class::<ViewersClient>("ViewersClient", |class| {
+            class.async_method("get", ViewersClient::get).finish()
+        })
+        .class::<Viewer>("Viewer", |class| {
+            class
+                .method("name", |_scope, this| this.display_name.clone())
+                .method("first_seen", |_scope, this| {
+                    this.first_seen.format("%Y-%m-%d").to_string()
+                })
+                .method("last_seen", |_scope, this| {
+                    format!("{} ago", format_duration(Utc::now() - this.last_seen))
+                })
+                .method("watch_time", |_scope, this| {
+                    format_duration(this.watch_time)
+                })
+                .method("message_count", |_scope, this| {
+                    i32::try_from(this.message_count).unwrap_or(i32::MAX)
+                })
+                .finish()
+        })
+        .class::<EmotesClient>("EmotesClient", |class| {
+            class.method("get", EmotesClient::get).finish()
+        })
+        .class::<Emote>("Emote", |class| {
+            class
+                .method("name", |_scope, this| this.name.clone())
+                .method("provider", |_scope, this| {
+                    String::from(match this.provider {
+                        EmoteProvider::Twitch => "Twitch",
+                        EmoteProvider::SevenTv => "7TV",
+                        EmoteProvider::Bttv => "BTTV",
+                        EmoteProvider::Ffz => "FFZ",
+                    })
+                })
+                .method("url", |_scope, this| this.provider.page_url(&this.id))
+                .method("image_url", |_scope, this| this.image_url())
+                .finish()
+        })
+        .class::<ModerationClient>("ModerationClient", |class| {
+            class.method("permit", ModerationClient::permit).finish()
+        })
+        .class::<HealthClient>("HealthClient", |class| {
+            class.method("status", HealthClient::status).finish()
+        })
         .class::<SpotifyClient>("SpotifyClient", |class| {
             class
                 .method("get_current_song", |_scope, _this| {
@@ -1,132 +1,1543 @@
-use chrono::{DateTime, Utc};
-use rusqlite::{Connection, OptionalExtension};
-use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
-use crate::{APP, AlertEventType, OneShotSender};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{Connection, OptionalExtension, ToSql, Transaction, params_from_iter};
+use sadmadbotlad_protocol::{
+    ChatDeletion, ChatLogMessage, ChatLogPage, ChatLogRequest, Credits, EventRecord, HistoryPage,
+    HistoryRequest, StreamSession, StreamStats, StreamSummary,
+};
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    AlertEventType,
+    song_requests::{Queue, SongRequest},
+    twitch::UserToken,
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum DatabaseError {
     #[error(transparent)]
     SQLiteError(#[from] rusqlite::Error),
 
-    #[error("value not of type Object")]
-    NotObject,
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("database schema version {0} is newer than this build knows about")]
+    UnknownSchemaVersion(usize),
 
-    #[error("value not of type Array")]
-    NotArray,
+    #[error("database thread is not running")]
+    Closed,
+}
 
-    #[error("value not of type i64")]
-    NotI64,
+type Migration = fn(&Transaction) -> Result<(), DatabaseError>;
 
-    #[error("value not of type bool")]
-    NotBool,
+/// Every schema change in order, `PRAGMA user_version` is how many of them
+/// the database has already been through
+const MIGRATIONS: [Migration; 7] = [
+    initial_schema,
+    event_columns,
+    event_search,
+    users,
+    chat_log,
+    strikes,
+    twitch_tokens,
+];
 
-    #[error("value not of type String")]
-    NotString,
+/// A chatter counts as watching for this long after each message while live
+const PRESENCE_WINDOW: Duration = Duration::minutes(10);
 
-    #[error("Property {0} not found ")]
-    PropertyNotFound(String),
+/// How far back a CLEARCHAT reaches. twitch only clears what is still on
+/// screen, which is rarely more than this
+const CLEAR_WINDOW: Duration = Duration::hours(1);
 
-    #[error("failed to create event")]
-    EventNotReturned,
+type Job = Box<dyn FnOnce(&mut Store) + Send>;
 
-    #[error("event not found")]
-    EventNotFound,
+enum Request {
+    Run(Job),
+    Shutdown,
 }
 
-pub enum DBMessage {
-    NewEvent(AlertEventType),
-    GetEvent(i32, OneShotSender<DbEventRecord>),
-    GetEvents(OneShotSender<Vec<DbEventRecord>>),
+/// Cloneable handle to the database, which lives on its own thread since
+/// sqlite calls block
+#[derive(Clone)]
+pub struct Db {
+    sender: mpsc::UnboundedSender<Request>,
+}
+
+impl Db {
+    /// Opens the database and brings its schema up to date.
+    /// the returned thread exits after [`Db::close`] or once every handle is dropped
+    pub async fn open(path: PathBuf) -> Result<(Self, std::thread::JoinHandle<()>), DatabaseError> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (ready_tx, ready_rx) = oneshot::channel();
+
+        let thread = std::thread::spawn(move || {
+            let mut store = match Store::open(&path) {
+                Ok(store) => {
+                    ready_tx.send(Ok(())).ok();
+                    store
+                }
+                Err(e) => {
+                    ready_tx.send(Err(e)).ok();
+                    return;
+                }
+            };
+
+            while let Some(Request::Run(job)) = receiver.blocking_recv() {
+                job(&mut store);
+            }
+        });
+
+        ready_rx.await.map_err(|_| DatabaseError::Closed)??;
+
+        Ok((Self { sender }, thread))
+    }
+
+    /// Stops the database thread once everything sent before this is handled
+    pub fn close(&self) {
+        self.sender.send(Request::Shutdown).ok();
+    }
+
+    async fn call<T, F>(&self, f: F) -> Result<T, DatabaseError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Store) -> Result<T, DatabaseError> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        self.sender
+            .send(Request::Run(Box::new(move |store| {
+                tx.send(f(store)).ok();
+            })))
+            .map_err(|_| DatabaseError::Closed)?;
+
+        rx.await.map_err(|_| DatabaseError::Closed)?
+    }
+
+    pub async fn new_event(&self, alert: AlertEventType) -> Result<EventRecord, DatabaseError> {
+        self.call(move |store| store.new_event(alert)).await
+    }
+
+    pub async fn get_event(&self, id: i32) -> Result<Option<EventRecord>, DatabaseError> {
+        self.call(move |store| store.get_event(id)).await
+    }
+
+    /// Every event between `since` and `until`, hidden ones included, oldest first
+    pub async fn get_events(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<EventRecord>, DatabaseError> {
+        self.call(move |store| store.get_events(since, until)).await
+    }
+
+    /// Returns how many events were added, events already stored are skipped
+    pub async fn import_events(&self, events: Vec<EventRecord>) -> Result<usize, DatabaseError> {
+        self.call(move |store| store.import_events(events)).await
+    }
+
+    pub async fn get_events_page(
+        &self,
+        request: HistoryRequest,
+    ) -> Result<HistoryPage, DatabaseError> {
+        self.call(move |store| store.get_events_page(request)).await
+    }
+
+    pub async fn start_stream_session(
+        &self,
+        session: NewStreamSession,
+    ) -> Result<StreamSession, DatabaseError> {
+        self.call(move |store| store.start_stream_session(session))
+            .await
+    }
+
+    pub async fn get_live_stream_session(&self) -> Result<Option<StreamSession>, DatabaseError> {
+        self.call(|store| store.get_live_stream_session()).await
+    }
+
+    pub async fn set_stream_session_message_id(
+        &self,
+        id: i32,
+        discord_msg_id: String,
+    ) -> Result<(), DatabaseError> {
+        self.call(move |store| store.set_stream_session_message_id(id, &discord_msg_id))
+            .await
+    }
+
+    pub async fn update_stream_session_info(
+        &self,
+        id: i32,
+        title: String,
+        game_name: String,
+    ) -> Result<(), DatabaseError> {
+        self.call(move |store| store.update_stream_session_info(id, &title, &game_name))
+            .await
+    }
+
+    pub async fn update_peak_viewers(&self, id: i32, viewers: u32) -> Result<(), DatabaseError> {
+        self.call(move |store| store.update_peak_viewers(id, viewers))
+            .await
+    }
+
+    pub async fn end_stream_session(
+        &self,
+        id: i32,
+        ended_at: DateTime<Utc>,
+    ) -> Result<(), DatabaseError> {
+        self.call(move |store| store.end_stream_session(id, ended_at))
+            .await
+    }
+
+    pub async fn new_song_request(&self, song: SongRequest) -> Result<(), DatabaseError> {
+        self.call(move |store| store.new_song_request(&song)).await
+    }
+
+    pub async fn save_queue(&self, queue: Queue) -> Result<(), DatabaseError> {
+        self.call(move |store| store.save_queue(&queue)).await
+    }
+
+    pub async fn get_saved_queue(&self) -> Result<Queue, DatabaseError> {
+        self.call(|store| store.get_saved_queue()).await
+    }
+
+    /// Logs a chat message and counts it towards its sender
+    pub async fn new_chat_message(&self, message: NewChatMessage) -> Result<(), DatabaseError> {
+        self.call(move |store| store.new_chat_message(&message))
+            .await
+    }
+
+    /// Marks a message as deleted by a mod, `msg_id` is twitch's id for it
+    pub async fn delete_chat_message(&self, msg_id: String) -> Result<(), DatabaseError> {
+        self.call(move |store| store.delete_chat_message(&msg_id))
+            .await
+    }
+
+    /// Marks someone's recent messages as removed by a timeout or ban, or
+    /// everyone's when `user_id` is `None`
+    pub async fn clear_chat(
+        &self,
+        user_id: Option<String>,
+        deletion: ChatDeletion,
+    ) -> Result<(), DatabaseError> {
+        self.call(move |store| store.clear_chat(user_id.as_deref(), deletion))
+            .await
+    }
+
+    pub async fn get_chat_log(
+        &self,
+        request: ChatLogRequest,
+    ) -> Result<ChatLogPage, DatabaseError> {
+        self.call(move |store| store.get_chat_log(request)).await
+    }
+
+    /// Deletes chat messages older than `before`, returns how many there were
+    pub async fn prune_chat_log(&self, before: DateTime<Utc>) -> Result<usize, DatabaseError> {
+        self.call(move |store| store.prune_chat_log(before)).await
+    }
+
+    /// Gives someone a strike, returns how many they've had since `since`, this one included
+    pub async fn add_strike(
+        &self,
+        strike: NewStrike,
+        since: DateTime<Utc>,
+    ) -> Result<u32, DatabaseError> {
+        self.call(move |store| store.add_strike(&strike, since))
+            .await
+    }
+
+    pub async fn get_twitch_token(
+        &self,
+        login: String,
+    ) -> Result<Option<UserToken>, DatabaseError> {
+        self.call(move |store| store.get_twitch_token(&login)).await
+    }
+
+    /// Replaces whatever token was stored for the same account
+    pub async fn save_twitch_token(&self, token: UserToken) -> Result<(), DatabaseError> {
+        self.call(move |store| store.save_twitch_token(&token))
+            .await
+    }
+
+    /// Looks a viewer up by login or display name, ignoring case and a leading `@`
+    pub async fn get_viewer(&self, name: String) -> Result<Option<Viewer>, DatabaseError> {
+        self.call(move |store| store.get_viewer(&name)).await
+    }
+
+    pub async fn get_stream_stats(&self) -> Result<StreamStats, DatabaseError> {
+        self.call(|store| store.get_stream_stats()).await
+    }
+
+    pub async fn get_credits(&self) -> Result<Credits, DatabaseError> {
+        self.call(|store| store.get_credits()).await
+    }
+
+    /// `false` if there was no event with that id
+    pub async fn delete_event(&self, id: i32) -> Result<bool, DatabaseError> {
+        self.call(move |store| store.delete_event(id)).await
+    }
+
+    pub async fn set_event_hidden(
+        &self,
+        id: i32,
+        hidden: bool,
+    ) -> Result<Option<EventRecord>, DatabaseError> {
+        self.call(move |store| store.set_event_hidden(id, hidden))
+            .await
+    }
 }
 
-pub struct Store {
+struct Store {
     db: Connection,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DbEvent {
-    alert_type: AlertEventType,
-    ctime: DateTime<Utc>,
+/// The sender of a chat message, as told by its IRC tags
+#[derive(Debug, Clone)]
+pub struct Chatter {
+    /// twitch user id
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
+    pub is_subscriber: bool,
+    pub is_vip: bool,
+    pub is_mod: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DbEventRecord {
-    pub id: i32,
-    pub alert_type: AlertEventType,
-    pub ctime: DateTime<Utc>,
+/// A chat message to be logged
+#[derive(Debug, Clone)]
+pub struct NewChatMessage {
+    pub chatter: Chatter,
+    /// twitch's id for the message, from the `id` tag
+    pub msg_id: Option<String>,
+    pub message: String,
+    /// every IRC tag, stored as they came
+    pub tags: HashMap<String, String>,
+}
+
+/// A message that broke one of the chat filters
+#[derive(Debug, Clone)]
+pub struct NewStrike {
+    pub user_id: String,
+    pub login: String,
+    /// which filter it broke
+    pub filter: String,
+    pub message: String,
+}
+
+/// Everything known about someone who has chatted
+#[derive(Debug, Clone)]
+pub struct Viewer {
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub message_count: u64,
+    /// sub/VIP/mod status as of their last message
+    pub is_subscriber: bool,
+    pub is_vip: bool,
+    pub is_mod: bool,
+    /// estimated from chat presence during live streams
+    pub watch_time: Duration,
+}
+
+fn viewer_from_row(row: &rusqlite::Row) -> Result<Viewer, rusqlite::Error> {
+    Ok(Viewer {
+        id: row.get("id")?,
+        login: row.get("login")?,
+        display_name: row.get("display_name")?,
+        first_seen: row.get("first_seen")?,
+        last_seen: row.get("last_seen")?,
+        message_count: row.get("message_count")?,
+        is_subscriber: row.get("is_subscriber")?,
+        is_vip: row.get("is_vip")?,
+        is_mod: row.get("is_mod")?,
+        watch_time: Duration::seconds(row.get("watch_time")?),
+    })
+}
+
+fn twitch_token_from_row(row: &rusqlite::Row) -> Result<UserToken, rusqlite::Error> {
+    Ok(UserToken {
+        login: row.get("login")?,
+        user_id: row.get("user_id")?,
+        access_token: row.get("access_token")?,
+        refresh_token: row.get("refresh_token")?,
+        scopes: row
+            .get::<_, String>("scopes")?
+            .split_whitespace()
+            .map(String::from)
+            .collect(),
+        expires_at: row.get("expires_at")?,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct NewStreamSession {
+    pub started_at: DateTime<Utc>,
+    pub title: String,
+    pub game_name: String,
+    pub viewers: u32,
+}
+
+fn stream_session_from_row(row: &rusqlite::Row) -> Result<StreamSession, rusqlite::Error> {
+    Ok(StreamSession {
+        id: row.get("id")?,
+        started_at: row.get("started_at")?,
+        ended_at: row.get("ended_at")?,
+        title: row.get("title")?,
+        game_name: row.get("game_name")?,
+        peak_viewers: row.get("peak_viewers")?,
+        discord_msg_id: row.get("discord_msg_id")?,
+    })
+}
+
+/// Expects `id`, `payload`, `ctime` and `hidden` columns
+fn event_from_row(row: &rusqlite::Row) -> Result<EventRecord, DatabaseError> {
+    Ok(EventRecord {
+        id: row.get("id")?,
+        alert_type: serde_json::from_str(&row.get::<_, String>("payload")?)?,
+        ctime: row.get("ctime")?,
+        hidden: row.get("hidden")?,
+    })
+}
+
+fn chat_log_message_from_row(row: &rusqlite::Row) -> Result<ChatLogMessage, DatabaseError> {
+    let deletion = row
+        .get::<_, Option<String>>("deletion")?
+        .map(|deletion| serde_json::from_value(serde_json::Value::String(deletion)))
+        .transpose()?;
+
+    Ok(ChatLogMessage {
+        id: row.get("id")?,
+        user_id: row.get("user_id")?,
+        login: row.get("login")?,
+        display_name: row.get("display_name")?,
+        message: row.get("message")?,
+        ctime: row.get("ctime")?,
+        deletion,
+        deleted_at: row.get("deleted_at")?,
+    })
+}
+
+/// The text a [`ChatDeletion`] is stored as
+fn deletion_to_sql(deletion: ChatDeletion) -> Result<String, DatabaseError> {
+    match serde_json::to_value(deletion)? {
+        serde_json::Value::String(deletion) => Ok(deletion),
+        _ => unreachable!("ChatDeletion is a unit enum"),
+    }
+}
+
+fn events_from_rows(mut rows: rusqlite::Rows) -> Result<Vec<EventRecord>, DatabaseError> {
+    let mut events = Vec::new();
+
+    while let Some(row) = rows.next()? {
+        events.push(event_from_row(row)?);
+    }
+
+    Ok(events)
 }
 
 impl Store {
-    pub fn new() -> Result<Self, DatabaseError> {
-        let db = Connection::open(&APP.config.database_path)?;
+    fn open(path: &Path) -> Result<Self, DatabaseError> {
+        let mut db = Connection::open(path)?;
 
-        db.execute(
+        migrate(&mut db)?;
+
+        Ok(Self { db })
+    }
+
+    fn new_event(&self, alert: AlertEventType) -> Result<EventRecord, DatabaseError> {
+        let ctime = Utc::now();
+
+        let id = self.db.query_one(
             r#"
-                CREATE TABLE IF NOT EXISTS events (
-                    id INTEGER PRIMARY KEY,
-                    data TEXT NOT NULL
-                );
+                INSERT INTO events (event_type, user, payload, ctime, stream_session_id)
+                VALUES (?1, ?2, ?3, ?4, (SELECT id FROM stream_sessions WHERE ended_at IS NULL ORDER BY id DESC LIMIT 1))
+                RETURNING id
             "#,
-            (),
+            (
+                alert.kind(),
+                alert.user(),
+                serde_json::to_string(&alert)?,
+                ctime,
+            ),
+            |row| row.get(0),
         )?;
 
-        Ok(Self { db })
+        Ok(EventRecord {
+            id,
+            alert_type: alert,
+            ctime,
+            hidden: false,
+        })
     }
 
-    pub fn new_event(&self, alert: AlertEventType) -> Result<DbEventRecord, DatabaseError> {
-        let now = Utc::now();
+    fn get_events(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<EventRecord>, DatabaseError> {
+        let mut stmt = self.db.prepare(
+            r#"
+                SELECT id, payload, ctime, hidden FROM events
+                WHERE (?1 IS NULL OR ctime >= ?1) AND (?2 IS NULL OR ctime < ?2)
+                ORDER BY ctime, id
+            "#,
+        )?;
+
+        events_from_rows(stmt.query((since, until))?)
+    }
+
+    /// Imported events get new ids and no stream session. An event with the
+    /// same time and payload as a stored one is taken to be the same event
+    fn import_events(&mut self, events: Vec<EventRecord>) -> Result<usize, DatabaseError> {
+        let tx = self.db.transaction()?;
+        let mut imported = 0;
+
+        {
+            let mut insert = tx.prepare(
+                r#"
+                    INSERT INTO events (event_type, user, payload, ctime, hidden)
+                    SELECT ?1, ?2, ?3, ?4, ?5
+                    WHERE NOT EXISTS (SELECT 1 FROM events WHERE ctime = ?4 AND payload = ?3)
+                "#,
+            )?;
+
+            for event in events {
+                imported += insert.execute((
+                    event.alert_type.kind(),
+                    event.alert_type.user(),
+                    serde_json::to_string(&event.alert_type)?,
+                    event.ctime,
+                    event.hidden,
+                ))?;
+            }
+        }
+
+        tx.commit()?;
+
+        Ok(imported)
+    }
+
+    fn get_event(&self, id: i32) -> Result<Option<EventRecord>, DatabaseError> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT id, payload, ctime, hidden FROM events WHERE id = ?1")?;
 
+        Ok(events_from_rows(stmt.query((id,))?)?.pop())
+    }
+
+    /// Newest events matching `request.filter` first, `request.limit` at a time
+    fn get_events_page(&self, request: HistoryRequest) -> Result<HistoryPage, DatabaseError> {
+        let HistoryRequest {
+            before,
+            limit,
+            filter,
+        } = request;
+
+        // `?` parameters are numbered in the order they appear, same as `params`
+        let mut conditions = Vec::new();
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+        let mut condition = |sql: &str, param: Box<dyn ToSql>| {
+            conditions.push(sql.to_string());
+            params.push(param);
+        };
+
+        if let Some(before) = before {
+            condition("id < ?", Box::new(before));
+        }
+        if let Some(kind) = filter.kind {
+            condition("event_type = ?", Box::new(kind));
+        }
+        if let Some(user) = filter.user {
+            condition("user = ? COLLATE NOCASE", Box::new(user));
+        }
+        if let Some(since) = filter.since {
+            condition("ctime >= ?", Box::new(since));
+        }
+        if let Some(until) = filter.until {
+            condition("ctime < ?", Box::new(until));
+        }
+        if let Some(search) = filter.search {
+            // searched as a phrase, so fts5 query syntax in the input can't fail the query
+            condition(
+                "id IN (SELECT rowid FROM event_messages WHERE event_messages MATCH ?)",
+                Box::new(format!("\"{}\"", search.replace('"', "\"\""))),
+            );
+        }
+        if !filter.include_hidden {
+            conditions.push(String::from("NOT hidden"));
+        }
+
+        params.push(Box::new(limit));
+
+        let sql = format!(
+            "SELECT id, payload, ctime, hidden FROM events {} ORDER BY id DESC LIMIT ?",
+            if conditions.is_empty() {
+                String::new()
+            } else {
+                format!("WHERE {}", conditions.join(" AND "))
+            }
+        );
+
+        let mut stmt = self.db.prepare(&sql)?;
+
+        let events = events_from_rows(stmt.query(params_from_iter(params))?)?;
+
+        let next_cursor = if events.len() as u32 == limit {
+            events.last().map(|event| event.id)
+        } else {
+            None
+        };
+
+        Ok(HistoryPage {
+            events,
+            next_cursor,
+        })
+    }
+
+    fn start_stream_session(
+        &self,
+        session: NewStreamSession,
+    ) -> Result<StreamSession, DatabaseError> {
         Ok(self.db.query_one(
             r#"
-                INSERT INTO events (data) VALUES (?1) RETURNING id, data
+                INSERT INTO stream_sessions (started_at, title, game_name, peak_viewers)
+                VALUES (?1, ?2, ?3, ?4)
+                RETURNING *
             "#,
-            (serde_json::to_string(&DbEvent {
-                alert_type: alert,
-                ctime: now,
-            })
-            .unwrap(),),
-            |row| {
-                let event = serde_json::from_str::<DbEvent>(&row.get::<_, String>(1)?).unwrap();
-                Ok(DbEventRecord {
-                    id: row.get(0)?,
-                    alert_type: event.alert_type,
-                    ctime: event.ctime,
-                })
-            },
+            (
+                session.started_at,
+                session.title,
+                session.game_name,
+                session.viewers,
+            ),
+            stream_session_from_row,
         )?)
     }
 
-    pub fn get_events(&self) -> Result<Vec<DbEventRecord>, DatabaseError> {
-        let mut stmt = self.db.prepare("SELECT * FROM events")?;
+    /// The latest stream session that hasn't ended yet
+    fn get_live_stream_session(&self) -> Result<Option<StreamSession>, DatabaseError> {
+        Ok(self
+            .db
+            .query_one(
+                r#"
+                    SELECT * FROM stream_sessions
+                    WHERE ended_at IS NULL
+                    ORDER BY id DESC
+                    LIMIT 1
+                "#,
+                (),
+                stream_session_from_row,
+            )
+            .optional()?)
+    }
+
+    fn set_stream_session_message_id(
+        &self,
+        id: i32,
+        discord_msg_id: &str,
+    ) -> Result<(), DatabaseError> {
+        self.db.execute(
+            "UPDATE stream_sessions SET discord_msg_id = ?2 WHERE id = ?1",
+            (id, discord_msg_id),
+        )?;
+
+        Ok(())
+    }
 
-        let res: Result<Vec<DbEventRecord>, rusqlite::Error> = stmt
+    /// Update the current title and game, keeping a record of the change
+    fn update_stream_session_info(
+        &self,
+        id: i32,
+        title: &str,
+        game_name: &str,
+    ) -> Result<(), DatabaseError> {
+        self.db.execute(
+            "UPDATE stream_sessions SET title = ?2, game_name = ?3 WHERE id = ?1",
+            (id, title, game_name),
+        )?;
+
+        self.db.execute(
+            r#"
+                INSERT INTO stream_session_changes (stream_session_id, title, game_name, ctime)
+                VALUES (?1, ?2, ?3, ?4)
+            "#,
+            (id, title, game_name, Utc::now()),
+        )?;
+
+        Ok(())
+    }
+
+    fn update_peak_viewers(&self, id: i32, viewers: u32) -> Result<(), DatabaseError> {
+        self.db.execute(
+            "UPDATE stream_sessions SET peak_viewers = MAX(peak_viewers, ?2) WHERE id = ?1",
+            (id, viewers),
+        )?;
+
+        Ok(())
+    }
+
+    fn end_stream_session(&self, id: i32, ended_at: DateTime<Utc>) -> Result<(), DatabaseError> {
+        self.db.execute(
+            "UPDATE stream_sessions SET ended_at = ?2 WHERE id = ?1",
+            (id, ended_at),
+        )?;
+
+        Ok(())
+    }
+
+    fn new_song_request(&self, song: &SongRequest) -> Result<(), DatabaseError> {
+        self.db.execute(
+            r#"
+                INSERT INTO song_requests (user, title, url, ctime, stream_session_id)
+                VALUES (?1, ?2, ?3, ?4, (SELECT id FROM stream_sessions WHERE ended_at IS NULL ORDER BY id DESC LIMIT 1))
+            "#,
+            (&song.user, &song.title, &song.url, Utc::now()),
+        )?;
+
+        Ok(())
+    }
+
+    /// Replaces the saved song queue, so it can be picked up again after a restart
+    fn save_queue(&mut self, queue: &Queue) -> Result<(), DatabaseError> {
+        let tx = self.db.transaction()?;
+
+        tx.execute("DELETE FROM song_queue", ())?;
+
+        for (position, song) in queue.queue.iter().flatten().enumerate() {
+            tx.execute(
+                r#"
+                    INSERT INTO song_queue (position, video_id, user, title, url)
+                    VALUES (?1, ?2, ?3, ?4, ?5)
+                "#,
+                (position, &song.id, &song.user, &song.title, &song.url),
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn get_saved_queue(&self) -> Result<Queue, DatabaseError> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT video_id, user, title, url FROM song_queue ORDER BY position")?;
+
+        let songs = stmt
             .query_map((), |row| {
-                let id = row.get(0)?;
-                let event = serde_json::from_str::<DbEvent>(&row.get::<_, String>(1)?).unwrap();
-                Ok(DbEventRecord {
-                    id,
-                    alert_type: event.alert_type,
-                    ctime: event.ctime,
+                Ok(SongRequest {
+                    id: row.get("video_id")?,
+                    user: row.get("user")?,
+                    title: row.get("title")?,
+                    url: row.get("url")?,
                 })
             })?
-            .collect();
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut queue = Queue::default();
 
-        Ok(res?)
+        for song in songs {
+            if queue.enqueue(&song).is_err() {
+                break;
+            }
+        }
+
+        Ok(queue)
     }
 
-    pub fn get_event(&self, id: i32) -> Result<Option<DbEventRecord>, DatabaseError> {
+    /// Log a chat message and count it towards its sender and the live
+    /// stream session, if there is one
+    fn new_chat_message(&mut self, message: &NewChatMessage) -> Result<(), DatabaseError> {
+        let now = Utc::now();
+        let chatter = &message.chatter;
+
+        let tx = self.db.transaction()?;
+
+        let live_since = tx
+            .query_one(
+                "SELECT started_at FROM stream_sessions WHERE ended_at IS NULL ORDER BY id DESC LIMIT 1",
+                (),
+                |row| row.get::<_, DateTime<Utc>>(0),
+            )
+            .optional()?;
+
+        let last_seen = tx
+            .query_one(
+                "SELECT last_seen FROM users WHERE id = ?1",
+                (&chatter.id,),
+                |row| row.get::<_, DateTime<Utc>>(0),
+            )
+            .optional()?;
+
+        // they were around since their last message, if it wasn't too long ago,
+        // but not from before the stream started
+        let watched = match (last_seen, live_since) {
+            (Some(last_seen), Some(started_at)) => {
+                (now - last_seen.max(started_at)).clamp(Duration::zero(), PRESENCE_WINDOW)
+            }
+            _ => Duration::zero(),
+        };
+
+        tx.execute(
+            r#"
+                INSERT INTO users (id, login, display_name, first_seen, last_seen, message_count, is_subscriber, is_vip, is_mod)
+                VALUES (?1, ?2, ?3, ?4, ?4, 1, ?5, ?6, ?7)
+                ON CONFLICT (id) DO UPDATE SET
+                    login = excluded.login,
+                    display_name = excluded.display_name,
+                    last_seen = excluded.last_seen,
+                    message_count = message_count + 1,
+                    is_subscriber = excluded.is_subscriber,
+                    is_vip = excluded.is_vip,
+                    is_mod = excluded.is_mod,
+                    watch_time = watch_time + ?8
+            "#,
+            (
+                &chatter.id,
+                &chatter.login,
+                &chatter.display_name,
+                now,
+                chatter.is_subscriber,
+                chatter.is_vip,
+                chatter.is_mod,
+                watched.num_seconds(),
+            ),
+        )?;
+
+        tx.execute(
+            r#"
+                INSERT INTO chat_activity (stream_session_id, user_id, messages)
+                SELECT id, ?1, 1 FROM stream_sessions WHERE ended_at IS NULL ORDER BY id DESC LIMIT 1
+                ON CONFLICT (stream_session_id, user_id) DO UPDATE SET messages = messages + 1
+            "#,
+            (&chatter.id,),
+        )?;
+
+        tx.execute(
+            r#"
+                INSERT INTO chat_messages (msg_id, user_id, login, display_name, message, tags, ctime)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            "#,
+            (
+                &message.msg_id,
+                &chatter.id,
+                &chatter.login,
+                &chatter.display_name,
+                &message.message,
+                serde_json::to_string(&message.tags)?,
+                now,
+            ),
+        )?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn delete_chat_message(&self, msg_id: &str) -> Result<(), DatabaseError> {
+        self.db.execute(
+            r#"
+                UPDATE chat_messages SET deletion = ?2, deleted_at = ?3
+                WHERE msg_id = ?1 AND deletion IS NULL
+            "#,
+            (msg_id, deletion_to_sql(ChatDeletion::Deleted)?, Utc::now()),
+        )?;
+
+        Ok(())
+    }
+
+    fn clear_chat(
+        &self,
+        user_id: Option<&str>,
+        deletion: ChatDeletion,
+    ) -> Result<(), DatabaseError> {
+        let now = Utc::now();
+
+        self.db.execute(
+            r#"
+                UPDATE chat_messages SET deletion = ?2, deleted_at = ?3
+                WHERE (?1 IS NULL OR user_id = ?1) AND deletion IS NULL AND ctime >= ?4
+            "#,
+            (user_id, deletion_to_sql(deletion)?, now, now - CLEAR_WINDOW),
+        )?;
+
+        Ok(())
+    }
+
+    /// Newest messages matching the request first, `request.limit` at a time
+    fn get_chat_log(&self, request: ChatLogRequest) -> Result<ChatLogPage, DatabaseError> {
+        // `?` parameters are numbered in the order they appear, same as `params`
+        let mut conditions = Vec::new();
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+        let mut condition = |sql: &str, values: Vec<Box<dyn ToSql>>| {
+            conditions.push(sql.to_string());
+            params.extend(values);
+        };
+
+        if let Some(before) = request.before {
+            condition("id < ?", vec![Box::new(before)]);
+        }
+        if let Some(user) = request.user {
+            let user = user.trim_start_matches('@').to_string();
+            condition(
+                "(login = ? COLLATE NOCASE OR display_name = ? COLLATE NOCASE)",
+                vec![Box::new(user.clone()), Box::new(user)],
+            );
+        }
+        if let Some(since) = request.since {
+            condition("ctime >= ?", vec![Box::new(since)]);
+        }
+        if let Some(until) = request.until {
+            condition("ctime < ?", vec![Box::new(until)]);
+        }
+        if let Some(search) = request.search {
+            // searched as a phrase, so fts5 query syntax in the input can't fail the query
+            condition(
+                "id IN (SELECT rowid FROM chat_messages_search WHERE chat_messages_search MATCH ?)",
+                vec![Box::new(format!("\"{}\"", search.replace('"', "\"\"")))],
+            );
+        }
+
+        params.push(Box::new(request.limit));
+
+        let sql = format!(
+            "SELECT * FROM chat_messages {} ORDER BY id DESC LIMIT ?",
+            if conditions.is_empty() {
+                String::new()
+            } else {
+                format!("WHERE {}", conditions.join(" AND "))
+            }
+        );
+
+        let mut stmt = self.db.prepare(&sql)?;
+        let mut rows = stmt.query(params_from_iter(params))?;
+
+        let mut messages = Vec::new();
+        while let Some(row) = rows.next()? {
+            messages.push(chat_log_message_from_row(row)?);
+        }
+
+        let next_cursor = if messages.len() as u32 == request.limit {
+            messages.last().map(|message| message.id)
+        } else {
+            None
+        };
+
+        Ok(ChatLogPage {
+            messages,
+            next_cursor,
+        })
+    }
+
+    fn prune_chat_log(&self, before: DateTime<Utc>) -> Result<usize, DatabaseError> {
         Ok(self
             .db
-            .query_one(r#"SELECT * from events WHERE id = ?1"#, (id,), |row| {
-                let id = row.get(0)?;
-                let event = serde_json::from_str::<DbEvent>(&row.get::<_, String>(1)?).unwrap();
-                Ok(DbEventRecord {
-                    id,
-                    alert_type: event.alert_type,
-                    ctime: event.ctime,
-                })
-            })
+            .execute("DELETE FROM chat_messages WHERE ctime < ?1", (before,))?)
+    }
+
+    fn add_strike(
+        &mut self,
+        strike: &NewStrike,
+        since: DateTime<Utc>,
+    ) -> Result<u32, DatabaseError> {
+        let tx = self.db.transaction()?;
+
+        tx.execute(
+            r#"
+                INSERT INTO strikes (user_id, login, filter, message, ctime)
+                VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+            (
+                &strike.user_id,
+                &strike.login,
+                &strike.filter,
+                &strike.message,
+                Utc::now(),
+            ),
+        )?;
+
+        let strikes = tx.query_one(
+            "SELECT COUNT(*) FROM strikes WHERE user_id = ?1 AND ctime >= ?2",
+            (&strike.user_id, since),
+            |row| row.get::<_, u32>(0),
+        )?;
+
+        tx.commit()?;
+
+        Ok(strikes)
+    }
+
+    fn get_twitch_token(&self, login: &str) -> Result<Option<UserToken>, DatabaseError> {
+        Ok(self
+            .db
+            .query_one(
+                "SELECT * FROM twitch_tokens WHERE login = ?1 COLLATE NOCASE",
+                (login,),
+                twitch_token_from_row,
+            )
             .optional()?)
     }
+
+    fn save_twitch_token(&self, token: &UserToken) -> Result<(), DatabaseError> {
+        self.db.execute(
+            r#"
+                INSERT INTO twitch_tokens (login, user_id, access_token, refresh_token, scopes, expires_at, mtime)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT (login) DO UPDATE SET
+                    user_id = excluded.user_id,
+                    access_token = excluded.access_token,
+                    refresh_token = excluded.refresh_token,
+                    scopes = excluded.scopes,
+                    expires_at = excluded.expires_at,
+                    mtime = excluded.mtime
+            "#,
+            (
+                &token.login,
+                &token.user_id,
+                &token.access_token,
+                &token.refresh_token,
+                token.scopes.join(" "),
+                token.expires_at,
+                Utc::now(),
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    fn get_viewer(&self, name: &str) -> Result<Option<Viewer>, DatabaseError> {
+        Ok(self
+            .db
+            .query_one(
+                r#"
+                    SELECT * FROM users
+                    WHERE login = ?1 COLLATE NOCASE OR display_name = ?1 COLLATE NOCASE
+                    ORDER BY last_seen DESC
+                    LIMIT 1
+                "#,
+                (name.trim_start_matches('@'),),
+                viewer_from_row,
+            )
+            .optional()?)
+    }
+
+    fn get_stream_sessions(&self, limit: u32) -> Result<Vec<StreamSession>, DatabaseError> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM stream_sessions ORDER BY id DESC LIMIT ?1")?;
+
+        let sessions = stmt
+            .query_map((limit,), stream_session_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(sessions)
+    }
+
+    /// Events that happened during a stream session, oldest first
+    fn get_session_events(&self, session_id: i32) -> Result<Vec<EventRecord>, DatabaseError> {
+        let mut stmt = self.db.prepare(
+            "SELECT id, payload, ctime, hidden FROM events WHERE stream_session_id = ?1 AND NOT hidden ORDER BY id",
+        )?;
+
+        events_from_rows(stmt.query((session_id,))?)
+    }
+
+    fn get_stream_summary(&self, session: StreamSession) -> Result<StreamSummary, DatabaseError> {
+        let mut summary = StreamSummary::default();
+
+        for event in self.get_session_events(session.id)? {
+            summary.add_event(&event.alert_type);
+        }
+
+        summary.songs_requested = self.db.query_one(
+            "SELECT COUNT(*) FROM song_requests WHERE stream_session_id = ?1",
+            (session.id,),
+            |row| row.get(0),
+        )?;
+
+        (summary.chat_messages, summary.chatters) = self.db.query_one(
+            r#"
+                SELECT COALESCE(SUM(messages), 0), COUNT(*) FROM chat_activity
+                WHERE stream_session_id = ?1
+            "#,
+            (session.id,),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        summary.session = Some(session);
+
+        Ok(summary)
+    }
+
+    /// Totals of everything that happened since `since`, on or off stream
+    fn get_summary_since(&self, since: DateTime<Utc>) -> Result<StreamSummary, DatabaseError> {
+        let mut summary = StreamSummary::default();
+
+        let mut stmt = self
+            .db
+            .prepare("SELECT id, payload, ctime, hidden FROM events WHERE ctime >= ?1 AND NOT hidden ORDER BY id")?;
+
+        for event in events_from_rows(stmt.query((since,))?)? {
+            summary.add_event(&event.alert_type);
+        }
+
+        summary.songs_requested = self.db.query_one(
+            "SELECT COUNT(*) FROM song_requests WHERE ctime >= ?1",
+            (since,),
+            |row| row.get(0),
+        )?;
+
+        (summary.chat_messages, summary.chatters) = self.db.query_one(
+            r#"
+                SELECT COALESCE(SUM(messages), 0), COUNT(DISTINCT user_id) FROM chat_activity
+                WHERE stream_session_id IN (SELECT id FROM stream_sessions WHERE started_at >= ?1)
+            "#,
+            (since,),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        Ok(summary)
+    }
+
+    fn get_stream_stats(&self) -> Result<StreamStats, DatabaseError> {
+        let now = Utc::now();
+
+        let streams = self
+            .get_stream_sessions(10)?
+            .into_iter()
+            .map(|session| self.get_stream_summary(session))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(StreamStats {
+            streams,
+            last_7_days: self.get_summary_since(now - Duration::days(7))?,
+            last_30_days: self.get_summary_since(now - Duration::days(30))?,
+        })
+    }
+
+    fn delete_event(&self, id: i32) -> Result<bool, DatabaseError> {
+        Ok(self.db.execute("DELETE FROM events WHERE id = ?1", (id,))? > 0)
+    }
+
+    fn set_event_hidden(
+        &self,
+        id: i32,
+        hidden: bool,
+    ) -> Result<Option<EventRecord>, DatabaseError> {
+        self.db
+            .execute("UPDATE events SET hidden = ?2 WHERE id = ?1", (id, hidden))?;
+
+        self.get_event(id)
+    }
+
+    /// Everyone who showed up in the live stream, or the last one if we're offline
+    fn get_credits(&self) -> Result<Credits, DatabaseError> {
+        let mut credits = Credits::default();
+
+        let Some(session) = self.get_stream_sessions(1)?.pop() else {
+            return Ok(credits);
+        };
+
+        for event in self.get_session_events(session.id)? {
+            credits.add_event(&event.alert_type);
+        }
+
+        let mut stmt = self
+            .db
+            .prepare("SELECT user FROM song_requests WHERE stream_session_id = ?1 ORDER BY id")?;
+
+        for user in stmt.query_map((session.id,), |row| row.get::<_, String>(0))? {
+            let user = user?;
+            if !credits.song_requesters.contains(&user) {
+                credits.song_requesters.push(user);
+            }
+        }
+
+        Ok(credits)
+    }
+}
+
+fn migrate(db: &mut Connection) -> Result<(), DatabaseError> {
+    let version: usize = db.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    if version > MIGRATIONS.len() {
+        return Err(DatabaseError::UnknownSchemaVersion(version));
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = db.transaction()?;
+
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+
+        tx.commit()?;
+
+        tracing::info!("migrated database to schema version {}", i + 1);
+    }
+
+    Ok(())
+}
+
+/// Everything from before the schema was versioned. `IF NOT EXISTS` so
+/// databases that already have these tables are left as they are
+fn initial_schema(tx: &Transaction) -> Result<(), DatabaseError> {
+    tx.execute_batch(
+        r#"
+            CREATE TABLE IF NOT EXISTS events (
+                id INTEGER PRIMARY KEY,
+                data TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS stream_sessions (
+                id INTEGER PRIMARY KEY,
+                started_at TEXT NOT NULL,
+                ended_at TEXT,
+                title TEXT NOT NULL,
+                game_name TEXT NOT NULL,
+                peak_viewers INTEGER NOT NULL DEFAULT 0,
+                discord_msg_id TEXT
+            );
+
+            CREATE TABLE IF NOT EXISTS stream_session_changes (
+                id INTEGER PRIMARY KEY,
+                stream_session_id INTEGER NOT NULL REFERENCES stream_sessions(id),
+                title TEXT NOT NULL,
+                game_name TEXT NOT NULL,
+                ctime TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS song_requests (
+                id INTEGER PRIMARY KEY,
+                user TEXT NOT NULL,
+                title TEXT NOT NULL,
+                url TEXT NOT NULL,
+                ctime TEXT NOT NULL,
+                stream_session_id INTEGER REFERENCES stream_sessions(id)
+            );
+
+            CREATE TABLE IF NOT EXISTS song_queue (
+                position INTEGER PRIMARY KEY,
+                video_id TEXT NOT NULL,
+                user TEXT NOT NULL,
+                title TEXT NOT NULL,
+                url TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS chat_activity (
+                stream_session_id INTEGER NOT NULL REFERENCES stream_sessions(id),
+                user_id TEXT NOT NULL,
+                messages INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (stream_session_id, user_id)
+            );
+        "#,
+    )?;
+
+    add_column_if_missing(
+        tx,
+        "events",
+        "stream_session_id",
+        "INTEGER REFERENCES stream_sessions(id)",
+    )?;
+
+    Ok(())
+}
+
+/// An `events.data` blob from before [`event_columns`], with the timestamp inside the JSON
+#[derive(Deserialize)]
+struct LegacyEvent {
+    alert_type: AlertEventType,
+    ctime: DateTime<Utc>,
+}
+
+/// Split `events.data` into real columns so events can be filtered and
+/// sorted without parsing every row
+fn event_columns(tx: &Transaction) -> Result<(), DatabaseError> {
+    tx.execute_batch(
+        r#"
+            CREATE TABLE events_new (
+                id INTEGER PRIMARY KEY,
+                event_type TEXT NOT NULL,
+                user TEXT,
+                payload TEXT NOT NULL,
+                ctime TEXT NOT NULL,
+                stream_session_id INTEGER REFERENCES stream_sessions(id)
+            );
+
+            -- rows whose data couldn't be parsed, kept as they were
+            CREATE TABLE events_unparsable (
+                id INTEGER PRIMARY KEY,
+                data TEXT NOT NULL,
+                stream_session_id INTEGER
+            );
+        "#,
+    )?;
+
+    {
+        let mut select = tx.prepare("SELECT id, data, stream_session_id FROM events")?;
+        let mut insert = tx.prepare(
+            r#"
+                INSERT INTO events_new (id, event_type, user, payload, ctime, stream_session_id)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )?;
+        let mut quarantine = tx.prepare(
+            "INSERT INTO events_unparsable (id, data, stream_session_id) VALUES (?1, ?2, ?3)",
+        )?;
+
+        let mut rows = select.query(())?;
+
+        while let Some(row) = rows.next()? {
+            let id: i32 = row.get(0)?;
+            let data: String = row.get(1)?;
+            let stream_session_id: Option<i32> = row.get(2)?;
+
+            let event = match serde_json::from_str::<LegacyEvent>(&data) {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!("moving unparsable event {id} to events_unparsable: {e}");
+                    quarantine.execute((id, data, stream_session_id))?;
+                    continue;
+                }
+            };
+
+            insert.execute((
+                id,
+                event.alert_type.kind(),
+                event.alert_type.user(),
+                serde_json::to_string(&event.alert_type)?,
+                event.ctime,
+                stream_session_id,
+            ))?;
+        }
+    }
+
+    tx.execute_batch(
+        r#"
+            DROP TABLE events;
+            ALTER TABLE events_new RENAME TO events;
+
+            CREATE INDEX events_event_type ON events (event_type);
+            CREATE INDEX events_user ON events (user);
+            CREATE INDEX events_ctime ON events (ctime);
+            CREATE INDEX events_stream_session_id ON events (stream_session_id);
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Lets events be hidden instead of deleted, and cheer messages be searched
+fn event_search(tx: &Transaction) -> Result<(), DatabaseError> {
+    tx.execute_batch(
+        r#"
+            ALTER TABLE events ADD COLUMN hidden INTEGER NOT NULL DEFAULT 0;
+
+            DROP INDEX events_user;
+            CREATE INDEX events_user ON events (user COLLATE NOCASE);
+
+            CREATE VIRTUAL TABLE event_messages USING fts5 (message);
+
+            INSERT INTO event_messages (rowid, message)
+            SELECT id, json_extract(payload, '$.Bits.message') FROM events
+            WHERE event_type = 'Bits';
+
+            CREATE TRIGGER events_message_insert AFTER INSERT ON events
+            WHEN new.event_type = 'Bits'
+            BEGIN
+                INSERT INTO event_messages (rowid, message)
+                VALUES (new.id, json_extract(new.payload, '$.Bits.message'));
+            END;
+
+            CREATE TRIGGER events_message_delete AFTER DELETE ON events
+            BEGIN
+                DELETE FROM event_messages WHERE rowid = old.id;
+            END;
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Everyone who has chatted, keyed by twitch user id
+fn users(tx: &Transaction) -> Result<(), DatabaseError> {
+    tx.execute_batch(
+        r#"
+            CREATE TABLE users (
+                id TEXT PRIMARY KEY,
+                login TEXT NOT NULL,
+                display_name TEXT NOT NULL,
+                first_seen TEXT NOT NULL,
+                last_seen TEXT NOT NULL,
+                message_count INTEGER NOT NULL DEFAULT 0,
+                is_subscriber INTEGER NOT NULL DEFAULT 0,
+                is_vip INTEGER NOT NULL DEFAULT 0,
+                is_mod INTEGER NOT NULL DEFAULT 0,
+                -- seconds
+                watch_time INTEGER NOT NULL DEFAULT 0
+            );
+
+            CREATE INDEX users_login ON users (login COLLATE NOCASE);
+            CREATE INDEX users_display_name ON users (display_name COLLATE NOCASE);
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Every chat message with its tags, and whether a mod took it down
+fn chat_log(tx: &Transaction) -> Result<(), DatabaseError> {
+    tx.execute_batch(
+        r#"
+            CREATE TABLE chat_messages (
+                id INTEGER PRIMARY KEY,
+                -- twitch's id, what CLEARMSG refers to
+                msg_id TEXT,
+                user_id TEXT NOT NULL,
+                login TEXT NOT NULL,
+                display_name TEXT NOT NULL,
+                message TEXT NOT NULL,
+                -- JSON object of the IRC tags
+                tags TEXT NOT NULL,
+                ctime TEXT NOT NULL,
+                deletion TEXT,
+                deleted_at TEXT
+            );
+
+            CREATE INDEX chat_messages_msg_id ON chat_messages (msg_id);
+            CREATE INDEX chat_messages_user_id ON chat_messages (user_id);
+            CREATE INDEX chat_messages_login ON chat_messages (login COLLATE NOCASE);
+            CREATE INDEX chat_messages_display_name ON chat_messages (display_name COLLATE NOCASE);
+            CREATE INDEX chat_messages_ctime ON chat_messages (ctime);
+
+            CREATE VIRTUAL TABLE chat_messages_search USING fts5 (message);
+
+            CREATE TRIGGER chat_messages_search_insert AFTER INSERT ON chat_messages
+            BEGIN
+                INSERT INTO chat_messages_search (rowid, message) VALUES (new.id, new.message);
+            END;
+
+            CREATE TRIGGER chat_messages_search_delete AFTER DELETE ON chat_messages
+            BEGIN
+                DELETE FROM chat_messages_search WHERE rowid = old.id;
+            END;
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Messages the chat filters caught, so repeat offenders get punished harder
+fn strikes(tx: &Transaction) -> Result<(), DatabaseError> {
+    tx.execute_batch(
+        r#"
+            CREATE TABLE strikes (
+                id INTEGER PRIMARY KEY,
+                user_id TEXT NOT NULL,
+                login TEXT NOT NULL,
+                filter TEXT NOT NULL,
+                message TEXT NOT NULL,
+                ctime TEXT NOT NULL
+            );
+
+            CREATE INDEX strikes_user_id_ctime ON strikes (user_id, ctime);
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Twitch tokens, which used to be rewritten into config.toml on every refresh
+fn twitch_tokens(tx: &Transaction) -> Result<(), DatabaseError> {
+    tx.execute_batch(
+        r#"
+            CREATE TABLE twitch_tokens (
+                login TEXT PRIMARY KEY COLLATE NOCASE,
+                user_id TEXT NOT NULL,
+                access_token TEXT NOT NULL,
+                refresh_token TEXT NOT NULL,
+                scopes TEXT NOT NULL,
+                expires_at TEXT NOT NULL,
+                mtime TEXT NOT NULL
+            );
+        "#,
+    )?;
+
+    Ok(())
+}
+
+fn add_column_if_missing(
+    db: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), DatabaseError> {
+    let exists = db
+        .prepare(&format!("PRAGMA table_info({table})"))?
+        .query_map((), |row| row.get::<_, String>("name"))?
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
+        db.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            (),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_columns_quarantines_unparsable_rows() {
+        let mut db = Connection::open_in_memory().unwrap();
+
+        db.execute_batch(
+            r#"
+                CREATE TABLE events (
+                    id INTEGER PRIMARY KEY,
+                    data TEXT NOT NULL
+                );
+
+                INSERT INTO events (id, data) VALUES
+                    (1, '{"alert_type":{"Follow":{"follower":"a"}},"ctime":"2024-01-01T00:00:00Z"}'),
+                    (2, '{"alert_type":"Bogus"}');
+            "#,
+        )
+        .unwrap();
+
+        migrate(&mut db).unwrap();
+
+        let version: usize = db
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        let events = db
+            .prepare("SELECT id, event_type, user FROM events")
+            .unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<(i32, String, Option<String>)>, _>>()
+            .unwrap();
+        assert_eq!(events, [(1, "Follow".into(), Some("a".into()))]);
+
+        let unparsable: (i32, String) = db
+            .query_one("SELECT id, data FROM events_unparsable", (), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(unparsable, (2, r#"{"alert_type":"Bogus"}"#.into()));
+    }
 }
@@ -0,0 +1,27 @@
+//! The trunk build of the frontend, compiled into the binary
+
+use axum::{
+    http::{StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use rust_embed::RustEmbed;
+
+#[derive(RustEmbed)]
+#[folder = "../frontend/dist/"]
+struct FrontendAssets;
+
+/// Serves a file from the embedded build, anything unknown gets index.html so
+/// the frontend's routes work on reload
+pub async fn serve(uri: Uri) -> Response {
+    let path = uri.path().trim_start_matches('/');
+
+    let Some(file) = FrontendAssets::get(path).or_else(|| FrontendAssets::get("index.html")) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    (
+        [(header::CONTENT_TYPE, file.metadata.mimetype().to_owned())],
+        file.data,
+    )
+        .into_response()
+}
@@ -0,0 +1,369 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use sadmadbotlad_protocol::{ChatEmote, Emote, EmoteEvent, EmoteProvider};
+use serde::{Deserialize, Deserializer};
+use tokio_util::sync::CancellationToken;
+
+const CHANNEL_ID: &str = "143306668";
+
+/// How often the emote sets are fetched again, to pick up newly added emotes
+const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(6 * 60 * 60);
+
+/// An emote has to show up in this many messages for a combo
+const COMBO_THRESHOLD: usize = 5;
+
+/// ...within this long of each other
+const COMBO_WINDOW: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmotesError {
+    #[error(transparent)]
+    ReqwestError(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// 7TV, BTTV and FFZ emotes, global and the channel's, by name. Cloning
+/// shares the same set
+#[derive(Debug, Clone, Default)]
+pub struct Emotes(Arc<RwLock<HashMap<String, Emote>>>);
+
+impl Emotes {
+    pub fn get(&self, name: &str) -> Option<Emote> {
+        self.0.read().expect("emotes lock").get(name).cloned()
+    }
+
+    /// Third-party emotes in a chat message, twitch's own are in its tags
+    pub fn find_in(&self, message: &str) -> Vec<ChatEmote> {
+        let emotes = self.0.read().expect("emotes lock");
+
+        let mut found = Vec::new();
+        let mut start = 0;
+
+        for word in message.split(' ') {
+            let len = word.chars().count();
+
+            if let Some(emote) = emotes.get(word) {
+                found.push(ChatEmote {
+                    id: emote.id.clone(),
+                    provider: emote.provider,
+                    start,
+                    end: start + len - 1,
+                });
+            }
+
+            // +1 for the space
+            start += len + 1;
+        }
+
+        found
+    }
+
+    /// Fetches every provider's emotes, using the copy cached in `cache_dir`
+    /// for any provider that can't be reached
+    pub async fn load(&self, cache_dir: &Path) -> Result<(), EmotesError> {
+        let http_client = reqwest::Client::new();
+
+        let mut emotes = HashMap::new();
+
+        for provider in [
+            EmoteProvider::Ffz,
+            EmoteProvider::Bttv,
+            EmoteProvider::SevenTv,
+        ] {
+            let cache = cache_dir.join(format!("{}.json", provider_name(provider)));
+
+            let provider_emotes = match fetch(&http_client, provider).await {
+                Ok(provider_emotes) => {
+                    tokio::fs::create_dir_all(cache_dir).await?;
+                    tokio::fs::write(&cache, serde_json::to_vec(&provider_emotes)?).await?;
+                    provider_emotes
+                }
+                Err(e) => {
+                    tracing::warn!("failed to fetch {provider:?} emotes, using the cache: {e}");
+                    read_cache(&cache).await?
+                }
+            };
+
+            // later providers win on name clashes, 7TV being the most used in chat
+            emotes.extend(
+                provider_emotes
+                    .into_iter()
+                    .map(|emote| (emote.name.clone(), emote)),
+            );
+        }
+
+        tracing::info!("loaded {} emotes", emotes.len());
+
+        *self.0.write().expect("emotes lock") = emotes;
+
+        Ok(())
+    }
+}
+
+impl FromIterator<Emote> for Emotes {
+    fn from_iter<I: IntoIterator<Item = Emote>>(iter: I) -> Self {
+        Self(Arc::new(RwLock::new(
+            iter.into_iter()
+                .map(|emote| (emote.name.clone(), emote))
+                .collect(),
+        )))
+    }
+}
+
+/// Counts how often each emote shows up in chat, to spot combos
+#[derive(Debug, Default)]
+pub struct ComboTracker {
+    /// when each emote was last used, oldest first, by name
+    uses: HashMap<String, VecDeque<Instant>>,
+}
+
+impl ComboTracker {
+    /// Records the emotes of one message, a message spamming the same emote
+    /// only counts once
+    pub fn record(&mut self, emotes: &[Emote]) -> Vec<EmoteEvent> {
+        let now = Instant::now();
+
+        self.uses.retain(|_, uses| {
+            while uses.front().is_some_and(|used| now - *used > COMBO_WINDOW) {
+                uses.pop_front();
+            }
+            !uses.is_empty()
+        });
+
+        let mut combos = Vec::new();
+
+        for (i, emote) in emotes.iter().enumerate() {
+            if emotes[..i].iter().any(|seen| seen.name == emote.name) {
+                continue;
+            }
+
+            let uses = self.uses.entry(emote.name.clone()).or_default();
+            uses.push_back(now);
+
+            if uses.len() >= COMBO_THRESHOLD {
+                combos.push(EmoteEvent::Combo {
+                    emote: emote.clone(),
+                    count: uses.len() as u32,
+                });
+            }
+        }
+
+        combos
+    }
+}
+
+/// Keeps `emotes` up to date until shutdown
+pub async fn refresh_emotes(
+    emotes: Emotes,
+    cache_dir: PathBuf,
+    shutdown: CancellationToken,
+) -> Result<(), EmotesError> {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+
+        emotes.load(&cache_dir).await?;
+    }
+}
+
+fn provider_name(provider: EmoteProvider) -> &'static str {
+    match provider {
+        EmoteProvider::Twitch => "twitch",
+        EmoteProvider::SevenTv => "7tv",
+        EmoteProvider::Bttv => "bttv",
+        EmoteProvider::Ffz => "ffz",
+    }
+}
+
+async fn read_cache(cache: &Path) -> Result<Vec<Emote>, EmotesError> {
+    match tokio::fs::read(cache).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        // never fetched, nothing to fall back on
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn fetch(
+    http_client: &reqwest::Client,
+    provider: EmoteProvider,
+) -> Result<Vec<Emote>, EmotesError> {
+    let emote = |id: String, name: String| Emote { id, name, provider };
+
+    let emotes = match provider {
+        EmoteProvider::Twitch => Vec::new(),
+        EmoteProvider::SevenTv => {
+            let global =
+                get::<SevenTvEmoteSet>(http_client, "https://7tv.io/v3/emote-sets/global").await?;
+            let channel = get_channel::<SevenTvUser>(
+                http_client,
+                &format!("https://7tv.io/v3/users/twitch/{CHANNEL_ID}"),
+            )
+            .await?;
+
+            global
+                .emotes
+                .into_iter()
+                .chain(channel.emote_set.map(|set| set.emotes).unwrap_or_default())
+                .map(|e| emote(e.id, e.name))
+                .collect()
+        }
+        EmoteProvider::Bttv => {
+            let global = get::<Vec<BttvEmote>>(
+                http_client,
+                "https://api.betterttv.net/3/cached/emotes/global",
+            )
+            .await?;
+            let channel = get_channel::<BttvUser>(
+                http_client,
+                &format!("https://api.betterttv.net/3/cached/users/twitch/{CHANNEL_ID}"),
+            )
+            .await?;
+
+            global
+                .into_iter()
+                .chain(channel.channel_emotes)
+                .chain(channel.shared_emotes)
+                .map(|e| emote(e.id, e.code))
+                .collect()
+        }
+        EmoteProvider::Ffz => {
+            let global =
+                get::<FfzSets>(http_client, "https://api.frankerfacez.com/v1/set/global").await?;
+            let channel = get_channel::<FfzSets>(
+                http_client,
+                &format!("https://api.frankerfacez.com/v1/room/id/{CHANNEL_ID}"),
+            )
+            .await?;
+
+            global
+                .sets
+                .into_values()
+                .chain(channel.sets.into_values())
+                .flat_map(|set| set.emoticons)
+                .map(|e| emote(e.id.to_string(), e.name))
+                .collect()
+        }
+    };
+
+    Ok(emotes)
+}
+
+async fn get<T: serde::de::DeserializeOwned>(
+    http_client: &reqwest::Client,
+    url: &str,
+) -> Result<T, EmotesError> {
+    Ok(http_client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<T>()
+        .await?)
+}
+
+/// Like [`get`], but a channel that never set the provider up is a 404
+async fn get_channel<T: serde::de::DeserializeOwned + Default>(
+    http_client: &reqwest::Client,
+    url: &str,
+) -> Result<T, EmotesError> {
+    let res = http_client.get(url).send().await?;
+
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(T::default());
+    }
+
+    Ok(res.error_for_status()?.json::<T>().await?)
+}
+
+#[derive(Deserialize)]
+struct SevenTvEmoteSet {
+    /// `null` for an empty set
+    #[serde(default, deserialize_with = "null_as_empty")]
+    emotes: Vec<SevenTvEmote>,
+}
+
+fn null_as_empty<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(Option::<Vec<T>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[derive(Deserialize)]
+struct SevenTvEmote {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize, Default)]
+struct SevenTvUser {
+    emote_set: Option<SevenTvEmoteSet>,
+}
+
+#[derive(Deserialize)]
+struct BttvEmote {
+    id: String,
+    code: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct BttvUser {
+    #[serde(default)]
+    channel_emotes: Vec<BttvEmote>,
+    #[serde(default)]
+    shared_emotes: Vec<BttvEmote>,
+}
+
+#[derive(Deserialize, Default)]
+struct FfzSets {
+    sets: HashMap<String, FfzSet>,
+}
+
+#[derive(Deserialize)]
+struct FfzSet {
+    emoticons: Vec<FfzEmote>,
+}
+
+#[derive(Deserialize)]
+struct FfzEmote {
+    id: u64,
+    name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seven_tv_null_emote_set_is_empty() {
+        let set = serde_json::from_str::<SevenTvEmoteSet>(r#"{"emotes":null}"#).unwrap();
+        assert!(set.emotes.is_empty());
+
+        let set = serde_json::from_str::<SevenTvEmoteSet>("{}").unwrap();
+        assert!(set.emotes.is_empty());
+
+        let set = serde_json::from_str::<SevenTvEmoteSet>(
+            r#"{"emotes":[{"id":"60ae958e229664e8667aea38","name":"peepoHappy"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(set.emotes.len(), 1);
+        assert_eq!(set.emotes[0].name, "peepoHappy");
+    }
+}
@@ -1,43 +1,38 @@
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::db::{DBMessage, DatabaseError};
+use crate::db::{DatabaseError, Db, NewStreamSession};
 use crate::discord::{DiscordError, offline_notification, online_notification};
-use crate::twitch::{TwitchApiResponse, TwitchError, TwitchTokenMessages};
+use crate::helix::{BROADCASTER_ID, HelixClient};
+use crate::twitch::TwitchError;
 use crate::{Alert, AlertEventType, ApiInfo};
-use chrono::ParseError;
+use chrono::Utc;
 use futures_util::{SinkExt, StreamExt};
-use reqwest::StatusCode;
+use sadmadbotlad_protocol::Credits;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio_util::sync::CancellationToken;
 
 #[derive(thiserror::Error, Debug)]
 pub enum EventsubError {
     #[error(transparent)]
     WsError(#[from] tokio_tungstenite::tungstenite::error::Error),
 
-    #[error("connection unsed... disconnecting")]
-    ConnectionUnused,
-
     #[error(transparent)]
     IrcChannelError(
         #[from] tokio::sync::mpsc::error::SendError<tokio_tungstenite::tungstenite::Message>,
     ),
 
-    #[error(transparent)]
-    TwitchTokenSendError(#[from] tokio::sync::mpsc::error::SendError<TwitchTokenMessages>),
-
     #[error(transparent)]
     AlertSendError(#[from] tokio::sync::broadcast::error::SendError<Alert>),
 
-    #[error(transparent)]
-    DateParseError(#[from] ParseError),
-
     #[error(transparent)]
     TwitchError(#[from] TwitchError),
 
@@ -49,9 +44,6 @@ pub enum EventsubError {
 
     #[error(transparent)]
     DatabaseError(#[from] DatabaseError),
-
-    #[error(transparent)]
-    ReqwestError(#[from] reqwest::Error),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -98,6 +90,7 @@ struct EventsubSession {
     id: String,
     status: SessionStatus,
     reconnect_url: Option<String>,
+    keepalive_timeout_seconds: Option<u64>,
     #[serde(flatten)]
     extra: HashMap<String, Value>,
 }
@@ -109,363 +102,550 @@ enum SessionStatus {
     Reconnecting,
 }
 
+const EVENTSUB_URL: &str = "wss://eventsub.wss.twitch.tv/ws";
+
+/// Every subscription type the bot needs on a fresh session
+const SUBSCRIPTION_TYPES: [&str; 10] = [
+    "stream.online",
+    "stream.offline",
+    "channel.follow",
+    "channel.raid",
+    "channel.subscribe",
+    "channel.subscription.message",
+    "channel.subscription.gift",
+    "channel.channel_points_custom_reward_redemption.add",
+    "channel.cheer",
+    "channel.update",
+];
+
+/// Used until the welcome message tells us the real keepalive timeout
+const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// Extra time given to twitch on top of the keepalive timeout before
+/// the session is considered dead
+const KEEPALIVE_GRACE: Duration = Duration::from_secs(5);
+
+/// How often failed or revoked subscriptions are retried
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the viewer count is checked while live, to keep track of the peak
+const VIEWERS_CHECK_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+enum ConnectionEvent {
+    Message(Message),
+    Closed,
+}
+
+struct EventsubConnection {
+    id: usize,
+    handle: JoinHandle<Result<(), EventsubError>>,
+}
+
+impl EventsubConnection {
+    fn abort(&self) {
+        tracing::debug!("aborting eventsub connection {}", self.id);
+        self.handle.abort();
+    }
+}
+
+/// Tracks which websocket is the live one, and the one being
+/// migrated away from after a `session_reconnect`
+struct EventsubState {
+    next_id: usize,
+    current: EventsubConnection,
+    migrating_from: Option<EventsubConnection>,
+    session_id: Option<String>,
+    keepalive: Duration,
+    last_message: Instant,
+    /// subscription types that still need to be (re)created
+    pending: HashSet<String>,
+    events_sender: mpsc::UnboundedSender<(usize, ConnectionEvent)>,
+    /// every connection closes its websocket once this is cancelled
+    shutdown: CancellationToken,
+}
+
+impl EventsubState {
+    fn new(
+        events_sender: mpsc::UnboundedSender<(usize, ConnectionEvent)>,
+        shutdown: CancellationToken,
+    ) -> Self {
+        Self {
+            next_id: 1,
+            current: new_connection(EVENTSUB_URL, events_sender.clone(), 0, shutdown.clone()),
+            migrating_from: None,
+            session_id: None,
+            keepalive: DEFAULT_KEEPALIVE,
+            last_message: Instant::now(),
+            pending: HashSet::new(),
+            events_sender,
+            shutdown,
+        }
+    }
+
+    fn connect(&mut self, url: &str) -> EventsubConnection {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        new_connection(url, self.events_sender.clone(), id, self.shutdown.clone())
+    }
+
+    /// Drop every connection and start over with a fresh session.
+    /// subscriptions don't survive this, so they are all recreated
+    /// once the new session is welcomed
+    fn restart(&mut self) {
+        tracing::info!("restarting eventsub session");
+
+        if let Some(old) = self.migrating_from.take() {
+            old.abort();
+        }
+
+        self.current.abort();
+        self.current = self.connect(EVENTSUB_URL);
+        self.session_id = None;
+        self.keepalive = DEFAULT_KEEPALIVE;
+        self.last_message = Instant::now();
+    }
+
+    /// Open the reconnect url twitch gave us, and keep the old connection
+    /// around until the new one receives its welcome message
+    fn migrate(&mut self, reconnect_url: &str) {
+        tracing::debug!("migrating eventsub session to: {reconnect_url}");
+
+        let new = self.connect(reconnect_url);
+        let old = std::mem::replace(&mut self.current, new);
+
+        if let Some(older) = self.migrating_from.replace(old) {
+            older.abort();
+        }
+    }
+
+    /// Waits for every connection to close after shutdown, twitch drops
+    /// the session's subscriptions as soon as its websocket is closed
+    async fn close(self) {
+        for connection in std::iter::once(self.current).chain(self.migrating_from) {
+            if let Err(e) = connection.handle.await {
+                tracing::error!("eventsub connection {} failed to close: {e}", connection.id);
+            }
+        }
+    }
+
+    fn keepalive_deadline(&self) -> Instant {
+        self.last_message + self.keepalive + KEEPALIVE_GRACE
+    }
+
+    fn is_known(&self, id: usize) -> bool {
+        self.current.id == id || self.migrating_from.as_ref().is_some_and(|old| old.id == id)
+    }
+}
+
 pub async fn eventsub(
     alerts_sender: tokio::sync::broadcast::Sender<Alert>,
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
+    credits_sender: tokio::sync::broadcast::Sender<Credits>,
+    helix: HelixClient,
     api_info: Arc<ApiInfo>,
-    db_tx: std::sync::mpsc::Sender<DBMessage>,
+    db: Db,
+    shutdown: CancellationToken,
 ) -> Result<(), EventsubError> {
-    read(alerts_sender, token_sender, api_info, db_tx).await?;
+    read(alerts_sender, credits_sender, helix, api_info, db, shutdown).await?;
 
     Ok(())
 }
 
 async fn read(
     alerts_sender: tokio::sync::broadcast::Sender<Alert>,
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
+    credits_sender: tokio::sync::broadcast::Sender<Credits>,
+    helix: HelixClient,
     api_info: Arc<ApiInfo>,
-    db_tx: std::sync::mpsc::Sender<DBMessage>,
+    db: Db,
+    shutdown: CancellationToken,
 ) -> Result<(), EventsubError> {
-    let (irc_sender, mut irc_receiver) = mpsc::unbounded_channel::<Message>();
+    let (events_sender, mut events_receiver) =
+        mpsc::unbounded_channel::<(usize, ConnectionEvent)>();
 
-    let mut connections_handlers = vec![];
+    let mut state = EventsubState::new(events_sender, shutdown.clone());
 
-    'restart: loop {
-        tracing::debug!("'restart loop");
+    let mut resubscribe = tokio::time::interval(RESUBSCRIBE_INTERVAL);
+    resubscribe.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-        let mut discord_msg_id = String::new();
-        let mut title = String::new();
-        let mut game_name = String::new();
+    let mut viewers_check = tokio::time::interval(VIEWERS_CHECK_INTERVAL);
+    viewers_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-        {
-            let irc_sender = irc_sender.clone();
-            connections_handlers.push(new_connection(
-                "wss://eventsub.wss.twitch.tv/ws",
-                irc_sender,
-                connections_handlers.len(),
-            ));
+    loop {
+        let event = tokio::select! {
+            Some(event) = events_receiver.recv() => event,
+            _ = shutdown.cancelled() => {
+                tracing::info!("Closing eventsub sessions");
+                state.close().await;
+                return Ok(());
+            }
+            _ = tokio::time::sleep_until(state.keepalive_deadline()) => {
+                tracing::error!(
+                    "no eventsub message in {:?}, keepalive lapsed",
+                    state.keepalive + KEEPALIVE_GRACE
+                );
+                state.restart();
+                continue;
+            }
+            _ = resubscribe.tick(), if state.session_id.is_some() && !state.pending.is_empty() => {
+                let session_id = state.session_id.clone().expect("session id");
+                subscribe_pending(&helix, &session_id, &mut state.pending).await;
+                continue;
+            }
+            _ = viewers_check.tick() => {
+                if let Err(e) = update_peak_viewers(&helix, &db).await {
+                    tracing::error!("failed to update peak viewers: {e}");
+                }
+                continue;
+            }
+        };
+
+        let (id, event) = event;
+
+        if !state.is_known(id) {
+            tracing::debug!("ignoring event from stale connection {id}: {event:?}");
+            continue;
         }
 
-        while let Some(msg) = irc_receiver.recv().await {
-            let db_tx = db_tx.clone();
-            match msg {
-                Message::Close(reason) => match reason.as_ref() {
-                    Some(close_frame) => match close_frame.code {
-                        CloseCode::Reserved(code) if code == 4004 => {
-                            tracing::debug!("Received close code {code}. removing old connections");
-
-                            // abort all tokio tasks but the last one
-                            // to remove old connections
-                            let new_handler = connections_handlers.pop().expect("new handler");
-
-                            for (i, handler) in connections_handlers.iter().enumerate() {
-                                tracing::debug!("aborting connection {i} {handler:#?}");
-                                handler.abort();
-                            }
-
-                            connections_handlers.clear();
-                            connections_handlers.push(new_handler);
-
-                            tracing::debug!(
-                                "connections after aborting: {}",
-                                connections_handlers.len()
-                            );
-                        }
-                        code => {
-                            tracing::error!("websocket connection closed: {code:#?}");
-                            continue 'restart;
-                        }
-                    },
-                    None => {
-                        tracing::error!("websocket connection closed: {reason:#?}");
-                        continue 'restart;
+        let is_current = state.current.id == id;
+
+        if is_current {
+            state.last_message = Instant::now();
+        }
+
+        let msg = match event {
+            ConnectionEvent::Message(Message::Close(reason)) if !is_current => {
+                tracing::debug!("old connection {id} closed: {reason:?}");
+                if let Some(old) = state.migrating_from.take() {
+                    old.abort();
+                }
+                continue;
+            }
+            ConnectionEvent::Closed if !is_current => {
+                state.migrating_from.take();
+                continue;
+            }
+            ConnectionEvent::Message(Message::Close(reason)) => {
+                match reason.as_ref().map(|frame| frame.code) {
+                    Some(CloseCode::Library(4003)) => {
+                        tracing::error!("eventsub connection unused: {reason:?}");
                     }
-                },
-                msg => {
-                    let msg = msg.to_string();
-                    if msg.contains("connection unused") {
-                        tracing::error!("connection unused: {msg}");
-                        return Err(EventsubError::ConnectionUnused);
+                    _ => tracing::error!("websocket connection closed: {reason:#?}"),
+                }
+                state.restart();
+                continue;
+            }
+            ConnectionEvent::Closed => {
+                tracing::error!("eventsub connection {id} ended");
+                state.restart();
+                continue;
+            }
+            ConnectionEvent::Message(msg) => msg.to_string(),
+        };
+
+        let eventsub_message = match serde_json::from_str::<EventsubMessage>(&msg) {
+            Ok(json_msg) => json_msg,
+            Err(e) => {
+                tracing::error!("eventsub:: json Error: {e} \n Message: {msg}");
+                continue;
+            }
+        };
+
+        match eventsub_message.metadata.message_type {
+            EventsubMessageType::SessionWelcome => {
+                let Some(session) = eventsub_message.payload.session else {
+                    tracing::error!("session_welcome without session field: {msg}");
+                    continue;
+                };
+
+                if !is_current {
+                    continue;
+                }
+
+                if let Some(timeout) = session.keepalive_timeout_seconds {
+                    state.keepalive = Duration::from_secs(timeout);
+                }
+
+                match session.status {
+                    SessionStatus::Connected => {}
+                    status => {
+                        tracing::debug!("status: {:#?}", status);
+                        continue;
                     }
+                }
 
-                    let eventsub_message = match serde_json::from_str::<EventsubMessage>(&msg) {
-                        Ok(json_msg) => json_msg,
-                        Err(e) => {
-                            panic!("eventsub:: json Error: {} \n Message: {}", e, msg);
-                        }
-                    };
-
-                    match eventsub_message.metadata.message_type {
-                        EventsubMessageType::SessionWelcome => {
-                            if connections_handlers.len() > 1 {
-                                tracing::debug!(
-                                    "Received welcome message on new connection. removing old connections"
-                                );
-
-                                // abort all tokio tasks but the last one
-                                // to remove old connections
-                                let new_handler = connections_handlers.pop().expect("new handler");
-
-                                for handler in connections_handlers.iter() {
-                                    handler.abort();
-                                }
-
-                                connections_handlers.clear();
-                                connections_handlers.push(new_handler);
-
-                                tracing::debug!(
-                                    "connections after aborting: {}",
-                                    connections_handlers.len()
-                                );
-                            } else {
-                                let session = eventsub_message
-                                    .payload
-                                    .session
-                                    .expect("session_welcome should have session field");
-                                match session.status {
-                                    SessionStatus::Connected => {
-                                        let session_id = session.id;
-
-                                        tracing::info!("Subscribing to eventsubs...");
-
-                                        online_eventsub(token_sender.clone(), &session_id).await?;
-                                        offline_eventsub(token_sender.clone(), &session_id).await?;
-                                        follow_eventsub(token_sender.clone(), &session_id).await?;
-                                        raid_eventsub(token_sender.clone(), &session_id).await?;
-                                        subscribe_eventsub(token_sender.clone(), &session_id)
-                                            .await?;
-                                        resubscribe_eventsub(token_sender.clone(), &session_id)
-                                            .await?;
-                                        giftsub_eventsub(token_sender.clone(), &session_id).await?;
-                                        rewards_eventsub(token_sender.clone(), &session_id).await?;
-                                        cheers_eventsub(token_sender.clone(), &session_id).await?;
-
-                                        tracing::info!("Subscribed to eventsubs");
-                                    }
-                                    status => tracing::debug!("status: {:#?}", status),
-                                }
-                            }
-                        }
-                        EventsubMessageType::Notification => {
-                            let sub_type = eventsub_message
-                                .payload
-                                .subscription
-                                .expect("notification should have subscription field")
-                                .r#type;
-                            tracing::debug!("got {:?} event", sub_type);
-
-                            let event = eventsub_message
-                                .payload
-                                .extra
-                                .get("event")
-                                .expect("notification have event field");
-
-                            match sub_type.as_str() {
-                                "stream.online" => {
-                                    stream_online_event(
-                                        &token_sender,
-                                        &mut title,
-                                        &mut game_name,
-                                        &mut discord_msg_id,
-                                        &api_info,
-                                    )
-                                    .await?;
-                                }
-                                "stream.offline" => {
-                                    if title.is_empty() || game_name.is_empty() {
-                                        continue;
-                                    }
-
-                                    offline_notification(
-                                        &title,
-                                        &game_name,
-                                        &api_info,
-                                        &discord_msg_id,
-                                    )
-                                    .await?;
-                                }
-                                "channel.follow" => {
-                                    let follower = event["user_name"]
-                                        .as_str()
-                                        .expect("follow username")
-                                        .to_string();
-
-                                    write_recent("follow", &follower).ok();
-
-                                    let alert = AlertEventType::Follow { follower };
-
-                                    let res =
-                                        db_tx.send(DBMessage::NewEvent(alert.clone())).unwrap();
-
-                                    tracing::debug!("added {sub_type} event to db {res:#?}");
-
-                                    if let Err(err) = alerts_sender.send(Alert {
-                                        new: true,
-                                        r#type: alert.clone(),
-                                    }) {
-                                        tracing::error!(
-                                            "failed to send alert: {alert:#?} -- {err:#?}"
-                                        );
-                                    }
-                                }
-                                "channel.raid" => {
-                                    let from = event["from_broadcaster_user_name"]
-                                        .as_str()
-                                        .expect("from_broadcaster_user_name")
-                                        .to_string();
-                                    let viewers = event["viewers"].as_u64().expect("viewers");
-
-                                    write_recent("raid", &from).ok();
-
-                                    let alert = AlertEventType::Raid { from, viewers };
-
-                                    let res =
-                                        db_tx.send(DBMessage::NewEvent(alert.clone())).unwrap();
-
-                                    tracing::debug!("added {sub_type} event to db {res:#?}");
-
-                                    alerts_sender.send(Alert {
-                                        new: true,
-                                        r#type: alert,
-                                    })?;
-                                }
-                                "channel.subscribe" => {
-                                    channel_subscribe_event(event, db_tx, &alerts_sender).await?;
-                                }
-                                "channel.subscription.message" => {
-                                    channel_subscription_message_event(
-                                        event,
-                                        db_tx.clone(),
-                                        &alerts_sender,
-                                    )
-                                    .await?
-                                }
-                                "channel.subscription.gift" => {
-                                    let gifter =
-                                        event["user_name"].as_str().expect("user_name").to_string();
-
-                                    let tier = {
-                                        let long_tier = event["tier"].as_str().expect("tier");
-                                        if long_tier != "Prime" {
-                                            long_tier
-                                                .chars()
-                                                .next()
-                                                .expect("first char")
-                                                .to_string()
-                                        } else {
-                                            long_tier.to_string()
-                                        }
-                                    };
-
-                                    let total = event["total"].as_u64().expect("total");
-
-                                    let alert = AlertEventType::GiftSub {
-                                        gifter,
-                                        total,
-                                        tier,
-                                    };
-
-                                    let res =
-                                        db_tx.send(DBMessage::NewEvent(alert.clone())).unwrap();
-
-                                    tracing::debug!("added {sub_type} event to db {res:#?}");
-
-                                    alerts_sender.send(Alert {
-                                        new: true,
-                                        r#type: alert,
-                                    })?;
-                                }
-                                "channel.cheer" => {
-                                    channel_cheer_event(event, db_tx, &alerts_sender).await?
-                                }
-                                "channel.channel_points_custom_reward_redemption.add" => {
-                                    let redeemer =
-                                        event["user_name"].as_str().expect("user_name").to_string();
-
-                                    let reward_title = event["reward"]["title"]
-                                        .as_str()
-                                        .expect("reward title")
-                                        .to_string();
-
-                                    tracing::debug!("{redeemer} redeemed: {reward_title}");
-                                }
-                                _ => {}
-                            }
-                        }
-                        EventsubMessageType::SessionReconnect => {
-                            let reconnect_url = eventsub_message
-                                .payload
-                                .session
-                                .expect("session_reconnect should have session field")
-                                .reconnect_url
-                                .expect("session_reconnect should have reconnect_url field");
-
-                            tracing::debug!("got reconnection url: {reconnect_url}");
-                            {
-                                let irc_sender = irc_sender.clone();
-                                connections_handlers.push(new_connection(
-                                    &reconnect_url,
-                                    irc_sender,
-                                    connections_handlers.len(),
-                                ));
-                            }
-                        }
-                        EventsubMessageType::SessionKeepalive => {
-                            tracing::debug!("session_keepalive");
-                        }
-                        EventsubMessageType::Revocation => {
-                            let subscription = eventsub_message
-                                .payload
-                                .subscription
-                                .expect("revocation should have subscription field");
+                state.session_id = Some(session.id.clone());
 
-                            tracing::error!("connection revoked type: {:#?}", subscription);
-                        }
-                    };
+                if let Some(old) = state.migrating_from.take() {
+                    tracing::debug!(
+                        "Received welcome message on new connection. removing old connection"
+                    );
+                    // subscriptions carry over to the new session on reconnect
+                    old.abort();
+                    continue;
+                }
+
+                tracing::info!("Subscribing to eventsubs...");
+
+                state
+                    .pending
+                    .extend(SUBSCRIPTION_TYPES.iter().map(|t| t.to_string()));
+
+                subscribe_pending(&helix, &session.id, &mut state.pending).await;
+
+                tracing::info!("Subscribed to eventsubs");
+            }
+            EventsubMessageType::Notification => {
+                let Some(subscription) = eventsub_message.payload.subscription else {
+                    tracing::error!("notification without subscription field: {msg}");
+                    continue;
+                };
+
+                let Some(event) = eventsub_message.payload.extra.get("event") else {
+                    tracing::error!("notification without event field: {msg}");
+                    continue;
+                };
+
+                tracing::debug!("got {:?} event", subscription.r#type);
+
+                // one bad event shouldn't cost every subscription on the session
+                if let Err(e) = handle_notification(
+                    &subscription.r#type,
+                    event,
+                    &alerts_sender,
+                    &credits_sender,
+                    &helix,
+                    &api_info,
+                    &db,
+                )
+                .await
+                {
+                    tracing::error!("failed to handle {} event: {e}", subscription.r#type);
                 }
             }
+            EventsubMessageType::SessionReconnect => {
+                let Some(reconnect_url) = eventsub_message
+                    .payload
+                    .session
+                    .and_then(|session| session.reconnect_url)
+                else {
+                    tracing::error!("session_reconnect without reconnect_url: {msg}");
+                    state.restart();
+                    continue;
+                };
+
+                tracing::debug!("got reconnection url: {reconnect_url}");
+
+                state.migrate(&reconnect_url);
+            }
+            EventsubMessageType::SessionKeepalive => {
+                tracing::debug!("session_keepalive");
+            }
+            EventsubMessageType::Revocation => {
+                let Some(subscription) = eventsub_message.payload.subscription else {
+                    tracing::error!("revocation without subscription field: {msg}");
+                    continue;
+                };
+
+                tracing::error!(
+                    "subscription {} revoked: {}. retrying every {:?}",
+                    subscription.r#type,
+                    subscription.status,
+                    RESUBSCRIBE_INTERVAL
+                );
+
+                state.pending.insert(subscription.r#type);
+            }
+        };
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_notification(
+    sub_type: &str,
+    event: &Value,
+    alerts_sender: &tokio::sync::broadcast::Sender<Alert>,
+    credits_sender: &tokio::sync::broadcast::Sender<Credits>,
+    helix: &HelixClient,
+    api_info: &Arc<ApiInfo>,
+    db: &Db,
+) -> Result<(), EventsubError> {
+    match sub_type {
+        "stream.online" => {
+            stream_online_event(helix, db, api_info).await?;
+        }
+        "stream.offline" => {
+            stream_offline_event(db, api_info).await?;
+
+            let credits = db.get_credits().await?;
+
+            if let Err(e) = credits_sender.send(credits) {
+                tracing::debug!("no one is listening for the credits roll: {e}");
+            }
+        }
+        "channel.update" => {
+            let title = event["title"].as_str().unwrap_or_default();
+            let game_name = event["category_name"].as_str().unwrap_or_default();
+
+            if let Some(session) = db.get_live_stream_session().await? {
+                db.update_stream_session_info(session.id, title.to_string(), game_name.to_string())
+                    .await?;
+            }
+        }
+        "channel.follow" => {
+            let follower = event["user_name"]
+                .as_str()
+                .expect("follow username")
+                .to_string();
+
+            let alert = AlertEventType::Follow { follower };
+
+            let res = db.new_event(alert.clone()).await?;
+
+            tracing::debug!("added {sub_type} event to db {res:#?}");
+
+            if let Err(err) = alerts_sender.send(Alert {
+                new: true,
+                r#type: alert.clone(),
+            }) {
+                tracing::error!("failed to send alert: {alert:#?} -- {err:#?}");
+            }
         }
+        "channel.raid" => {
+            let from = event["from_broadcaster_user_name"]
+                .as_str()
+                .expect("from_broadcaster_user_name")
+                .to_string();
+            let viewers = event["viewers"].as_u64().expect("viewers");
+
+            let alert = AlertEventType::Raid { from, viewers };
+
+            let res = db.new_event(alert.clone()).await?;
+
+            tracing::debug!("added {sub_type} event to db {res:#?}");
+
+            alerts_sender.send(Alert {
+                new: true,
+                r#type: alert,
+            })?;
+        }
+        "channel.subscribe" => {
+            channel_subscribe_event(event, db, alerts_sender).await?;
+        }
+        "channel.subscription.message" => {
+            channel_subscription_message_event(event, db, alerts_sender).await?
+        }
+        "channel.subscription.gift" => {
+            let gifter = event["user_name"].as_str().expect("user_name").to_string();
+
+            let tier = {
+                let long_tier = event["tier"].as_str().expect("tier");
+                if long_tier != "Prime" {
+                    long_tier.chars().next().expect("first char").to_string()
+                } else {
+                    long_tier.to_string()
+                }
+            };
+
+            let total = event["total"].as_u64().expect("total");
+
+            let alert = AlertEventType::GiftSub {
+                gifter,
+                total,
+                tier,
+            };
+
+            let res = db.new_event(alert.clone()).await?;
+
+            tracing::debug!("added {sub_type} event to db {res:#?}");
+
+            alerts_sender.send(Alert {
+                new: true,
+                r#type: alert,
+            })?;
+        }
+        "channel.cheer" => channel_cheer_event(event, db, alerts_sender).await?,
+        "channel.channel_points_custom_reward_redemption.add" => {
+            let redeemer = event["user_name"].as_str().expect("user_name").to_string();
+
+            let reward_title = event["reward"]["title"]
+                .as_str()
+                .expect("reward title")
+                .to_string();
+
+            tracing::debug!("{redeemer} redeemed: {reward_title}");
+        }
+        _ => {}
     }
+
+    Ok(())
 }
 
 fn new_connection(
     connection_url: &str,
-    irc_sender: mpsc::UnboundedSender<Message>,
-    index: usize,
-) -> tokio::task::JoinHandle<Result<(), EventsubError>> {
-    tracing::debug!("new connection");
+    events_sender: mpsc::UnboundedSender<(usize, ConnectionEvent)>,
+    id: usize,
+    shutdown: CancellationToken,
+) -> EventsubConnection {
+    tracing::debug!("new connection {id}");
     let connection_url = connection_url.to_string();
-    tokio::spawn(async move {
-        let (mut sender, mut receiver) = connect_async(
-            connection_url
-                .clone()
-                .into_client_request()
-                .expect("Url parsed"),
-        )
-        .await?
-        .0
-        .split();
-
-        while let Some(msg) = receiver.next().await {
-            match msg {
-                Ok(Message::Ping(ping)) => {
-                    tracing::debug!("{index}:: {connection_url} -- ping {ping:?}");
-                    sender.send(Message::Pong(ping)).await?;
-                }
-                Ok(msg) => irc_sender.send(msg)?,
-                Err(err) => {
-                    tracing::error!("eventsub websocket error: {err}");
+    let handle = tokio::spawn(async move {
+        let result = async {
+            let (mut sender, mut receiver) =
+                connect_async(connection_url.clone().into_client_request()?)
+                    .await?
+                    .0
+                    .split();
+
+            loop {
+                let msg = tokio::select! {
+                    Some(msg) = receiver.next() => msg,
+                    _ = shutdown.cancelled() => {
+                        sender.send(Message::Close(None)).await?;
+                        break;
+                    }
+                    else => break,
+                };
+
+                match msg {
+                    Ok(Message::Ping(ping)) => {
+                        tracing::debug!("{id}:: {connection_url} -- ping {ping:?}");
+                        sender.send(Message::Pong(ping)).await?;
+                    }
+                    Ok(msg) => {
+                        if events_sender
+                            .send((id, ConnectionEvent::Message(msg)))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        tracing::error!("eventsub websocket error: {err}");
+                        break;
+                    }
                 }
             }
+
+            Ok::<_, EventsubError>(())
         }
+        .await;
 
-        Ok::<_, EventsubError>(())
-    })
+        if let Err(e) = &result {
+            tracing::error!("eventsub connection {id} failed: {e}");
+        }
+
+        events_sender.send((id, ConnectionEvent::Closed)).ok();
+
+        result
+    });
+
+    EventsubConnection { id, handle }
 }
 
 async fn channel_cheer_event(
     json_lossy: &Value,
-    db_tx: std::sync::mpsc::Sender<DBMessage>,
+    db: &Db,
     alerts_sender: &tokio::sync::broadcast::Sender<Alert>,
 ) -> Result<(), EventsubError> {
     let message = (*json_lossy)["payload"]["event"]["message"]
@@ -493,7 +673,7 @@ async fn channel_cheer_event(
         bits,
     };
 
-    let res = db_tx.send(DBMessage::NewEvent(alert.clone())).unwrap();
+    let res = db.new_event(alert.clone()).await?;
 
     tracing::debug!("added {alert:#?} event to db {res:#?}");
 
@@ -507,13 +687,11 @@ async fn channel_cheer_event(
 
 async fn channel_subscription_message_event(
     event: &Value,
-    db_tx: std::sync::mpsc::Sender<DBMessage>,
+    db: &Db,
     alerts_sender: &tokio::sync::broadcast::Sender<Alert>,
 ) -> Result<(), EventsubError> {
     let subscriber = event["user_name"].as_str().expect("user_name").to_string();
 
-    write_recent("sub", &subscriber).ok();
-
     let tier = event["tier"]
         .as_str()
         .expect("tier")
@@ -528,8 +706,6 @@ async fn channel_subscription_message_event(
 
     let streak = event["streak_months"].as_u64().expect("streak months");
 
-    write_recent("sub", &subscriber).ok();
-
     let alert = if subscribed_for > 1 {
         AlertEventType::ReSubscribe {
             subscriber,
@@ -541,7 +717,7 @@ async fn channel_subscription_message_event(
         AlertEventType::Subscribe { subscriber, tier }
     };
 
-    let res = db_tx.send(DBMessage::NewEvent(alert.clone())).unwrap();
+    let res = db.new_event(alert.clone()).await?;
 
     tracing::debug!("added {:#?} event to db {res:#?}", alert);
 
@@ -554,54 +730,92 @@ async fn channel_subscription_message_event(
 }
 
 async fn stream_online_event(
-    token_sender: &mpsc::UnboundedSender<TwitchTokenMessages>,
-    title: &mut String,
-    game_name: &mut String,
-    discord_msg_id: &mut String,
+    helix: &HelixClient,
+    db: &Db,
     api_info: &Arc<ApiInfo>,
 ) -> Result<(), EventsubError> {
-    let http_client = reqwest::Client::new();
+    let Some(data) = helix.get_stream().await? else {
+        return Err(EventsubError::TwitchError(TwitchError::FuckedUp));
+    };
 
-    let (send, recv) = oneshot::channel();
+    let started_at = data.started_at;
 
-    token_sender.send(TwitchTokenMessages::GetToken(send))?;
+    if let Some(session) = db.get_live_stream_session().await? {
+        if session.started_at == started_at {
+            tracing::info!("stream session {} is already recorded", session.id);
+            return Ok(());
+        }
 
-    let Ok(twitch_api_info) = recv.await else {
-        return Err(EventsubError::TwitchError(TwitchError::TokenError));
-    };
+        tracing::warn!(
+            "stream session {} never received an offline event, ending it",
+            session.id
+        );
+
+        db.end_stream_session(session.id, started_at).await?;
+    }
 
-    let res = http_client
-        .get("https://api.twitch.tv/helix/streams?user_login=sadmadladsalman")
-        .bearer_auth(twitch_api_info.twitch_access_token.clone())
-        .header("Client-Id", twitch_api_info.client_id.clone())
-        .send()
+    let session = db
+        .start_stream_session(NewStreamSession {
+            started_at,
+            title: data.title.clone(),
+            game_name: data.game_name.clone(),
+            viewers: data.viewer_count,
+        })
         .await?;
 
-    if !res.status().is_success() {
-        return Err(EventsubError::TwitchError(TwitchError::TwitchApiError {
-            request_name: String::from("user_id"),
-            status: res.status(),
-            message: res.text().await.expect("reponse text"),
-        }));
-    }
+    let discord_msg_id = online_notification(
+        &session.title,
+        &session.game_name,
+        started_at.timestamp(),
+        api_info,
+    )
+    .await?;
 
-    let res = res.json::<TwitchApiResponse>().await?;
-    let Some(data) = &res.data.get(0) else {
-        return Err(EventsubError::TwitchError(TwitchError::FuckedUp));
+    db.set_stream_session_message_id(session.id, discord_msg_id)
+        .await?;
+
+    Ok(())
+}
+
+async fn stream_offline_event(db: &Db, api_info: &Arc<ApiInfo>) -> Result<(), EventsubError> {
+    let Some(session) = db.get_live_stream_session().await? else {
+        tracing::warn!("stream went offline without a recorded stream session");
+        return Ok(());
     };
 
-    let timestamp = chrono::DateTime::parse_from_rfc3339(&data.started_at)?.timestamp();
+    db.end_stream_session(session.id, Utc::now()).await?;
+
+    let Some(discord_msg_id) = session.discord_msg_id else {
+        return Ok(());
+    };
+
+    offline_notification(
+        &session.title,
+        &session.game_name,
+        api_info,
+        &discord_msg_id,
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn update_peak_viewers(helix: &HelixClient, db: &Db) -> Result<(), EventsubError> {
+    let Some(session) = db.get_live_stream_session().await? else {
+        return Ok(());
+    };
 
-    *title = res.data[0].title.clone();
-    *game_name = res.data[0].game_name.clone();
-    *discord_msg_id = online_notification(&*title, &*game_name, timestamp, api_info).await?;
+    if let Some(data) = helix.get_stream().await? {
+        db.update_peak_viewers(session.id, data.viewer_count)
+            .await?;
+    }
 
     Ok(())
 }
 
 async fn channel_subscribe_event(
     event: &Value,
-    db_tx: std::sync::mpsc::Sender<DBMessage>,
+    db: &Db,
     alerts_sender: &tokio::sync::broadcast::Sender<Alert>,
 ) -> Result<(), EventsubError> {
     let subscriber = event["user_name"].as_str().expect("user_name").to_string();
@@ -615,8 +829,6 @@ async fn channel_subscribe_event(
         }
     };
 
-    write_recent("sub", &subscriber).ok();
-
     if event["is_gift"]
         .as_bool()
         .expect("channel subscribe should have is_gift field")
@@ -626,7 +838,7 @@ async fn channel_subscribe_event(
             tier,
         };
 
-        let res = db_tx.send(DBMessage::NewEvent(alert.clone())).unwrap();
+        let res = db.new_event(alert.clone()).await?;
 
         tracing::debug!("added {:#?} event to db {res:#?}", alert);
 
@@ -651,398 +863,35 @@ async fn channel_subscribe_event(
     Ok(())
 }
 
-async fn offline_eventsub(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-    session: &str,
-) -> Result<(), EventsubError> {
-    let http_client = reqwest::Client::new();
-
-    let (send, recv) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(send))?;
-
-    let Ok(api_info) = recv.await else {
-        return Err(EventsubError::TwitchError(TwitchError::TokenError));
-    };
-
-    let res = http_client
-        .post("https://api.twitch.tv/helix/eventsub/subscriptions")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "type": "stream.offline",
-            "version": "1",
-            "condition": {
-                "broadcaster_user_id": "143306668"
-            },
-            "transport": {
-                "method": "websocket",
-                "session_id": session
-            }
-        }))
-        .send()
-        .await?;
-
-    if res.status() != StatusCode::ACCEPTED {
-        return Err(EventsubError::TwitchError(TwitchError::TwitchApiError {
-            request_name: String::from("offline"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        }));
-    }
-
-    Ok(())
-}
-
-async fn cheers_eventsub(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-    session: &str,
-) -> Result<(), EventsubError> {
-    let http_client = reqwest::Client::new();
-
-    let (send, recv) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(send))?;
-
-    let Ok(api_info) = recv.await else {
-        return Err(EventsubError::TwitchError(TwitchError::TokenError));
-    };
-
-    let res = http_client
-        .post("https://api.twitch.tv/helix/eventsub/subscriptions")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "type": "channel.cheer",
-            "version": "1",
-            "condition": {
-                "broadcaster_user_id": "143306668"
-            },
-            "transport": {
-                "method": "websocket",
-                "session_id": session
-            }
-        }))
-        .send()
-        .await?;
-
-    if res.status() != StatusCode::ACCEPTED {
-        return Err(EventsubError::TwitchError(TwitchError::TwitchApiError {
-            request_name: String::from("cheers"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        }));
-    }
-
-    Ok(())
-}
-
-async fn online_eventsub(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-    session: &str,
-) -> Result<(), EventsubError> {
-    let http_client = reqwest::Client::new();
-
-    let (send, recv) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(send))?;
-
-    let Ok(api_info) = recv.await else {
-        return Err(EventsubError::TwitchError(TwitchError::TokenError));
-    };
-
-    let res = http_client
-        .post("https://api.twitch.tv/helix/eventsub/subscriptions")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "type": "stream.online",
-            "version": "1",
-            "condition": {
-                "broadcaster_user_id": "143306668" //110644052
-            },
-            "transport": {
-                "method": "websocket",
-                "session_id": session
-            }
-        }))
-        .send()
-        .await?;
-
-    if res.status() != StatusCode::ACCEPTED {
-        return Err(EventsubError::TwitchError(TwitchError::TwitchApiError {
-            request_name: String::from("online"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        }));
-    }
-
-    Ok(())
-}
-
-async fn follow_eventsub(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-    session: &str,
-) -> Result<(), EventsubError> {
-    let http_client = reqwest::Client::new();
-
-    let (send, recv) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(send))?;
+/// Try to create every pending subscription, keeping the ones that failed
+/// so they get retried later (e.g. after the token gets fixed)
+async fn subscribe_pending(helix: &HelixClient, session: &str, pending: &mut HashSet<String>) {
+    let sub_types = pending.drain().collect::<Vec<_>>();
 
-    let Ok(api_info) = recv.await else {
-        return Err(EventsubError::TwitchError(TwitchError::TokenError));
-    };
+    for sub_type in sub_types {
+        let (version, condition) = subscription_condition(&sub_type);
 
-    let res = http_client
-        .post("https://api.twitch.tv/helix/eventsub/subscriptions")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "type": "channel.follow",
-            "version": "2",
-            "condition": {
-                "broadcaster_user_id": "143306668", //110644052
-                "moderator_user_id": "143306668"
-            },
-            "transport": {
-                "method": "websocket",
-                "session_id": session
-            }
-        }))
-        .send()
-        .await?;
-
-    if res.status() != StatusCode::ACCEPTED {
-        return Err(EventsubError::TwitchError(TwitchError::TwitchApiError {
-            request_name: String::from("follow"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        }));
-    }
-
-    Ok(())
-}
-
-async fn raid_eventsub(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-    session: &str,
-) -> Result<(), EventsubError> {
-    let http_client = reqwest::Client::new();
-
-    let (send, recv) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(send))?;
-
-    let Ok(api_info) = recv.await else {
-        return Err(EventsubError::TwitchError(TwitchError::TokenError));
-    };
-
-    let res = http_client
-        .post("https://api.twitch.tv/helix/eventsub/subscriptions")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "type": "channel.raid",
-            "version": "1",
-            "condition": {
-                "to_broadcaster_user_id": "143306668" //110644052
-            },
-            "transport": {
-                "method": "websocket",
-                "session_id": session
-            }
-        }))
-        .send()
-        .await?;
-
-    if res.status() != StatusCode::ACCEPTED {
-        return Err(EventsubError::TwitchError(TwitchError::TwitchApiError {
-            request_name: String::from("raid"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        }));
-    }
-
-    Ok(())
-}
-
-async fn subscribe_eventsub(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-    session: &str,
-) -> Result<(), EventsubError> {
-    let http_client = reqwest::Client::new();
-
-    let (send, recv) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(send))?;
-
-    let Ok(api_info) = recv.await else {
-        return Err(EventsubError::TwitchError(TwitchError::TokenError));
-    };
-
-    let res = http_client
-        .post("https://api.twitch.tv/helix/eventsub/subscriptions")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "type": "channel.subscribe",
-            "version": "1",
-            "condition": {
-                "broadcaster_user_id": "143306668" //110644052
-            },
-            "transport": {
-                "method": "websocket",
-                "session_id": session
-            }
-        }))
-        .send()
-        .await?;
-
-    if res.status() != StatusCode::ACCEPTED {
-        return Err(EventsubError::TwitchError(TwitchError::TwitchApiError {
-            request_name: String::from("subscribe"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        }));
-    }
-
-    Ok(())
-}
-
-async fn resubscribe_eventsub(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-    session: &str,
-) -> Result<(), EventsubError> {
-    let http_client = reqwest::Client::new();
-
-    let (send, recv) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(send))?;
-
-    let Ok(api_info) = recv.await else {
-        return Err(EventsubError::TwitchError(TwitchError::TokenError));
-    };
-
-    let res = http_client
-        .post("https://api.twitch.tv/helix/eventsub/subscriptions")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "type": "channel.subscription.message",
-            "version": "1",
-            "condition": {
-                "broadcaster_user_id": "143306668" //110644052
-            },
-            "transport": {
-                "method": "websocket",
-                "session_id": session
-            }
-        }))
-        .send()
-        .await?;
-
-    if res.status() != StatusCode::ACCEPTED {
-        return Err(EventsubError::TwitchError(TwitchError::TwitchApiError {
-            request_name: String::from("resubscribe"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        }));
-    }
-
-    Ok(())
-}
-
-async fn giftsub_eventsub(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-    session: &str,
-) -> Result<(), EventsubError> {
-    let http_client = reqwest::Client::new();
-
-    let (send, recv) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(send))?;
-
-    let Ok(api_info) = recv.await else {
-        return Err(EventsubError::TwitchError(TwitchError::TokenError));
-    };
-
-    let res = http_client
-        .post("https://api.twitch.tv/helix/eventsub/subscriptions")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "type": "channel.subscription.gift",
-            "version": "1",
-            "condition": {
-                "broadcaster_user_id": "143306668" //110644052
-            },
-            "transport": {
-                "method": "websocket",
-                "session_id": session
-            }
-        }))
-        .send()
-        .await?;
-
-    if res.status() != StatusCode::ACCEPTED {
-        return Err(EventsubError::TwitchError(TwitchError::TwitchApiError {
-            request_name: String::from("giftsub"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        }));
+        if let Err(e) = helix
+            .create_eventsub_subscription(&sub_type, version, condition, session)
+            .await
+        {
+            tracing::error!("failed to subscribe to {sub_type}: {e}");
+            pending.insert(sub_type);
+        }
     }
-
-    Ok(())
 }
 
-async fn rewards_eventsub(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-    session: &str,
-) -> Result<(), EventsubError> {
-    let http_client = reqwest::Client::new();
-
-    let (send, recv) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(send))?;
-
-    let Ok(api_info) = recv.await else {
-        return Err(EventsubError::TwitchError(TwitchError::TokenError));
-    };
-
-    let res = http_client
-        .post("https://api.twitch.tv/helix/eventsub/subscriptions")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "type": "channel.channel_points_custom_reward_redemption.add",
-            "version": "1",
-            "condition": {
-                "broadcaster_user_id": "143306668" //110644052
-            },
-            "transport": {
-                "method": "websocket",
-                "session_id": session
-            }
-        }))
-        .send()
-        .await?;
-
-    if res.status() != StatusCode::ACCEPTED {
-        return Err(EventsubError::TwitchError(TwitchError::TwitchApiError {
-            request_name: String::from("rewards"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        }));
+fn subscription_condition(sub_type: &str) -> (&'static str, Value) {
+    match sub_type {
+        "channel.follow" => (
+            "2",
+            json!({
+                "broadcaster_user_id": BROADCASTER_ID,
+                "moderator_user_id": BROADCASTER_ID
+            }),
+        ),
+        "channel.raid" => ("1", json!({ "to_broadcaster_user_id": BROADCASTER_ID })),
+        "channel.update" => ("2", json!({ "broadcaster_user_id": BROADCASTER_ID })),
+        _ => ("1", json!({ "broadcaster_user_id": BROADCASTER_ID })),
     }
-
-    Ok(())
-}
-
-fn write_recent(sub_type: &str, arg: impl Into<String>) -> Result<(), EventsubError> {
-    fs::write(
-        format!("recents/recent_{}.txt", sub_type),
-        format!("recent {}: {}", sub_type, arg.into()),
-    )?;
-    Ok(())
 }
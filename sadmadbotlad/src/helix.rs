@@ -0,0 +1,724 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt, stream};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, header::HeaderMap};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::twitch::{TokenRole, TwitchError, TwitchTokenMessages, UserToken};
+
+pub const BROADCASTER_ID: &str = "143306668";
+
+const HELIX_URL: &str = "https://api.twitch.tv/helix";
+
+/// Twitch's rate limit bucket, as of the last response
+#[derive(Debug, Clone, Copy)]
+struct RateLimit {
+    remaining: u32,
+    /// when the bucket is full again
+    reset: DateTime<Utc>,
+}
+
+/// Every Helix request goes through here. Gets the token from
+/// [`crate::twitch::TwitchToken`], refreshes it once on a 401 and holds
+/// requests back while the rate limit is used up. Cloning shares the
+/// connections and the rate limit
+#[derive(Debug, Clone)]
+pub struct HelixClient {
+    http_client: Client,
+    client_id: String,
+    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
+    rate_limit: Arc<Mutex<Option<RateLimit>>>,
+}
+
+/// One page of a Helix response
+#[derive(Deserialize, Debug)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    #[serde(default)]
+    pagination: Pagination,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Pagination {
+    cursor: Option<String>,
+}
+
+impl HelixClient {
+    pub fn new(
+        client_id: String,
+        token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
+    ) -> Self {
+        Self {
+            http_client: Client::new(),
+            client_id,
+            token_sender,
+            rate_limit: Arc::default(),
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http_client
+            .request(method, format!("{HELIX_URL}{path}"))
+    }
+
+    /// Sends `request` with the token, a non-2xx response is a
+    /// [`TwitchError::TwitchApiError`] named after `request_name`
+    async fn send(
+        &self,
+        request_name: &str,
+        request: RequestBuilder,
+    ) -> Result<Response, TwitchError> {
+        let mut refreshed = false;
+        let mut rate_limited = false;
+
+        loop {
+            self.wait_for_rate_limit().await;
+
+            let token = self.token(false).await?;
+
+            let res = request
+                .try_clone()
+                .expect("helix request bodies are never streamed")
+                .bearer_auth(&token.access_token)
+                .header("Client-Id", &self.client_id)
+                .send()
+                .await?;
+
+            self.update_rate_limit(res.headers());
+
+            match res.status() {
+                // revoked or expired early, worth one try with a fresh token
+                StatusCode::UNAUTHORIZED if !refreshed => {
+                    tracing::warn!("{request_name}: twitch token was rejected, refreshing it");
+                    refreshed = true;
+                    self.token(true).await?;
+                }
+                // another client on the same token used up the bucket
+                StatusCode::TOO_MANY_REQUESTS if !rate_limited => {
+                    rate_limited = true;
+                }
+                status if !status.is_success() => {
+                    return Err(TwitchError::TwitchApiError {
+                        request_name: request_name.to_string(),
+                        status,
+                        message: res.text().await?,
+                    });
+                }
+                _ => return Ok(res),
+            }
+        }
+    }
+
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        request_name: &str,
+        request: RequestBuilder,
+    ) -> Result<T, TwitchError> {
+        Ok(self.send(request_name, request).await?.json::<T>().await?)
+    }
+
+    /// The first item of a response that's a list of one
+    async fn send_one<T: DeserializeOwned>(
+        &self,
+        request_name: &str,
+        request: RequestBuilder,
+    ) -> Result<Option<T>, TwitchError> {
+        let page = self.send_json::<Page<T>>(request_name, request).await?;
+
+        Ok(page.data.into_iter().next())
+    }
+
+    /// Every item of a paginated request, fetching pages as they're needed
+    pub fn paginate<T: DeserializeOwned + 'static>(
+        &self,
+        request_name: &str,
+        request: RequestBuilder,
+    ) -> impl Stream<Item = Result<T, TwitchError>> + '_ {
+        let request_name = request_name.to_string();
+
+        // `None` once the last page is in, `Some(None)` before the first
+        stream::try_unfold(Some(None::<String>), move |cursor| {
+            let request = request
+                .try_clone()
+                .expect("helix request bodies are never streamed");
+            let request_name = request_name.clone();
+
+            async move {
+                let Some(cursor) = cursor else {
+                    return Ok(None);
+                };
+
+                let request = match &cursor {
+                    Some(cursor) => request.query(&[("after", cursor)]),
+                    None => request,
+                };
+
+                let page = self.send_json::<Page<T>>(&request_name, request).await?;
+
+                let next = page
+                    .pagination
+                    .cursor
+                    .filter(|cursor| !cursor.is_empty())
+                    .map(Some);
+
+                Ok(Some((
+                    stream::iter(page.data.into_iter().map(Ok::<T, TwitchError>)),
+                    next,
+                )))
+            }
+        })
+        .try_flatten()
+    }
+
+    async fn token(&self, refresh: bool) -> Result<UserToken, TwitchError> {
+        let (one_shot_sender, one_shot_receiver) = oneshot::channel();
+
+        // everything here is done as the broadcaster, moderation included
+        self.token_sender.send(if refresh {
+            TwitchTokenMessages::RefreshToken(TokenRole::Broadcaster, one_shot_sender)
+        } else {
+            TwitchTokenMessages::GetToken(TokenRole::Broadcaster, one_shot_sender)
+        })?;
+
+        one_shot_receiver.await.map_err(|_| TwitchError::TokenError)
+    }
+
+    /// Waits for the bucket to refill if it's empty, and takes a point from it
+    async fn wait_for_rate_limit(&self) {
+        let wait = {
+            let mut rate_limit = self.rate_limit.lock().expect("rate limit lock");
+
+            match rate_limit.as_mut() {
+                Some(limit) if limit.remaining == 0 => (limit.reset - Utc::now()).to_std().ok(),
+                Some(limit) => {
+                    limit.remaining -= 1;
+                    None
+                }
+                None => None,
+            }
+        };
+
+        if let Some(wait) = wait {
+            tracing::warn!("twitch rate limit reached, waiting {}s", wait.as_secs());
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn update_rate_limit(&self, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name)?.to_str().ok()?.parse::<i64>().ok();
+
+        let (Some(remaining), Some(reset)) =
+            (header("ratelimit-remaining"), header("ratelimit-reset"))
+        else {
+            return;
+        };
+
+        *self.rate_limit.lock().expect("rate limit lock") =
+            DateTime::from_timestamp(reset, 0).map(|reset| RateLimit {
+                remaining: remaining.try_into().unwrap_or_default(),
+                reset,
+            });
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TwitchUser {
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChannelInformation {
+    pub broadcaster_id: String,
+    pub broadcaster_login: String,
+    pub broadcaster_name: String,
+    pub game_id: String,
+    /// empty if no category was ever set
+    pub game_name: String,
+    pub title: String,
+}
+
+/// Only the fields that are set get changed
+#[derive(Serialize, Debug, Clone, Default)]
+struct ModifyChannelRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    game_id: Option<&'a str>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LiveStream {
+    pub user_id: String,
+    pub title: String,
+    pub game_name: String,
+    pub started_at: DateTime<Utc>,
+    pub viewer_count: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Game {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Debug)]
+struct BanRequest<'a> {
+    data: BanData<'a>,
+}
+
+#[derive(Serialize, Debug)]
+struct BanData<'a> {
+    user_id: &'a str,
+    /// seconds, a ban without one is permanent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<u32>,
+    reason: &'a str,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnouncementColor {
+    /// the channel's accent color
+    #[default]
+    Primary,
+    Blue,
+    Green,
+    Orange,
+    Purple,
+}
+
+#[derive(Serialize, Debug)]
+struct AnnouncementRequest<'a> {
+    message: &'a str,
+    color: AnnouncementColor,
+}
+
+#[derive(Serialize, Debug)]
+struct StreamMarkerRequest<'a> {
+    user_id: &'a str,
+    description: &'a str,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StreamMarker {
+    pub id: String,
+    pub description: String,
+    /// how far into the stream
+    pub position_seconds: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Clip {
+    pub id: String,
+    pub edit_url: String,
+}
+
+impl Clip {
+    pub fn url(&self) -> String {
+        format!("https://clips.twitch.tv/{}", self.id)
+    }
+}
+
+/// The chat settings to change, `None` leaves a setting as it is
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ChatSettingsUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_mode: Option<bool>,
+    /// seconds between messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_mode_wait_time: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emote_mode: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub follower_mode: Option<bool>,
+    /// minutes someone must have followed for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub follower_mode_duration: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+struct CommercialRequest<'a> {
+    broadcaster_id: &'a str,
+    /// seconds
+    length: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Commercial {
+    pub length: u32,
+    pub message: String,
+    /// seconds until another one can be run
+    pub retry_after: u32,
+}
+
+#[derive(Serialize, Debug)]
+struct EventsubSubscriptionRequest<'a> {
+    r#type: &'a str,
+    version: &'a str,
+    condition: Value,
+    transport: EventsubTransport<'a>,
+}
+
+#[derive(Serialize, Debug)]
+struct EventsubTransport<'a> {
+    method: &'a str,
+    session_id: &'a str,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Vip {
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+}
+
+#[derive(Deserialize)]
+struct ChatBadgeSet {
+    set_id: String,
+    versions: Vec<ChatBadgeVersion>,
+}
+
+#[derive(Deserialize)]
+struct ChatBadgeVersion {
+    id: String,
+    image_url_2x: String,
+}
+
+/// Badge images by `(set_id, version)`, global badges overridden by the channel's own
+pub type BadgeImages = HashMap<(String, String), String>;
+
+impl HelixClient {
+    /// `None` if there's no twitch user called `login`
+    pub async fn get_user(&self, login: &str) -> Result<Option<TwitchUser>, TwitchError> {
+        let request = self
+            .request(Method::GET, "/users")
+            .query(&[("login", login.trim_start_matches('@'))]);
+
+        match self.send_one("get_user", request).await {
+            // twitch answers a login with characters logins can't have with a 400
+            Err(TwitchError::TwitchApiError { status, .. })
+                if status == StatusCode::BAD_REQUEST =>
+            {
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    pub async fn get_channel_information(
+        &self,
+        broadcaster_id: &str,
+    ) -> Result<Option<ChannelInformation>, TwitchError> {
+        let request = self
+            .request(Method::GET, "/channels")
+            .query(&[("broadcaster_id", broadcaster_id)]);
+
+        self.send_one("get_channel_information", request).await
+    }
+
+    pub async fn get_title(&self) -> Result<String, TwitchError> {
+        let channel = self.get_channel_information(BROADCASTER_ID).await?;
+
+        Ok(channel.map(|channel| channel.title).unwrap_or_default())
+    }
+
+    pub async fn set_title(&self, title: &str) -> Result<(), TwitchError> {
+        self.modify_channel(
+            "set_title",
+            ModifyChannelRequest {
+                title: Some(title),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    pub async fn get_game(&self) -> Result<String, TwitchError> {
+        let channel = self.get_channel_information(BROADCASTER_ID).await?;
+
+        Ok(channel.map(|channel| channel.game_name).unwrap_or_default())
+    }
+
+    /// Searches for the category, preferring an exact match, and switches the
+    /// stream to it. `None` if nothing matched
+    pub async fn set_game_by_name(&self, name: &str) -> Result<Option<Game>, TwitchError> {
+        let request = self
+            .request(Method::GET, "/search/categories")
+            .query(&[("query", name), ("first", "10")]);
+
+        let mut games = self
+            .send_json::<Page<Game>>("search_categories", request)
+            .await?
+            .data;
+
+        let Some(position) = games
+            .iter()
+            .position(|game| game.name.eq_ignore_ascii_case(name))
+            .or((!games.is_empty()).then_some(0))
+        else {
+            return Ok(None);
+        };
+
+        let game = games.swap_remove(position);
+
+        self.modify_channel(
+            "set_game",
+            ModifyChannelRequest {
+                game_id: Some(&game.id),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        Ok(Some(game))
+    }
+
+    async fn modify_channel(
+        &self,
+        request_name: &str,
+        changes: ModifyChannelRequest<'_>,
+    ) -> Result<(), TwitchError> {
+        let request = self
+            .request(Method::PATCH, "/channels")
+            .query(&[("broadcaster_id", BROADCASTER_ID)])
+            .json(&changes);
+
+        self.send(request_name, request).await?;
+
+        Ok(())
+    }
+
+    /// `None` while offline
+    pub async fn get_stream(&self) -> Result<Option<LiveStream>, TwitchError> {
+        let request = self
+            .request(Method::GET, "/streams")
+            .query(&[("user_id", BROADCASTER_ID)]);
+
+        self.send_one("get_stream", request).await
+    }
+
+    pub async fn delete_chat_message(&self, msg_id: &str) -> Result<(), TwitchError> {
+        let request = self.request(Method::DELETE, "/moderation/chat").query(&[
+            ("broadcaster_id", BROADCASTER_ID),
+            ("moderator_id", BROADCASTER_ID),
+            ("message_id", msg_id),
+        ]);
+
+        self.send("delete_chat_message", request).await?;
+
+        Ok(())
+    }
+
+    pub async fn ban_user(&self, user_id: &str, reason: &str) -> Result<(), TwitchError> {
+        self.ban("ban_user", user_id, None, reason).await
+    }
+
+    /// Twitch caps timeouts at two weeks
+    pub async fn timeout_user(
+        &self,
+        user_id: &str,
+        seconds: u32,
+        reason: &str,
+    ) -> Result<(), TwitchError> {
+        self.ban("timeout_user", user_id, Some(seconds), reason)
+            .await
+    }
+
+    /// A timeout is a ban with a duration
+    async fn ban(
+        &self,
+        request_name: &str,
+        user_id: &str,
+        duration: Option<u32>,
+        reason: &str,
+    ) -> Result<(), TwitchError> {
+        let request = self
+            .request(Method::POST, "/moderation/bans")
+            .query(&[
+                ("broadcaster_id", BROADCASTER_ID),
+                ("moderator_id", BROADCASTER_ID),
+            ])
+            .json(&BanRequest {
+                data: BanData {
+                    user_id,
+                    duration,
+                    reason,
+                },
+            });
+
+        self.send(request_name, request).await?;
+
+        Ok(())
+    }
+
+    pub async fn unban_user(&self, user_id: &str) -> Result<(), TwitchError> {
+        let request = self.request(Method::DELETE, "/moderation/bans").query(&[
+            ("broadcaster_id", BROADCASTER_ID),
+            ("moderator_id", BROADCASTER_ID),
+            ("user_id", user_id),
+        ]);
+
+        self.send("unban_user", request).await?;
+
+        Ok(())
+    }
+
+    pub async fn send_announcement(
+        &self,
+        message: &str,
+        color: AnnouncementColor,
+    ) -> Result<(), TwitchError> {
+        let request = self
+            .request(Method::POST, "/chat/announcements")
+            .query(&[
+                ("broadcaster_id", BROADCASTER_ID),
+                ("moderator_id", BROADCASTER_ID),
+            ])
+            .json(&AnnouncementRequest { message, color });
+
+        self.send("send_announcement", request).await?;
+
+        Ok(())
+    }
+
+    /// Twitch's own `/shoutout`, only works while live and at most once every
+    /// couple of minutes
+    pub async fn send_shoutout(&self, user_id: &str) -> Result<(), TwitchError> {
+        let request = self.request(Method::POST, "/chat/shoutouts").query(&[
+            ("from_broadcaster_id", BROADCASTER_ID),
+            ("to_broadcaster_id", user_id),
+            ("moderator_id", BROADCASTER_ID),
+        ]);
+
+        self.send("send_shoutout", request).await?;
+
+        Ok(())
+    }
+
+    /// `None` while offline, markers only go on live streams
+    pub async fn create_stream_marker(
+        &self,
+        description: &str,
+    ) -> Result<Option<StreamMarker>, TwitchError> {
+        let request = self
+            .request(Method::POST, "/streams/markers")
+            .json(&StreamMarkerRequest {
+                user_id: BROADCASTER_ID,
+                description,
+            });
+
+        not_live_is_none(self.send_one("create_stream_marker", request).await)
+    }
+
+    /// `None` while offline. The clip takes a few seconds to be processed after this
+    pub async fn create_clip(&self) -> Result<Option<Clip>, TwitchError> {
+        let request = self
+            .request(Method::POST, "/clips")
+            .query(&[("broadcaster_id", BROADCASTER_ID)]);
+
+        not_live_is_none(self.send_one("create_clip", request).await)
+    }
+
+    pub async fn update_chat_settings(
+        &self,
+        settings: &ChatSettingsUpdate,
+    ) -> Result<(), TwitchError> {
+        let request = self
+            .request(Method::PATCH, "/chat/settings")
+            .query(&[
+                ("broadcaster_id", BROADCASTER_ID),
+                ("moderator_id", BROADCASTER_ID),
+            ])
+            .json(settings);
+
+        self.send("update_chat_settings", request).await?;
+
+        Ok(())
+    }
+
+    /// Starts a 90 second ad break
+    pub async fn run_ads(&self) -> Result<Option<Commercial>, TwitchError> {
+        let request = self
+            .request(Method::POST, "/channels/commercial")
+            .json(&CommercialRequest {
+                broadcaster_id: BROADCASTER_ID,
+                length: 90,
+            });
+
+        self.send_one("run_ads", request).await
+    }
+
+    pub async fn get_chat_badges(&self) -> Result<BadgeImages, TwitchError> {
+        let mut badges = BadgeImages::new();
+
+        for request in [
+            self.request(Method::GET, "/chat/badges/global"),
+            self.request(Method::GET, "/chat/badges")
+                .query(&[("broadcaster_id", BROADCASTER_ID)]),
+        ] {
+            let sets = self
+                .send_json::<Page<ChatBadgeSet>>("get_chat_badges", request)
+                .await?;
+
+            for set in sets.data {
+                for version in set.versions {
+                    badges.insert((set.set_id.clone(), version.id), version.image_url_2x);
+                }
+            }
+        }
+
+        Ok(badges)
+    }
+
+    /// Everyone with the VIP badge in the channel
+    pub fn get_vips(&self) -> impl Stream<Item = Result<Vip, TwitchError>> + '_ {
+        let request = self
+            .request(Method::GET, "/channels/vips")
+            .query(&[("broadcaster_id", BROADCASTER_ID), ("first", "100")]);
+
+        self.paginate("get_vips", request)
+    }
+
+    /// Subscribes a websocket eventsub session to `sub_type` events
+    pub async fn create_eventsub_subscription(
+        &self,
+        sub_type: &str,
+        version: &str,
+        condition: Value,
+        session_id: &str,
+    ) -> Result<(), TwitchError> {
+        let request = self.request(Method::POST, "/eventsub/subscriptions").json(
+            &EventsubSubscriptionRequest {
+                r#type: sub_type,
+                version,
+                condition,
+                transport: EventsubTransport {
+                    method: "websocket",
+                    session_id,
+                },
+            },
+        );
+
+        self.send(sub_type, request).await?;
+
+        Ok(())
+    }
+}
+
+/// Twitch answers requests that need the stream to be live with a 404
+fn not_live_is_none<T>(result: Result<Option<T>, TwitchError>) -> Result<Option<T>, TwitchError> {
+    match result {
+        Err(TwitchError::TwitchApiError { status, .. }) if status == StatusCode::NOT_FOUND => {
+            Ok(None)
+        }
+        result => result,
+    }
+}
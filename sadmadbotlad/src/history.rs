@@ -0,0 +1,184 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sadmadbotlad_protocol::EventRecord;
+use serde::{Deserialize, Serialize};
+
+use crate::{APP, AlertEventType, EventsCommand, HistoryFormat, db::Db};
+
+/// A CSV row, flattened so it can be summed up in a spreadsheet
+#[derive(Serialize)]
+struct CsvEvent<'a> {
+    id: i32,
+    ctime: DateTime<Utc>,
+    kind: &'static str,
+    user: Option<&'a str>,
+    tier: Option<&'a str>,
+    /// bits, gifted subs, raid viewers or resub months
+    amount: Option<u64>,
+    message: Option<&'a str>,
+    hidden: bool,
+    /// the whole event, so the file can be imported again
+    payload: String,
+}
+
+impl<'a> CsvEvent<'a> {
+    fn new(event: &'a EventRecord) -> serde_json::Result<Self> {
+        let (tier, amount, message) = match &event.alert_type {
+            AlertEventType::Follow { .. } => (None, None, None),
+            AlertEventType::Raid { viewers, .. } => (None, Some(*viewers), None),
+            AlertEventType::Subscribe { tier, .. } | AlertEventType::GiftedSub { tier, .. } => {
+                (Some(tier.as_str()), None, None)
+            }
+            AlertEventType::ReSubscribe {
+                tier,
+                subscribed_for,
+                ..
+            } => (Some(tier.as_str()), Some(*subscribed_for), None),
+            AlertEventType::GiftSub { tier, total, .. } => {
+                (Some(tier.as_str()), Some(*total), None)
+            }
+            AlertEventType::Bits { bits, message, .. } => {
+                (None, Some(*bits), Some(message.as_str()))
+            }
+        };
+
+        Ok(Self {
+            id: event.id,
+            ctime: event.ctime,
+            kind: event.alert_type.kind(),
+            user: event.alert_type.user(),
+            tier,
+            amount,
+            message,
+            hidden: event.hidden,
+            payload: serde_json::to_string(&event.alert_type)?,
+        })
+    }
+}
+
+/// The columns an import needs, the rest are only there for reading
+#[derive(Deserialize)]
+struct CsvImport {
+    ctime: DateTime<Utc>,
+    hidden: bool,
+    payload: String,
+}
+
+/// Runs an `events` subcommand against the configured database
+pub async fn run(command: &EventsCommand) -> anyhow::Result<()> {
+    let (db, db_thread) = Db::open(APP.config.database_path.clone()).await?;
+
+    let result = run_command(&db, command).await;
+
+    db.close();
+    if db_thread.join().is_err() {
+        anyhow::bail!("database thread panicked");
+    }
+
+    result
+}
+
+async fn run_command(db: &Db, command: &EventsCommand) -> anyhow::Result<()> {
+    match command {
+        EventsCommand::Export {
+            format,
+            since,
+            until,
+            output,
+        } => {
+            let events = db.get_events(*since, *until).await?;
+
+            match output {
+                Some(path) => {
+                    let file = fs::File::create(path)
+                        .with_context(|| format!("could not create {}", path.display()))?;
+                    export(&events, *format, file)?;
+                    eprintln!("Exported {} events to {}", events.len(), path.display());
+                }
+                None => export(&events, *format, io::stdout().lock())?,
+            }
+
+            Ok(())
+        }
+        EventsCommand::Import { file, format } => {
+            let format = match format {
+                Some(format) => *format,
+                None => guess_format(file)?,
+            };
+
+            let events = import(file, format)?;
+            let total = events.len();
+            let imported = db.import_events(events).await?;
+
+            eprintln!(
+                "Imported {imported} events, skipped {} that were already stored",
+                total - imported
+            );
+
+            Ok(())
+        }
+    }
+}
+
+fn export(events: &[EventRecord], format: HistoryFormat, out: impl Write) -> anyhow::Result<()> {
+    match format {
+        HistoryFormat::Json => {
+            let mut out = io::BufWriter::new(out);
+            serde_json::to_writer_pretty(&mut out, events)?;
+            writeln!(out)?;
+            out.flush()?;
+        }
+        HistoryFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for event in events {
+                writer.serialize(CsvEvent::new(event)?)?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn import(path: &Path, format: HistoryFormat) -> anyhow::Result<Vec<EventRecord>> {
+    let file =
+        fs::File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+
+    match format {
+        HistoryFormat::Json => Ok(serde_json::from_reader(io::BufReader::new(file))?),
+        HistoryFormat::Csv => csv::Reader::from_reader(file)
+            .deserialize::<CsvImport>()
+            .enumerate()
+            .map(|(i, row)| {
+                // +2 for the header and 1-based line numbers
+                let row = row.with_context(|| format!("line {}", i + 2))?;
+                let alert_type = serde_json::from_str(&row.payload)
+                    .with_context(|| format!("invalid payload on line {}", i + 2))?;
+
+                Ok(EventRecord {
+                    id: 0,
+                    alert_type,
+                    ctime: row.ctime,
+                    hidden: row.hidden,
+                })
+            })
+            .collect(),
+    }
+}
+
+fn guess_format(path: &Path) -> anyhow::Result<HistoryFormat> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => Ok(HistoryFormat::Csv),
+        Some("json") => Ok(HistoryFormat::Json),
+        _ => anyhow::bail!(
+            "can't tell the format of {} from its extension, pass --format",
+            path.display()
+        ),
+    }
+}
@@ -3,20 +3,28 @@ use std::{collections::HashMap, fmt::Display, panic::AssertUnwindSafe, path::Pat
 use crate::{
     APP, Alert, CommandsError, CommandsLoader,
     commands::{Context, run_hebi},
-    db::DBMessage,
+    db::{Chatter, DatabaseError, Db, NewChatMessage},
+    emotes::{ComboTracker, Emotes},
+    helix::{BadgeImages, HelixClient},
+    moderation::{ChatFilters, RepeatTracker, enforce},
     song_requests::{QueueMessages, SongRequestsError},
-    twitch::{TwitchError, TwitchTokenMessages},
+    supervisor::Health,
+    twitch::{TokenRole, TwitchError, TwitchTokenMessages, UserToken},
 };
 use futures::FutureExt;
 use futures_util::{SinkExt, StreamExt};
 use libmpv::Mpv;
 use notify::{RecommendedWatcher, Watcher};
+use sadmadbotlad_protocol::{
+    ChatBadge, ChatDeletion, ChatEmote, ChatEvent, ChatMessage, Credits, EmoteEvent, EmoteProvider,
+};
 use tokio::sync::{
     broadcast,
     mpsc::{self, UnboundedSender},
     oneshot,
 };
 use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio_util::sync::CancellationToken;
 
 #[derive(thiserror::Error, Debug)]
 pub enum IrcError {
@@ -57,41 +65,71 @@ pub enum IrcError {
     SongRequest(#[from] SongRequestsError),
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn irc_connect(
     alerts_sender: broadcast::Sender<Alert>,
+    credits_sender: broadcast::Sender<Credits>,
+    chat_sender: broadcast::Sender<ChatEvent>,
+    emotes_sender: broadcast::Sender<EmoteEvent>,
     queue_sender: mpsc::UnboundedSender<QueueMessages>,
     token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-    db_tx: std::sync::mpsc::Sender<DBMessage>,
+    token_changed_sender: broadcast::Sender<(TokenRole, UserToken)>,
+    helix: HelixClient,
+    db: Db,
+    emotes: Emotes,
+    filters: Arc<ChatFilters>,
     mpv: Arc<Mpv>,
+    health: Health,
+    shutdown: CancellationToken,
 ) -> Result<(), IrcError> {
     tracing::info!("Starting IRC");
 
-    read(queue_sender, token_sender, mpv, alerts_sender, db_tx).await?;
+    read(
+        queue_sender,
+        token_sender,
+        token_changed_sender,
+        helix,
+        mpv,
+        alerts_sender,
+        credits_sender,
+        chat_sender,
+        emotes_sender,
+        db,
+        emotes,
+        filters,
+        health,
+        shutdown,
+    )
+    .await?;
 
     Ok(())
 }
 
+/// Logs in with the bot's token, returns the login it's for
 pub async fn irc_login(
     irc_sender: tokio::sync::mpsc::Sender<Message>,
     token_sender: UnboundedSender<TwitchTokenMessages>,
-) -> Result<(), IrcError> {
+) -> Result<String, IrcError> {
     let cap = Message::Text(String::from("CAP REQ :twitch.tv/commands twitch.tv/tags").into());
 
     irc_sender.send(cap).await?;
 
     let (one_shot_sender, one_shot_receiver) = oneshot::channel();
 
-    token_sender.send(TwitchTokenMessages::GetToken(one_shot_sender))?;
+    token_sender.send(TwitchTokenMessages::GetToken(
+        TokenRole::Bot,
+        one_shot_sender,
+    ))?;
 
-    let Ok(api_info) = one_shot_receiver.await else {
+    let Ok(token) = one_shot_receiver.await else {
         return Err(IrcError::TwitchError(TwitchError::TokenError));
     };
 
-    let pass_msg = Message::Text(format!("PASS oauth:{}", api_info.twitch_access_token).into());
+    let pass_msg = Message::Text(format!("PASS oauth:{}", token.access_token).into());
 
     irc_sender.send(pass_msg).await?;
 
-    let nick_msg = Message::Text(format!("NICK {}", api_info.user).into());
+    let nick_msg = Message::Text(format!("NICK {}", token.login).into());
 
     irc_sender.send(nick_msg).await?;
 
@@ -99,16 +137,25 @@ pub async fn irc_login(
         .send(Message::Text(String::from("JOIN #sadmadladsalman").into()))
         .await?;
 
-    Ok(())
+    Ok(token.login)
 }
 
 #[allow(clippy::too_many_arguments)]
 async fn read(
     queue_sender: mpsc::UnboundedSender<QueueMessages>,
     token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
+    token_changed_sender: broadcast::Sender<(TokenRole, UserToken)>,
+    helix: HelixClient,
     mpv: Arc<Mpv>,
     alerts_sender: broadcast::Sender<Alert>,
-    _db_tx: std::sync::mpsc::Sender<DBMessage>,
+    credits_sender: broadcast::Sender<Credits>,
+    chat_sender: broadcast::Sender<ChatEvent>,
+    emotes_sender: broadcast::Sender<EmoteEvent>,
+    db: Db,
+    emotes: Emotes,
+    filters: Arc<ChatFilters>,
+    health: Health,
+    shutdown: CancellationToken,
 ) -> Result<(), IrcError> {
     // let voters = Arc::new(RwLock::new(HashSet::new()));
 
@@ -146,6 +193,10 @@ async fn read(
         notify::RecursiveMode::Recursive,
     )?;
 
+    let mut combos = ComboTracker::default();
+    let mut repeats = RepeatTracker::default();
+    let mut token_changed = token_changed_sender.subscribe();
+
     'restart: loop {
         tracing::debug!("irc 'restart loop");
 
@@ -155,7 +206,7 @@ async fn read(
 
         let (irc_sender, mut irc_receiver) = tokio::sync::mpsc::channel::<Message>(200);
 
-        tokio::spawn(async move {
+        let writer = tokio::spawn(async move {
             while let Some(message) = irc_receiver.recv().await {
                 if let Err(e) = ws_sender.send(message).await {
                     tracing::error!("Failed to send on twitch IRC websocket: {e}");
@@ -163,18 +214,61 @@ async fn read(
             }
         });
 
-        irc_login(irc_sender.clone(), token_sender.clone()).await?;
+        let login = irc_login(irc_sender.clone(), token_sender.clone()).await?;
+
+        // the chat overlay can do without badges, so this isn't worth restarting over
+        let badges = helix.get_chat_badges().await.unwrap_or_else(|e| {
+            tracing::error!("failed to get chat badges: {e}");
+            BadgeImages::new()
+        });
 
         let mut vm = run_hebi(
             irc_sender.clone(),
             alerts_sender.clone(),
-            token_sender.clone(),
+            helix.clone(),
             mpv.clone(),
             queue_sender.clone(),
+            credits_sender.clone(),
+            db.clone(),
+            emotes.clone(),
+            filters.clone(),
+            health.clone(),
         )
         .await?;
 
-        while let Some(msg) = ws_receiver.next().await {
+        loop {
+            let msg = tokio::select! {
+                msg = ws_receiver.next() => msg,
+                Ok((role, token)) = token_changed.recv() => {
+                    // refreshes don't matter once logged in, a token for
+                    // another account does
+                    if role == TokenRole::Bot && !token.login.eq_ignore_ascii_case(&login) {
+                        tracing::info!("twitch token is now for {}, logging in again", token.login);
+                        continue 'restart;
+                    }
+                    continue;
+                }
+                _ = shutdown.cancelled() => {
+                    tracing::info!("Leaving IRC");
+
+                    irc_sender
+                        .send(Message::Text(String::from("PART #sadmadladsalman").into()))
+                        .await?;
+                    irc_sender.send(Message::Close(None)).await?;
+
+                    // the writer stops once every sender is gone, after flushing the PART
+                    drop(vm);
+                    drop(irc_sender);
+                    writer.await.ok();
+
+                    return Ok(());
+                }
+            };
+
+            let Some(msg) = msg else {
+                continue 'restart;
+            };
+
             match msg {
                 Ok(Message::Ping(ping)) => {
                     tracing::debug!("IRC WebSocket Ping {ping:?}");
@@ -182,9 +276,60 @@ async fn read(
                         .send(Message::Pong(tokio_tungstenite::tungstenite::Bytes::new()))
                         .await?;
                 }
+                Ok(Message::Text(msg))
+                    if matches!(irc_command(&msg), Some("CLEARMSG" | "CLEARCHAT")) =>
+                {
+                    if let Err(e) = handle_clear(&db, &chat_sender, &msg).await {
+                        tracing::error!("failed to record chat deletion: {e}");
+                    }
+                }
                 Ok(Message::Text(msg)) if msg.contains("PRIVMSG") => {
                     let parsed_msg = parse_irc(&msg);
 
+                    if let Some(chat_message) = parsed_msg.chat_message() {
+                        if let Err(e) = db.new_chat_message(chat_message).await {
+                            tracing::error!("failed to record chat message: {e}");
+                        }
+                    }
+
+                    if let Some(overlay_message) = parsed_msg.overlay_message(&badges, &emotes) {
+                        // caught messages are taken down, so they never reach
+                        // the overlays or run a command
+                        if let Some(filter) =
+                            filters.check(&parsed_msg, &overlay_message, &mut repeats)
+                        {
+                            tokio::spawn({
+                                let enforcement = enforce(
+                                    filters.clone(),
+                                    filter,
+                                    parsed_msg,
+                                    overlay_message,
+                                    db.clone(),
+                                    helix.clone(),
+                                    irc_sender.clone(),
+                                );
+                                async move {
+                                    if let Err(e) = enforcement.await {
+                                        tracing::error!("failed to enforce chat filter: {e}");
+                                    }
+                                }
+                            });
+
+                            continue;
+                        }
+
+                        let used = overlay_message.used_emotes();
+
+                        if !used.is_empty() {
+                            for combo in combos.record(&used) {
+                                let _ = emotes_sender.send(combo);
+                            }
+                            let _ = emotes_sender.send(EmoteEvent::Used { emotes: used });
+                        }
+
+                        let _ = chat_sender.send(ChatEvent::Message(overlay_message));
+                    }
+
                     let message = if parsed_msg.tags.get_reply().is_some() {
                         // remove mention
                         parsed_msg
@@ -337,14 +482,115 @@ impl Tags {
     pub fn get_sender(&self) -> Option<String> {
         self.0.get("display-name").map(|s| s.to_string())
     }
+    /// Twitch emotes from `emotes=25:0-4,12-16/1902:6-10`, in the order they appear
+    pub fn emotes(&self) -> Vec<ChatEmote> {
+        let Some(emotes) = self.0.get("emotes") else {
+            return Vec::new();
+        };
+
+        let mut emotes = emotes
+            .split('/')
+            .filter_map(|emote| emote.split_once(':'))
+            .flat_map(|(id, positions)| {
+                positions.split(',').filter_map(move |position| {
+                    let (start, end) = position.split_once('-')?;
+                    Some(ChatEmote {
+                        id: id.to_string(),
+                        provider: EmoteProvider::Twitch,
+                        start: start.parse().ok()?,
+                        end: end.parse().ok()?,
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        emotes.sort_by_key(|emote| emote.start);
+        emotes
+    }
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct TwitchIrcMessage {
     pub tags: Tags,
+    pub login: String,
     pub message: String,
 }
 
+impl TwitchIrcMessage {
+    /// Who sent the message, `None` if twitch left out the user tags
+    pub fn chatter(&self) -> Option<Chatter> {
+        Some(Chatter {
+            id: self.tags.get("user-id")?.clone(),
+            login: self.login.clone(),
+            display_name: self.tags.get_sender()?,
+            is_subscriber: self.tags.get("subscriber").is_some_and(|s| s == "1"),
+            is_vip: self.tags.get("vip").is_some_and(|s| s == "1"),
+            is_mod: self.tags.is_mod().unwrap_or_default(),
+        })
+    }
+
+    /// The message as it goes into the chat log
+    pub fn chat_message(&self) -> Option<NewChatMessage> {
+        Some(NewChatMessage {
+            chatter: self.chatter()?,
+            msg_id: self.tags.get("id").cloned(),
+            message: self.message.clone(),
+            tags: self.tags.0.clone(),
+        })
+    }
+
+    /// The message as the chat overlay shows it, `None` if twitch left out the user tags
+    pub fn overlay_message(
+        &self,
+        badge_images: &BadgeImages,
+        emotes: &Emotes,
+    ) -> Option<ChatMessage> {
+        // `/me` messages come wrapped in CTCP, emote positions don't count the wrapper
+        let message = self
+            .message
+            .strip_prefix("\u{1}ACTION ")
+            .map(|action| action.trim_end_matches('\u{1}'))
+            .unwrap_or(&self.message);
+
+        let badges = self
+            .tags
+            .get("badges")
+            .into_iter()
+            .flat_map(|badges| badges.split(','))
+            .filter_map(|badge| badge.split_once('/'))
+            .map(|(set_id, version)| ChatBadge {
+                set_id: set_id.to_string(),
+                version: version.to_string(),
+                image_url: badge_images
+                    .get(&(set_id.to_string(), version.to_string()))
+                    .cloned(),
+            })
+            .collect();
+
+        let twitch_emotes = self.tags.emotes();
+
+        // twitch's own win over a third-party emote in the same spot
+        let mut all_emotes = emotes.find_in(message);
+        all_emotes.retain(|emote| {
+            !twitch_emotes
+                .iter()
+                .any(|twitch| emote.start <= twitch.end && twitch.start <= emote.end)
+        });
+        all_emotes.extend(twitch_emotes);
+        all_emotes.sort_by_key(|emote| emote.start);
+
+        Some(ChatMessage {
+            id: self.tags.get("id")?.clone(),
+            user_id: self.tags.get("user-id")?.clone(),
+            display_name: self.tags.get_sender()?,
+            color: self.tags.get("color").filter(|c| !c.is_empty()).cloned(),
+            badges,
+            message: message.to_string(),
+            emotes: all_emotes,
+        })
+    }
+}
+
 impl From<HashMap<String, String>> for Tags {
     fn from(value: HashMap<String, String>) -> Self {
         Tags(value)
@@ -355,11 +601,78 @@ pub fn to_irc_message(msg: impl Display) -> String {
     format!("PRIVMSG #sadmadladsalman :{}", msg)
 }
 
+/// The command of a raw IRC line, after its tags and prefix
+fn irc_command(msg: &str) -> Option<&str> {
+    let mut parts = msg
+        .split(' ')
+        .skip_while(|part| part.starts_with('@') || part.starts_with(':'));
+
+    parts.next().map(str::trim_end)
+}
+
+/// Parses `@key=value;key=value`, leaving the values escaped
+fn parse_tags(tags: &str) -> Tags {
+    tags.trim_start_matches('@')
+        .split(';')
+        .filter_map(|tag| tag.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<HashMap<String, String>>()
+        .into()
+}
+
+/// Tells the chat overlay about a CLEARMSG or CLEARCHAT, and marks the
+/// messages it took down in the chat log
+async fn handle_clear(
+    db: &Db,
+    chat_sender: &broadcast::Sender<ChatEvent>,
+    msg: &str,
+) -> Result<(), DatabaseError> {
+    let tags = match msg.split_once(' ') {
+        Some((tags, _)) if tags.starts_with('@') => parse_tags(tags),
+        _ => Tags::default(),
+    };
+
+    if irc_command(msg) == Some("CLEARMSG") {
+        return match tags.get("target-msg-id") {
+            Some(msg_id) => {
+                let _ = chat_sender.send(ChatEvent::MessageDeleted { id: msg_id.clone() });
+                db.delete_chat_message(msg_id.clone()).await
+            }
+            None => Ok(()),
+        };
+    }
+
+    let user_id = tags.get("target-user-id").cloned();
+    let _ = chat_sender.send(ChatEvent::Cleared {
+        user_id: user_id.clone(),
+    });
+
+    // a CLEARCHAT with no target user is `/clear`
+    match user_id {
+        Some(user_id) => {
+            let deletion = if tags.contains_key("ban-duration") {
+                ChatDeletion::Timeout
+            } else {
+                ChatDeletion::Ban
+            };
+
+            db.clear_chat(Some(user_id), deletion).await
+        }
+        None => db.clear_chat(None, ChatDeletion::Clear).await,
+    }
+}
+
 fn parse_irc(msg: &str) -> TwitchIrcMessage {
     let (tags, message) = msg.split_once(' ').expect("sperate tags and message");
 
     let message = &message[1..];
 
+    // the prefix is `login!login@login.tmi.twitch.tv`
+    let login = message
+        .split_once('!')
+        .map(|(login, _)| login.to_string())
+        .unwrap_or_default();
+
     let message = message
         .split_once(':')
         .expect("message")
@@ -383,5 +696,53 @@ fn parse_irc(msg: &str) -> TwitchIrcMessage {
         .collect::<HashMap<String, String>>()
         .into();
 
-    TwitchIrcMessage { tags, message }
+    TwitchIrcMessage {
+        tags,
+        login,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sadmadbotlad_protocol::Emote;
+
+    #[test]
+    fn overlay_message_drops_third_party_emotes_over_twitch_ones() {
+        let msg = parse_irc(
+            "@emotes=25:0-4;id=abc;user-id=1;display-name=A;color= :a!a@a.tmi.twitch.tv PRIVMSG #sadmadladsalman :Kappa peepoHappy",
+        );
+
+        let emotes = ["Kappa", "peepoHappy"]
+            .into_iter()
+            .map(|name| Emote {
+                id: format!("7tv-{name}"),
+                name: name.to_string(),
+                provider: EmoteProvider::SevenTv,
+            })
+            .collect::<Emotes>();
+
+        let message = msg
+            .overlay_message(&BadgeImages::new(), &emotes)
+            .expect("user tags");
+
+        assert_eq!(
+            message.emotes,
+            [
+                ChatEmote {
+                    id: "25".into(),
+                    provider: EmoteProvider::Twitch,
+                    start: 0,
+                    end: 4,
+                },
+                ChatEmote {
+                    id: "7tv-peepoHappy".into(),
+                    provider: EmoteProvider::SevenTv,
+                    start: 6,
+                    end: 15,
+                },
+            ]
+        );
+    }
 }
@@ -1,9 +1,10 @@
-use clap::Parser;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
 use hebi::prelude::*;
 use lazy_static::lazy_static;
+use sadmadbotlad_protocol::Capability;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, io::Read, path::PathBuf, sync::Arc};
-use tokio::task::JoinHandle;
+use std::{collections::HashMap, fs, io::Read, net::SocketAddr, path::PathBuf, sync::Arc};
 use tracing::{Level, metadata::LevelFilter};
 use tracing_appender::rolling;
 use tracing_subscriber::{
@@ -11,51 +12,31 @@ use tracing_subscriber::{
     util::SubscriberInitExt,
 };
 
+use moderation::ModerationConfig;
 use song_requests::Queue;
 use twitch::TwitchApiInfo;
 
+pub use sadmadbotlad_protocol::{Alert, AlertEventType};
+
 pub mod commands;
 pub mod db;
 pub mod discord;
+#[cfg(feature = "embed-frontend")]
+pub mod embedded_frontend;
+pub mod emotes;
 pub mod eventsub;
+pub mod helix;
+pub mod history;
 pub mod irc;
+pub mod moderation;
 pub mod obs_websocket;
 pub mod song_requests;
 pub mod sr_ws_server;
+pub mod supervisor;
 pub mod twitch;
 pub mod ws_server;
 pub mod youtube;
 
-pub async fn flatten<T>(handle: JoinHandle<anyhow::Result<T>>) -> anyhow::Result<T> {
-    match handle.await {
-        Ok(Ok(result)) => Ok(result),
-        Ok(Err(err)) => Err(err),
-        Err(e) => Err(e.into()),
-    }
-}
-
-pub fn oneshot<T>() -> (OneShotSender<T>, OneShotReceiver<T>) {
-    let (tx, rx) = std::sync::mpsc::channel();
-
-    (OneShotSender(tx), OneShotReceiver(rx))
-}
-
-pub struct OneShotSender<T>(std::sync::mpsc::Sender<T>);
-
-impl<T> OneShotSender<T> {
-    pub fn send(self, t: T) -> Result<(), std::sync::mpsc::SendError<T>> {
-        self.0.send(t)
-    }
-}
-
-pub struct OneShotReceiver<T>(std::sync::mpsc::Receiver<T>);
-
-impl<T> OneShotReceiver<T> {
-    pub fn recv(self) -> Result<T, std::sync::mpsc::RecvError> {
-        self.0.recv()
-    }
-}
-
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
@@ -68,11 +49,68 @@ pub struct Cli {
     #[arg(short = 'd', long)]
     pub cmd_delim: Option<char>,
     #[arg(short, long)]
-    pub port: Option<u16>,
-    #[arg(short, long)]
     pub frontend_port: Option<u16>,
     #[arg(short, long)]
     pub static_path: Option<PathBuf>,
+    /// where fetched 7TV, BTTV and FFZ emotes are kept for when they can't be fetched
+    #[arg(long)]
+    pub emote_cache_path: Option<PathBuf>,
+    /// how many days of chat to keep, 0 keeps everything
+    #[arg(long)]
+    pub chat_log_retention_days: Option<u32>,
+    /// give every websocket client full control when no tokens are configured
+    #[arg(long)]
+    pub insecure_no_auth: bool,
+    /// run a maintenance command instead of the bot
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Manage the stored event history
+    #[command(subcommand)]
+    Events(EventsCommand),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum EventsCommand {
+    /// Write events to a file, or stdout, oldest first
+    Export {
+        #[arg(long, value_enum, default_value_t = HistoryFormat::Csv)]
+        format: HistoryFormat,
+        /// YYYY-MM-DD or an RFC 3339 timestamp
+        #[arg(long, value_parser = parse_time)]
+        since: Option<DateTime<Utc>>,
+        /// YYYY-MM-DD or an RFC 3339 timestamp, not included
+        #[arg(long, value_parser = parse_time)]
+        until: Option<DateTime<Utc>>,
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Add the events from an export, skipping ones that are already stored
+    Import {
+        file: PathBuf,
+        /// guessed from the file extension when not given
+        #[arg(long, value_enum)]
+        format: Option<HistoryFormat>,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    Csv,
+    Json,
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).expect("midnight").and_utc());
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| format!("expected YYYY-MM-DD or an RFC 3339 timestamp: {e}"))
 }
 
 #[derive(Debug)]
@@ -81,14 +119,19 @@ pub struct Config {
     pub config_path: PathBuf,
     pub commands_path: PathBuf,
     pub cmd_delim: char,
-    pub port: u16,
     pub frontend_port: u16,
-    pub static_path: PathBuf,
+    /// serve the frontend from here instead of the embedded build
+    pub static_path: Option<PathBuf>,
+    pub emote_cache_path: PathBuf,
+    /// `None` keeps the chat log forever
+    pub chat_log_retention: Option<Duration>,
+    pub insecure_no_auth: bool,
 }
 
 #[derive(Debug)]
 pub struct App {
     pub config: Config,
+    pub command: Option<Command>,
 }
 
 impl App {
@@ -101,10 +144,16 @@ impl App {
                 config_path: flags.config_path.unwrap_or("./config.toml".into()),
                 commands_path: flags.commands_path.unwrap_or("./commands".into()),
                 cmd_delim: flags.cmd_delim.unwrap_or('!'),
-                port: flags.port.unwrap_or(3000),
                 frontend_port: flags.frontend_port.unwrap_or(8080),
-                static_path: flags.static_path.unwrap_or_default(),
+                static_path: flags.static_path,
+                emote_cache_path: flags.emote_cache_path.unwrap_or("./emote_cache".into()),
+                chat_log_retention: match flags.chat_log_retention_days.unwrap_or(90) {
+                    0 => None,
+                    days => Some(Duration::days(days.into())),
+                },
+                insecure_no_auth: flags.insecure_no_auth,
             },
+            command: flags.command,
         }
     }
 }
@@ -119,107 +168,6 @@ lazy_static! {
     pub static ref APP: App = App::new();
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub enum AlertEventType {
-    Follow {
-        follower: String,
-    },
-    Raid {
-        from: String,
-        viewers: u64,
-    },
-    Subscribe {
-        subscriber: String,
-        tier: String,
-    },
-    ReSubscribe {
-        subscriber: String,
-        tier: String,
-        subscribed_for: u64,
-        streak: u64,
-    },
-    GiftSub {
-        gifter: String,
-        total: u64,
-        tier: String,
-    },
-    GiftedSub {
-        gifted: String,
-        tier: String,
-    },
-    Bits {
-        message: String,
-        is_anonymous: bool,
-        cheerer: String,
-        bits: u64,
-    },
-}
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct Alert {
-    new: bool,
-    r#type: AlertEventType,
-}
-
-impl Alert {
-    pub fn raid_test() -> Self {
-        Alert {
-            new: true,
-            r#type: AlertEventType::Raid {
-                from: String::from("lmao"),
-                viewers: 9999,
-            },
-        }
-    }
-    pub fn follow_test() -> Self {
-        Alert {
-            new: true,
-            r#type: AlertEventType::Follow {
-                follower: String::from("lmao"),
-            },
-        }
-    }
-    pub fn sub_test() -> Self {
-        Alert {
-            new: true,
-            r#type: AlertEventType::Subscribe {
-                subscriber: String::from("lmao"),
-                tier: String::from("3"),
-            },
-        }
-    }
-    pub fn resub_test() -> Self {
-        Alert {
-            new: true,
-            r#type: AlertEventType::ReSubscribe {
-                subscriber: String::from("lmao"),
-                tier: String::from("3"),
-                subscribed_for: 4,
-                streak: 2,
-            },
-        }
-    }
-    pub fn giftsub_test() -> Self {
-        Alert {
-            new: true,
-            r#type: AlertEventType::GiftSub {
-                gifter: String::from("lmao"),
-                total: 9999,
-                tier: String::from("3"),
-            },
-        }
-    }
-    pub fn asd_test() -> Self {
-        Alert {
-            new: true,
-            r#type: AlertEventType::Raid {
-                from: String::from("asd"),
-                viewers: 9999,
-            },
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 pub enum SrEvent {
     QueueRequest,
@@ -233,6 +181,60 @@ pub struct ApiInfo {
     pub twitch: TwitchApiInfo,
     pub discord_token: String,
     pub obs_server_password: String,
+    #[serde(default)]
+    pub ws_auth: WsAuth,
+    #[serde(default)]
+    pub moderation: ModerationConfig,
+}
+
+/// Tokens websocket clients authenticate with, in the `[ws_auth]` table of config.toml
+///
+/// With no tokens configured only clients on this machine get in, read-only,
+/// unless `--insecure-no-auth` turns auth off
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WsAuth {
+    /// for overlays, which only need to receive events
+    #[serde(default)]
+    pub read_tokens: Vec<String>,
+    /// for dashboards, which can also replay and inject alerts
+    #[serde(default)]
+    pub control_tokens: Vec<String>,
+    /// from `--insecure-no-auth`
+    #[serde(skip)]
+    pub insecure_no_auth: bool,
+}
+
+impl WsAuth {
+    pub fn is_enabled(&self) -> bool {
+        !self.read_tokens.is_empty() || !self.control_tokens.is_empty()
+    }
+
+    /// What a client can do before it sends a token
+    pub fn initial_capability(&self, peer: &SocketAddr) -> Option<Capability> {
+        if self.is_enabled() {
+            None
+        } else if self.insecure_no_auth {
+            Some(Capability::Control)
+        } else if peer.ip().is_loopback() {
+            Some(Capability::Read)
+        } else {
+            None
+        }
+    }
+
+    pub fn capability(&self, token: &str) -> Option<Capability> {
+        if !self.is_enabled() {
+            return self.insecure_no_auth.then_some(Capability::Control);
+        }
+
+        if self.control_tokens.iter().any(|t| t == token) {
+            Some(Capability::Control)
+        } else if self.read_tokens.iter().any(|t| t == token) {
+            Some(Capability::Read)
+        } else {
+            None
+        }
+    }
 }
 
 impl ApiInfo {
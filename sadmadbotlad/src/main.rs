@@ -1,27 +1,42 @@
 use anyhow::Context;
-use axum::Router;
+use axum::extract::State;
 use axum::http::StatusCode;
-use axum::routing::get_service;
-use sadmadbotlad::db::{DBMessage, Store};
+use axum::routing::{MethodRouter, get, get_service};
+use axum::{Json, Router};
+use futures_util::TryFutureExt;
+use sadmadbotlad::db::Db;
+use sadmadbotlad_protocol::{ChatEvent, Credits, EmoteEvent};
+use std::collections::BTreeMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 use tower_http::services::{ServeDir, ServeFile};
 use tower_http::trace::TraceLayer;
 
+use sadmadbotlad::emotes::{Emotes, refresh_emotes};
 use sadmadbotlad::eventsub::eventsub;
+use sadmadbotlad::helix::HelixClient;
 use sadmadbotlad::irc::irc_connect;
+use sadmadbotlad::moderation::ChatFilters;
 use sadmadbotlad::obs_websocket::obs_websocket;
 use sadmadbotlad::song_requests::{QueueMessages, SongRequest, SrQueue, play_song, setup_mpv};
-use sadmadbotlad::sr_ws_server::sr_ws_server;
-use sadmadbotlad::twitch::{TwitchToken, TwitchTokenMessages};
-use sadmadbotlad::ws_server::ws_server;
-use sadmadbotlad::{APP, Alert, ApiInfo, flatten, logging};
+use sadmadbotlad::sr_ws_server::{SongsWsState, songs_ws};
+use sadmadbotlad::supervisor::{Health, Supervisor, TaskStatus};
+use sadmadbotlad::twitch::{
+    AuthCode, TokenRole, TwitchToken, TwitchTokenMessages, UserToken, auth_callback,
+};
+use sadmadbotlad::ws_server::{AlertsWsState, alerts_ws};
+use sadmadbotlad::{APP, Alert, ApiInfo, Command, WsAuth, history, logging};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    if let Some(Command::Events(command)) = &APP.command {
+        return history::run(command).await;
+    }
+
     logging();
 
     rustls::crypto::aws_lc_rs::default_provider()
@@ -30,10 +45,7 @@ async fn main() -> anyhow::Result<()> {
 
     let api_info = ApiInfo::new().expect("Api info failed");
 
-    if let Err(e) = tokio::try_join!(
-        run_frontend(APP.config.frontend_port, &APP.config.static_path),
-        run(api_info),
-    ) {
+    if let Err(e) = run(api_info).await {
         tracing::error!("Sadmadladbot failed: {e:?}");
     }
 
@@ -43,10 +55,28 @@ async fn main() -> anyhow::Result<()> {
 async fn run(api_info: ApiInfo) -> anyhow::Result<()> {
     let api_info = Arc::new(api_info);
 
+    let shutdown = CancellationToken::new();
+
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            tracing::info!("Shutting down");
+            shutdown.cancel();
+        }
+    });
+
     let (token_request_sender, token_request_receiver) =
         mpsc::unbounded_channel::<TwitchTokenMessages>();
 
-    let twitch = TwitchToken::new(api_info.twitch.clone(), token_request_receiver);
+    let (code_sender, code_receiver) = mpsc::unbounded_channel::<AuthCode>();
+
+    let (token_changed_sender, _) = tokio::sync::broadcast::channel::<(TokenRole, UserToken)>(10);
+
+    let helix = HelixClient::new(
+        api_info.twitch.client_id.clone(),
+        token_request_sender.clone(),
+    );
 
     let (queue_sender, queue_receiver) = mpsc::unbounded_channel::<QueueMessages>();
 
@@ -54,30 +84,37 @@ async fn run(api_info: ApiInfo) -> anyhow::Result<()> {
 
     let (alerts_sender, _) = tokio::sync::broadcast::channel::<Alert>(100);
 
-    let (db_tx, db_rx) = std::sync::mpsc::channel();
-
-    std::thread::spawn(move || {
-        let store = Store::new().unwrap();
-        while let Ok(message) = db_rx.recv() {
-            match message {
-                DBMessage::NewEvent(alert_event_type) => {
-                    store.new_event(alert_event_type).unwrap();
-                }
-                DBMessage::GetEvent(id, one_shot_sender) => {
-                    one_shot_sender
-                        .send(store.get_event(id).unwrap().unwrap())
-                        .unwrap();
-                }
-                DBMessage::GetEvents(one_shot_sender) => {
-                    one_shot_sender.send(store.get_events().unwrap()).unwrap();
-                }
-            }
-        }
-    });
+    let (credits_sender, _) = tokio::sync::broadcast::channel::<Credits>(10);
+
+    let (chat_sender, _) = tokio::sync::broadcast::channel::<ChatEvent>(100);
+
+    let (emotes_sender, _) = tokio::sync::broadcast::channel::<EmoteEvent>(100);
+
+    let (db, db_thread) = Db::open(APP.config.database_path.clone()).await?;
+
+    let twitch = TwitchToken::new(
+        api_info.twitch.clone(),
+        db.clone(),
+        token_request_receiver,
+        code_receiver,
+        token_changed_sender.clone(),
+    );
 
     let mpv = Arc::new(setup_mpv());
 
-    let queue = SrQueue::new(api_info.clone(), song_sender, queue_receiver);
+    let emotes = Emotes::default();
+
+    let filters = Arc::new(ChatFilters::new(api_info.moderation.clone())?);
+
+    let saved_queue = db.get_saved_queue().await?;
+
+    let queue = SrQueue::new(
+        api_info.clone(),
+        song_sender,
+        queue_receiver,
+        db.clone(),
+        saved_queue,
+    );
 
     {
         let queue_sender = queue_sender.clone();
@@ -85,87 +122,254 @@ async fn run(api_info: ApiInfo) -> anyhow::Result<()> {
         std::thread::spawn(move || play_song(mpv, song_receiver, queue_sender));
     }
 
-    tokio::try_join!(
-        flatten(tokio::spawn({
-            let alerts_sender = alerts_sender.clone();
-            let token_sender = token_request_sender.clone();
-            let db_tx = db_tx.clone();
-            let api_info = api_info.clone();
-            async move {
-                eventsub(
-                    alerts_sender.clone(),
-                    token_sender.clone(),
-                    api_info.clone(),
-                    db_tx.clone(),
-                )
-                .await
-                .with_context(|| "eventsub")
-            }
-        })),
-        flatten(tokio::spawn(async move {
-            twitch.handle_messages().await.with_context(|| "twitch")
-        })),
-        flatten(tokio::spawn(async move {
-            queue.handle_messages().await.with_context(|| "queue")
-        })),
-        flatten(tokio::spawn({
-            let queue_sender = queue_sender.clone();
-            async move {
-                sr_ws_server(queue_sender.clone())
-                    .await
-                    .with_context(|| "sr_ws_server")
-            }
-        })),
-        flatten(tokio::spawn({
-            let alerts_sender = alerts_sender.clone();
-            let token_sender = token_request_sender.clone();
-            let db_tx = db_tx.clone();
-            async move {
-                irc_connect(
-                    alerts_sender.clone(),
-                    queue_sender,
-                    token_sender.clone(),
-                    db_tx.clone(),
-                    mpv,
-                )
-                .await
-                .with_context(|| "irc_connect")
-            }
-        })),
-        flatten(tokio::spawn(async move {
-            ws_server(alerts_sender, db_tx)
-                .await
-                .with_context(|| "ws_server")
-        })),
-        flatten(tokio::spawn(async move {
-            obs_websocket(
-                // e_sender,
-                token_request_sender,
-                api_info,
+    let ws_auth = Arc::new(WsAuth {
+        insecure_no_auth: APP.config.insecure_no_auth,
+        ..api_info.ws_auth.clone()
+    });
+
+    if !ws_auth.is_enabled() {
+        if ws_auth.insecure_no_auth {
+            tracing::warn!("no websocket tokens configured, every client gets full control");
+        } else {
+            tracing::warn!(
+                "no websocket tokens configured, only local clients can connect and only to read"
+            );
+        }
+    }
+
+    let alerts_state = AlertsWsState {
+        alerts_sender: alerts_sender.clone(),
+        credits_sender: credits_sender.clone(),
+        chat_sender: chat_sender.clone(),
+        emotes_sender: emotes_sender.clone(),
+        db: db.clone(),
+        ws_auth: ws_auth.clone(),
+        shutdown: shutdown.clone(),
+    };
+
+    let songs_state = SongsWsState {
+        queue_sender: queue_sender.clone(),
+        ws_auth,
+    };
+
+    let health = Health::default();
+
+    let mut supervisor = Supervisor::new(health.clone(), shutdown.clone());
+
+    supervisor.spawn("frontend", {
+        let health = health.clone();
+        let shutdown = shutdown.clone();
+        move || {
+            run_frontend(
+                APP.config.frontend_port,
+                APP.config.static_path.as_deref(),
+                alerts_state.clone(),
+                songs_state.clone(),
+                code_sender.clone(),
+                health.clone(),
+                shutdown.clone(),
+            )
+        }
+    });
+
+    supervisor.spawn("eventsub", {
+        let alerts_sender = alerts_sender.clone();
+        let credits_sender = credits_sender.clone();
+        let helix = helix.clone();
+        let db = db.clone();
+        let api_info = api_info.clone();
+        let shutdown = shutdown.clone();
+        move || {
+            eventsub(
+                alerts_sender.clone(),
+                credits_sender.clone(),
+                helix.clone(),
+                api_info.clone(),
+                db.clone(),
+                shutdown.clone(),
+            )
+            .map_err(anyhow::Error::from)
+        }
+    });
+
+    // the token and queue own their channels' receivers, so they're shared
+    // between attempts instead of being rebuilt on every restart
+    let twitch = Arc::new(tokio::sync::Mutex::new(twitch));
+
+    supervisor.spawn("twitch", {
+        let shutdown = shutdown.clone();
+        move || {
+            let twitch = twitch.clone();
+            let shutdown = shutdown.clone();
+            async move { Ok(twitch.lock().await.handle_messages(shutdown).await?) }
+        }
+    });
+
+    let queue = Arc::new(tokio::sync::Mutex::new(queue));
+
+    supervisor.spawn("queue", {
+        let shutdown = shutdown.clone();
+        move || {
+            let queue = queue.clone();
+            let shutdown = shutdown.clone();
+            async move { queue.lock().await.handle_messages(shutdown).await }
+        }
+    });
+
+    supervisor.spawn("emotes", {
+        let emotes = emotes.clone();
+        let shutdown = shutdown.clone();
+        move || {
+            refresh_emotes(
+                emotes.clone(),
+                APP.config.emote_cache_path.clone(),
+                shutdown.clone(),
+            )
+            .map_err(anyhow::Error::from)
+        }
+    });
+
+    supervisor.spawn("irc", {
+        let alerts_sender = alerts_sender.clone();
+        let credits_sender = credits_sender.clone();
+        let chat_sender = chat_sender.clone();
+        let emotes_sender = emotes_sender.clone();
+        let token_sender = token_request_sender.clone();
+        let helix = helix.clone();
+        let db = db.clone();
+        let emotes = emotes.clone();
+        let health = health.clone();
+        let mpv = mpv.clone();
+        let shutdown = shutdown.clone();
+        move || {
+            irc_connect(
+                alerts_sender.clone(),
+                credits_sender.clone(),
+                chat_sender.clone(),
+                emotes_sender.clone(),
+                queue_sender.clone(),
+                token_sender.clone(),
+                token_changed_sender.clone(),
+                helix.clone(),
+                db.clone(),
+                emotes.clone(),
+                filters.clone(),
+                mpv.clone(),
+                health.clone(),
+                shutdown.clone(),
             )
-            .await
-            .with_context(|| "obs_websocket")
-        }))
-    )?;
+            .map_err(anyhow::Error::from)
+        }
+    });
+
+    if let Some(retention) = APP.config.chat_log_retention {
+        supervisor.spawn("chat_log_retention", {
+            let db = db.clone();
+            let shutdown = shutdown.clone();
+            move || prune_chat_log(db.clone(), retention, shutdown.clone())
+        });
+    }
+
+    supervisor.spawn("obs_websocket", {
+        let shutdown = shutdown.clone();
+        move || obs_websocket(helix.clone(), api_info.clone(), shutdown.clone())
+    });
+
+    supervisor.wait().await;
+
+    if let Err(e) = mpv.command("stop", &[]) {
+        tracing::error!("Failed to stop mpv: {e}");
+    }
+
+    // everything queued before this, like the saved song queue, is written first
+    db.close();
+
+    if db_thread.join().is_err() {
+        return Err(anyhow::anyhow!("database thread panicked"));
+    }
+
+    tracing::info!("Shut down cleanly");
 
     Ok(())
 }
 
-async fn run_frontend(port: u16, static_path: impl AsRef<Path>) -> anyhow::Result<()> {
-    let static_path = static_path.as_ref();
-
-    let router = Router::new().fallback_service(
-        Router::new().fallback_service(
-            get_service(ServeDir::new(static_path).fallback(ServeFile::new(
-                PathBuf::from(static_path).join("index.html"),
-            )))
-            .handle_error(|error| async move {
-                tracing::error!(?error, "failed serving static file");
-                StatusCode::INTERNAL_SERVER_ERROR
-            })
-            .layer(TraceLayer::new_for_http()),
-        ),
-    );
+/// Deletes chat messages older than `retention`, once an hour
+async fn prune_chat_log(
+    db: Db,
+    retention: chrono::Duration,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+
+        let pruned = db.prune_chat_log(chrono::Utc::now() - retention).await?;
+
+        if pruned > 0 {
+            tracing::info!("pruned {pruned} chat messages");
+        }
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Serves the frontend, its websockets and the twitch auth callback, all on one port
+async fn run_frontend(
+    port: u16,
+    static_path: Option<&Path>,
+    alerts_state: AlertsWsState,
+    songs_state: SongsWsState,
+    code_sender: mpsc::UnboundedSender<AuthCode>,
+    health: Health,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let router = Router::new()
+        .route("/health", get(health_status).with_state(health))
+        .route("/ws/alerts", get(alerts_ws).with_state(alerts_state))
+        .route("/ws/songs", get(songs_ws).with_state(songs_state))
+        .route("/auth/callback", get(auth_callback).with_state(code_sender));
+
+    let router = match static_path {
+        Some(static_path) => router.fallback_service(static_files(static_path)),
+        #[cfg(feature = "embed-frontend")]
+        None => router.fallback(sadmadbotlad::embedded_frontend::serve),
+        #[cfg(not(feature = "embed-frontend"))]
+        None => router.fallback_service(static_files(Path::new(""))),
+    }
+    .layer(TraceLayer::new_for_http());
 
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
 
@@ -175,9 +379,36 @@ async fn run_frontend(port: u16, static_path: impl AsRef<Path>) -> anyhow::Resul
         .await
         .with_context(|| "frontend tcp listener")?;
 
-    axum::serve(listener, router)
-        .await
-        .with_context(|| "frontend axum server")?;
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.cancelled_owned())
+    .await
+    .with_context(|| "frontend axum server")?;
 
     Ok(())
 }
+
+/// Every supervised task's status, 503 once any of them has been given up on
+async fn health_status(
+    State(health): State<Health>,
+) -> (StatusCode, Json<BTreeMap<&'static str, TaskStatus>>) {
+    let status = if health.any_failed() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+
+    (status, Json(health.snapshot()))
+}
+
+fn static_files(static_path: &Path) -> MethodRouter {
+    get_service(ServeDir::new(static_path).fallback(ServeFile::new(
+        PathBuf::from(static_path).join("index.html"),
+    )))
+    .handle_error(|error| async move {
+        tracing::error!(?error, "failed serving static file");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
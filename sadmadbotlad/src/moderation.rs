@@ -0,0 +1,470 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use regex::Regex;
+use sadmadbotlad_protocol::ChatMessage;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    db::{DatabaseError, Db, NewStrike},
+    helix::HelixClient,
+    irc::{TwitchIrcMessage, to_irc_message},
+    twitch::TwitchError,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ModerationError {
+    #[error("invalid moderation pattern: {0}")]
+    RegexError(#[from] regex::Error),
+
+    #[error(transparent)]
+    DatabaseError(#[from] DatabaseError),
+
+    #[error(transparent)]
+    TwitchError(#[from] TwitchError),
+
+    #[error(transparent)]
+    WsSendError(#[from] mpsc::error::SendError<Message>),
+}
+
+/// The `[moderation]` table of config.toml. Every filter is off until it's configured
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ModerationConfig {
+    /// matched as whole words, ignoring case
+    pub banned_words: Vec<String>,
+    /// regexes, e.g. `(?i)free\s+followers`
+    pub banned_patterns: Vec<String>,
+    /// links from anyone without a `!permit`
+    pub block_links: bool,
+    /// links in someone's very first message in the channel
+    pub block_first_time_links: bool,
+    /// subdomains are allowed too, `twitch.tv` covers `clips.twitch.tv`
+    pub allowed_domains: Vec<String>,
+    /// how long a `!permit` lasts
+    pub permit_seconds: u64,
+    /// caps and symbols are only checked in messages with at least this many characters
+    pub min_length: usize,
+    pub max_caps_percent: Option<u8>,
+    pub max_symbols_percent: Option<u8>,
+    pub max_emotes: Option<usize>,
+    /// the same message this many times...
+    pub max_repeats: Option<usize>,
+    /// ...within this long
+    pub repeat_window_seconds: u64,
+    /// mods and the broadcaster are always exempt
+    pub exempt_vips: bool,
+    pub exempt_subscribers: bool,
+    /// strikes older than this are forgotten
+    pub strike_expiry_hours: u32,
+    /// what each strike gets, the last one repeats for any after it
+    pub punishments: Vec<Punishment>,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            banned_words: Vec::new(),
+            banned_patterns: Vec::new(),
+            block_links: false,
+            block_first_time_links: false,
+            allowed_domains: Vec::new(),
+            permit_seconds: 60,
+            min_length: 15,
+            max_caps_percent: None,
+            max_symbols_percent: None,
+            max_emotes: None,
+            max_repeats: None,
+            repeat_window_seconds: 60,
+            exempt_vips: true,
+            exempt_subscribers: false,
+            strike_expiry_hours: 24,
+            punishments: vec![
+                Punishment::Delete,
+                Punishment::Timeout { seconds: 60 },
+                Punishment::Timeout { seconds: 600 },
+            ],
+        }
+    }
+}
+
+/// e.g. `{ action = "timeout", seconds = 600 }`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Punishment {
+    Delete,
+    Timeout { seconds: u32 },
+    Ban,
+}
+
+impl Display for Punishment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Punishment::Delete => write!(f, "message deleted"),
+            Punishment::Timeout { seconds } => write!(f, "timed out for {seconds}s"),
+            Punishment::Ban => write!(f, "banned"),
+        }
+    }
+}
+
+/// Which filter a message broke
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    BannedWord,
+    BannedPattern,
+    Link,
+    FirstTimeLink,
+    Repeated,
+    Caps,
+    Symbols,
+    Emotes,
+}
+
+impl Filter {
+    /// As stored with the strike
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Filter::BannedWord => "banned_word",
+            Filter::BannedPattern => "banned_pattern",
+            Filter::Link => "link",
+            Filter::FirstTimeLink => "first_time_link",
+            Filter::Repeated => "repeated",
+            Filter::Caps => "caps",
+            Filter::Symbols => "symbols",
+            Filter::Emotes => "emotes",
+        }
+    }
+
+    /// Told to the chatter, and given to twitch as the timeout reason
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Filter::BannedWord | Filter::BannedPattern => "that isn't allowed here",
+            Filter::Link => "no links without a permit",
+            Filter::FirstTimeLink => "no links in your first message",
+            Filter::Repeated => "stop repeating yourself",
+            Filter::Caps => "too many caps",
+            Filter::Symbols => "too many symbols",
+            Filter::Emotes => "too many emotes",
+        }
+    }
+}
+
+/// Logins allowed to post a link, until when. Cloning shares the same permits
+#[derive(Debug, Clone, Default)]
+pub struct Permits(Arc<Mutex<HashMap<String, Instant>>>);
+
+impl Permits {
+    pub fn grant(&self, login: &str, duration: Duration) {
+        self.0.lock().expect("permits lock").insert(
+            login.trim_start_matches('@').to_lowercase(),
+            Instant::now() + duration,
+        );
+    }
+
+    /// Uses up `login`'s permit, `false` if they don't have one
+    fn take(&self, login: &str) -> bool {
+        let mut permits = self.0.lock().expect("permits lock");
+
+        permits.retain(|_, until| *until > Instant::now());
+        permits.remove(login).is_some()
+    }
+}
+
+/// The configured filters, ready to check messages with
+#[derive(Debug)]
+pub struct ChatFilters {
+    pub config: ModerationConfig,
+    banned_words: Option<Regex>,
+    banned_patterns: Vec<Regex>,
+    pub permits: Permits,
+}
+
+impl ChatFilters {
+    pub fn new(config: ModerationConfig) -> Result<Self, ModerationError> {
+        let banned_words = if config.banned_words.is_empty() {
+            None
+        } else {
+            let words = config
+                .banned_words
+                .iter()
+                .map(|word| regex::escape(word))
+                .collect::<Vec<_>>();
+
+            Some(Regex::new(&format!(r"(?i)\b(?:{})\b", words.join("|")))?)
+        };
+
+        let banned_patterns = config
+            .banned_patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            config,
+            banned_words,
+            banned_patterns,
+            permits: Permits::default(),
+        })
+    }
+
+    /// The first filter `message` breaks, if any. `chat_message` is the same
+    /// message with its emotes found
+    pub fn check(
+        &self,
+        message: &TwitchIrcMessage,
+        chat_message: &ChatMessage,
+        repeats: &mut RepeatTracker,
+    ) -> Option<Filter> {
+        if self.is_exempt(message) {
+            return None;
+        }
+
+        let config = &self.config;
+        let text = &chat_message.message;
+
+        let repeated = repeats.record(
+            &chat_message.user_id,
+            text,
+            Duration::from_secs(config.repeat_window_seconds),
+        );
+
+        if self
+            .banned_words
+            .as_ref()
+            .is_some_and(|words| words.is_match(text))
+        {
+            return Some(Filter::BannedWord);
+        }
+
+        if self.banned_patterns.iter().any(|re| re.is_match(text)) {
+            return Some(Filter::BannedPattern);
+        }
+
+        let first_message = message.tags.get("first-msg").is_some_and(|f| f == "1");
+
+        if (config.block_links || (config.block_first_time_links && first_message))
+            && link_domains(text).any(|domain| !self.is_allowed_domain(&domain))
+            && !self.permits.take(&message.login)
+        {
+            return Some(if config.block_links {
+                Filter::Link
+            } else {
+                Filter::FirstTimeLink
+            });
+        }
+
+        if config.max_repeats.is_some_and(|max| repeated >= max) {
+            return Some(Filter::Repeated);
+        }
+
+        // emotes like `KEKW` would count as caps
+        let words = without_emotes(chat_message);
+        let chars = words.chars().filter(|c| !c.is_whitespace());
+
+        if chars.clone().count() >= config.min_length {
+            let letters = chars.clone().filter(|c| c.is_alphabetic()).count();
+            let caps = chars.clone().filter(|c| c.is_uppercase()).count();
+            let symbols = chars.clone().filter(|c| !c.is_alphanumeric()).count();
+            let total = chars.count();
+
+            if config
+                .max_caps_percent
+                .is_some_and(|max| letters > 0 && caps * 100 > letters * max as usize)
+            {
+                return Some(Filter::Caps);
+            }
+
+            if config
+                .max_symbols_percent
+                .is_some_and(|max| symbols * 100 > total * max as usize)
+            {
+                return Some(Filter::Symbols);
+            }
+        }
+
+        if config
+            .max_emotes
+            .is_some_and(|max| chat_message.emotes.len() > max)
+        {
+            return Some(Filter::Emotes);
+        }
+
+        None
+    }
+
+    /// What someone gets for their `strikes`th strike
+    pub fn punishment(&self, strikes: u32) -> Punishment {
+        let punishments = &self.config.punishments;
+
+        let Some(last) = punishments.last() else {
+            return Punishment::Delete;
+        };
+
+        punishments
+            .get((strikes as usize).saturating_sub(1))
+            .copied()
+            .unwrap_or(*last)
+    }
+
+    fn is_exempt(&self, message: &TwitchIrcMessage) -> bool {
+        let tag = |name: &str| message.tags.get(name).is_some_and(|value| value == "1");
+
+        message.tags.is_mod().unwrap_or_default()
+            || message.tags.is_broadcaster().unwrap_or_default()
+            || (self.config.exempt_vips && tag("vip"))
+            || (self.config.exempt_subscribers && tag("subscriber"))
+    }
+
+    fn is_allowed_domain(&self, domain: &str) -> bool {
+        self.config.allowed_domains.iter().any(|allowed| {
+            let allowed = allowed.to_lowercase();
+            domain == allowed || domain.ends_with(&format!(".{allowed}"))
+        })
+    }
+}
+
+/// Recent messages from each chatter, to spot someone repeating themselves
+#[derive(Debug, Default)]
+pub struct RepeatTracker {
+    /// when each chatter sent what, oldest first, by user id
+    messages: HashMap<String, VecDeque<(Instant, String)>>,
+}
+
+impl RepeatTracker {
+    /// Records a message, returns how many times it's been sent within `window`,
+    /// this one included
+    fn record(&mut self, user_id: &str, message: &str, window: Duration) -> usize {
+        let now = Instant::now();
+
+        self.messages.retain(|_, messages| {
+            while messages
+                .front()
+                .is_some_and(|(sent, _)| now - *sent > window)
+            {
+                messages.pop_front();
+            }
+            !messages.is_empty()
+        });
+
+        // chatterino and 7TV tack on an invisible character to get past
+        // twitch's own duplicate check
+        let message = message
+            .trim_end_matches(|c: char| c.is_whitespace() || c == '\u{E0000}')
+            .to_lowercase();
+
+        let messages = self.messages.entry(user_id.to_string()).or_default();
+        messages.push_back((now, message.clone()));
+
+        messages.iter().filter(|(_, sent)| *sent == message).count()
+    }
+}
+
+/// Deals out the strike for breaking `filter`, and tells the chatter why
+pub async fn enforce(
+    filters: Arc<ChatFilters>,
+    filter: Filter,
+    message: TwitchIrcMessage,
+    chat_message: ChatMessage,
+    db: Db,
+    helix: HelixClient,
+    irc_sender: mpsc::Sender<Message>,
+) -> Result<(), ModerationError> {
+    let since = Utc::now() - chrono::Duration::hours(filters.config.strike_expiry_hours.into());
+
+    let strikes = db
+        .add_strike(
+            NewStrike {
+                user_id: chat_message.user_id.clone(),
+                login: message.login.clone(),
+                filter: filter.as_str().to_string(),
+                message: chat_message.message.clone(),
+            },
+            since,
+        )
+        .await?;
+
+    let punishment = filters.punishment(strikes);
+
+    tracing::info!(
+        "{} broke the {} filter, strike {strikes}: {punishment}",
+        message.login,
+        filter.as_str()
+    );
+
+    match punishment {
+        Punishment::Delete => {
+            helix.delete_chat_message(&chat_message.id).await?;
+        }
+        Punishment::Timeout { seconds } => {
+            helix
+                .timeout_user(&chat_message.user_id, seconds, filter.reason())
+                .await?;
+        }
+        Punishment::Ban => {
+            helix
+                .ban_user(&chat_message.user_id, filter.reason())
+                .await?;
+        }
+    }
+
+    irc_sender
+        .send(Message::Text(
+            to_irc_message(format!(
+                "@{} {} (strike {strikes}, {punishment})",
+                chat_message.display_name,
+                filter.reason()
+            ))
+            .into(),
+        ))
+        .await?;
+
+    Ok(())
+}
+
+/// Domains of whatever looks like a link, lowercased and without `www.`
+fn link_domains(message: &str) -> impl Iterator<Item = String> + '_ {
+    message.split_whitespace().filter_map(|word| {
+        let word = word.trim_matches(|c: char| matches!(c, '(' | ')' | '<' | '>' | '"' | '\''));
+        let word = word.split_once("://").map(|(_, rest)| rest).unwrap_or(word);
+
+        let host = word
+            .split(['/', '?', '#', ':'])
+            .next()?
+            .trim_end_matches(['.', ',', '!'])
+            .to_lowercase();
+
+        let (_, tld) = host.rsplit_once('.')?;
+
+        // `e.g.` or `...` aren't links, `youtube.com` and `bit.ly` are
+        let is_domain = tld.len() >= 2
+            && tld.chars().all(|c| c.is_ascii_alphabetic())
+            && host.split('.').all(|label| {
+                !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+            });
+
+        is_domain.then(|| host.trim_start_matches("www.").to_string())
+    })
+}
+
+/// The message with its emotes taken out
+fn without_emotes(chat_message: &ChatMessage) -> String {
+    chat_message
+        .message
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| {
+            !chat_message
+                .emotes
+                .iter()
+                .any(|emote| (emote.start..=emote.end).contains(i))
+        })
+        .map(|(_, c)| c)
+        .collect()
+}
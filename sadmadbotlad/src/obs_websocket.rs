@@ -1,25 +1,25 @@
 use std::{sync::Arc, time::Duration};
 
+use anyhow::Context;
 use futures_util::{StreamExt, pin_mut};
 use obws::{Client, events::Event};
-use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 use crate::{
     ApiInfo,
     // event_handler::{Event as EventHandler, IrcChat, IrcEvent},
-    twitch::{TwitchTokenMessages, run_ads},
+    helix::HelixClient,
 };
 
 pub async fn obs_websocket(
     // e_sender: UnboundedSender<EventHandler>,
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
+    helix: HelixClient,
     api_info: Arc<ApiInfo>,
+    shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
-    let Ok(client) = Client::connect("localhost", 4455, Some(&api_info.obs_server_password)).await
-    else {
-        tracing::error!("Could not connect to obs websocket");
-        return Ok(());
-    };
+    let client = Client::connect("localhost", 4455, Some(&api_info.obs_server_password))
+        .await
+        .context("could not connect to obs websocket")?;
 
     if let Err(e) = refresh_alert_box(&client).await {
         tracing::error!("{e}");
@@ -28,7 +28,13 @@ pub async fn obs_websocket(
     let events = client.events()?;
     pin_mut!(events);
 
-    while let Some(event) = events.next().await {
+    loop {
+        let event = tokio::select! {
+            Some(event) = events.next() => event,
+            _ = shutdown.cancelled() => return Ok(()),
+            else => break,
+        };
+
         if let Event::CurrentProgramSceneChanged { id } = event {
             if let Err(e) = refresh_alert_box(&client).await {
                 tracing::error!("{e}");
@@ -38,10 +44,10 @@ pub async fn obs_websocket(
                 continue;
             }
 
-            match run_ads(token_sender.clone()).await {
-                Ok(retry) => {
-                    tracing::info!("retry after {} seconds", retry);
-                    tracing::info!("Starting a 90 seconds commercial break");
+            match helix.run_ads().await {
+                Ok(Some(commercial)) => {
+                    tracing::info!("retry after {} seconds", commercial.retry_after);
+                    tracing::info!("Starting a {} seconds commercial break", commercial.length);
 
                     // TODO: send this to IRC
                     // ws_sender
@@ -50,18 +56,13 @@ pub async fn obs_websocket(
                     //     )))
                     //     .await?;
                 }
-                Err(e) => {
-                    if e.to_string().contains("too many requests") {
-                        tracing::error!("{e:?}");
-                    } else {
-                        panic!("{e}");
-                    }
-                }
+                Ok(None) => tracing::error!("Twitch didn't start a commercial break"),
+                Err(e) => tracing::error!("Failed to start commercial break: {e:?}"),
             }
         }
     }
 
-    Ok(())
+    Err(anyhow::anyhow!("obs websocket closed"))
 }
 
 async fn refresh_alert_box(client: &Client) -> anyhow::Result<()> {
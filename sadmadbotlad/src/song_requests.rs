@@ -2,11 +2,14 @@ use std::sync::Arc;
 
 use libmpv::events::{Event, PropertyData};
 use libmpv::{FileState, Mpv};
-use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedSender};
 use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
 
-use crate::{ApiInfo, youtube};
+use sadmadbotlad_protocol::{QUEUE_SIZE, QueueFull};
+pub use sadmadbotlad_protocol::{Queue, SongRequest};
+
+use crate::{ApiInfo, db::Db, youtube};
 use html_escape::decode_html_entities;
 
 #[derive(thiserror::Error, Debug)]
@@ -33,70 +36,30 @@ pub enum QueueMessages {
     Sr((String, String), oneshot::Sender<String>),
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct SongRequest {
-    pub title: String,
-    pub user: String,
-    pub url: String,
-    pub id: String,
-}
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct Queue {
-    pub queue: [Option<SongRequest>; 20],
-    pub rear: usize,
-}
-
-impl Queue {
-    pub fn enqueue(&mut self, item: &SongRequest) -> anyhow::Result<()> {
-        if self.rear >= 20 {
-            return Err(anyhow::anyhow!("queue is full"));
-        }
-
-        self.queue[self.rear] = Some(item.clone());
-
-        self.rear += 1;
-
-        Ok(())
-    }
-
-    pub fn dequeue(&mut self) {
-        for i in 0..self.rear {
-            self.queue[i] = self.queue[i + 1].take();
-        }
-
-        self.rear = self.rear.saturating_sub(1);
-    }
-
-    pub fn current_song(&self) -> Option<SongRequest> {
-        self.queue[0].clone()
+/// Looks up the requested song, sends it to the player and adds it to the queue
+async fn request_song(
+    queue: &mut Queue,
+    sender: &str,
+    song: &str,
+    song_sender: Sender<SongRequest>,
+    api_info: Arc<ApiInfo>,
+) -> anyhow::Result<SongRequest> {
+    // no point looking the video up if it can't be queued
+    if queue.rear >= QUEUE_SIZE {
+        return Err(QueueFull.into());
     }
 
-    pub async fn sr(
-        &mut self,
-        sender: &str,
-        song: &str,
-        song_sender: Sender<SongRequest>,
-        api_info: Arc<ApiInfo>,
-    ) -> anyhow::Result<String> {
+    let song = if !song.starts_with("https://") {
         // request is a video title
-        if !song.starts_with("https://") {
-            let video_info = youtube::video_info(song, api_info).await?;
-
-            let song = SongRequest {
-                title: video_info.title,
-                user: sender.into(),
-                url: format!("https://www.youtube.com/watch/{}", video_info.id),
-                id: video_info.id,
-            };
-
-            song_sender.send(song.clone()).await.expect("send song");
-
-            self.enqueue(&song).expect("Enqueuing");
+        let video_info = youtube::video_info(song, api_info).await?;
 
-            return Ok(format!("Added: {}", song.title));
+        SongRequest {
+            title: video_info.title,
+            user: sender.into(),
+            url: format!("https://www.youtube.com/watch/{}", video_info.id),
+            id: video_info.id,
         }
-
+    } else {
         // request is a URL
         let video_id = youtube::video_id_from_url(song)?;
 
@@ -104,19 +67,20 @@ impl Queue {
 
         let video_title = decode_html_entities(&video_title).to_string();
 
-        let song = SongRequest {
-            title: video_title.clone(),
+        SongRequest {
+            title: video_title,
             user: sender.into(),
             url: format!("https://youtube.com/watch/{}", video_id),
             id: video_id.to_string(),
-        };
+        }
+    };
 
-        song_sender.send(song.clone()).await.expect("send song");
+    // only a queued song reaches the player
+    queue.enqueue(&song)?;
 
-        self.enqueue(&song).expect("Enqueuing");
+    song_sender.send(song.clone()).await.expect("send song");
 
-        Ok(format!("Added: {}", video_title))
-    }
+    Ok(song)
 }
 
 pub struct SrQueue {
@@ -124,27 +88,33 @@ pub struct SrQueue {
     api_info: Arc<ApiInfo>,
     song_sender: Sender<SongRequest>,
     receiver: mpsc::UnboundedReceiver<QueueMessages>,
+    db: Db,
 }
 
 impl SrQueue {
+    /// `queue` is the one saved on the last shutdown, its songs are handed to the player again
     pub fn new(
         api_info: Arc<ApiInfo>,
         song_sender: Sender<SongRequest>,
         receiver: mpsc::UnboundedReceiver<QueueMessages>,
+        db: Db,
+        queue: Queue,
     ) -> Self {
+        for song in queue.queue.iter().flatten() {
+            song_sender.try_send(song.clone()).expect("send saved song");
+        }
+
         Self {
             api_info,
             song_sender,
             receiver,
-            queue: Queue {
-                queue: Default::default(),
-                rear: 0,
-            },
+            db,
+            queue,
         }
     }
 
     pub fn enqueue(&mut self, item: &SongRequest) -> anyhow::Result<()> {
-        self.queue.enqueue(item)
+        Ok(self.queue.enqueue(item)?)
     }
 
     pub fn dequeue(&mut self) {
@@ -158,11 +128,25 @@ impl SrQueue {
         song_sender: Sender<SongRequest>,
         api_info: Arc<ApiInfo>,
     ) -> anyhow::Result<String> {
-        self.queue.sr(sender, song, song_sender, api_info).await
+        let song = request_song(&mut self.queue, sender, song, song_sender, api_info).await?;
+
+        let message = format!("Added: {}", song.title);
+
+        if let Err(e) = self.db.new_song_request(song).await {
+            tracing::error!("failed to record song request: {e}");
+        }
+
+        Ok(message)
     }
 
-    pub async fn handle_messages(mut self) -> anyhow::Result<()> {
-        while let Some(message) = self.receiver.recv().await {
+    /// Handles queue messages until shutdown, then saves the queue
+    pub async fn handle_messages(&mut self, shutdown: CancellationToken) -> anyhow::Result<()> {
+        loop {
+            let message = tokio::select! {
+                Some(message) = self.receiver.recv() => message,
+                _ = shutdown.cancelled() => break,
+            };
+
             match message {
                 QueueMessages::GetQueue(one_shot_sender) => {
                     one_shot_sender
@@ -206,6 +190,11 @@ impl SrQueue {
                 }
             }
         }
+
+        tracing::info!("Saving song queue");
+
+        self.db.save_queue(self.queue.clone()).await?;
+
         Ok(())
     }
 }
@@ -238,6 +227,9 @@ pub fn play_song(
         .observe_property("idle-active", libmpv::Format::Flag, 0)
         .expect("observe property");
 
+    // mpv starts out idle, there's no finished song to take off the queue yet
+    let mut playing = false;
+
     loop {
         let ev = event_ctx
             .wait_event(600.)
@@ -249,15 +241,22 @@ pub fn play_song(
                 change: PropertyData::Flag(true),
                 ..
             }) => {
-                queue_sender.send(QueueMessages::Dequeue)?;
+                if playing {
+                    queue_sender.send(QueueMessages::Dequeue)?;
+                }
 
-                if let Some(song) = song_receiver.blocking_recv() {
-                    tracing::info!("song: {song:#?}");
+                let Some(song) = song_receiver.blocking_recv() else {
+                    return Ok(());
+                };
 
-                    mpv.playlist_load_files(&[(&song.url, FileState::AppendPlay, None)])
-                        .expect("play song");
-                }
+                tracing::info!("song: {song:#?}");
+
+                mpv.playlist_load_files(&[(&song.url, FileState::AppendPlay, None)])
+                    .expect("play song");
+
+                playing = true;
             }
+            Ok(Event::Shutdown) => return Ok(()),
             Err(libmpv::Error::Raw(e)) => {
                 tracing::error!("Mpv Error:: {e}");
                 queue_sender.send(QueueMessages::Dequeue)?;
@@ -1,54 +1,66 @@
-use futures_util::SinkExt;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
-use tokio::{
-    net::{TcpListener, TcpStream},
-    sync::{mpsc, oneshot},
+use axum::{
+    extract::{
+        ConnectInfo, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
 };
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use futures_util::{SinkExt, StreamExt};
+use sadmadbotlad_protocol::{ClientMessage, ErrorCode, ServerMessage};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, oneshot};
 
-use crate::{APP, song_requests::QueueMessages};
+use crate::{WsAuth, song_requests::QueueMessages};
 
-pub async fn sr_ws_server(
-    queue_sender: mpsc::UnboundedSender<QueueMessages>,
-) -> anyhow::Result<()> {
-    tracing::info!("Starting Sr WebSocket Server on port {}", APP.config.port);
-
-    let ip_address = Ipv4Addr::new(127, 0, 0, 1);
-    let address = SocketAddrV4::new(ip_address, APP.config.port);
-    let listener = TcpListener::bind(address).await?;
-
-    loop {
-        match listener.accept().await {
-            Ok((stream, _)) => {
-                let peer = stream.peer_addr()?;
+/// How long a client has to send its token before it gets disconnected
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
 
-                tracing::debug!("Songs Peer address: {}", peer);
+/// Everything an `/ws/songs` connection needs
+#[derive(Clone)]
+pub struct SongsWsState {
+    pub queue_sender: mpsc::UnboundedSender<QueueMessages>,
+    pub ws_auth: Arc<WsAuth>,
+}
 
-                let queue_sender = queue_sender.clone();
+pub async fn songs_ws(
+    ws: WebSocketUpgrade,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    State(state): State<SongsWsState>,
+) -> Response {
+    tracing::debug!("Songs Peer address: {}", peer);
 
-                tokio::spawn(async move {
-                    if let Err(e) = handle_connection(queue_sender, peer, stream).await {
-                        tracing::error!("Error processing connection: {}", e)
-                    }
-                });
-            }
-            Err(e) => return Err(anyhow::anyhow!("Failed to accept client: {e}")),
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = handle_connection(state, peer, socket).await {
+            tracing::error!("Error processing connection: {}", e)
         }
-    }
+    })
 }
 
 async fn handle_connection(
-    queue_sender: mpsc::UnboundedSender<QueueMessages>,
+    SongsWsState {
+        queue_sender,
+        ws_auth,
+    }: SongsWsState,
     peer: SocketAddr,
-    stream: TcpStream,
+    mut ws_stream: WebSocket,
 ) -> anyhow::Result<()> {
-    let mut ws_stream = accept_async(stream).await.expect("Failed to accept");
+    if ws_auth.initial_capability(&peer).is_none() && !authenticate(&mut ws_stream, &ws_auth).await
+    {
+        tracing::info!("Peer {peer} failed to authenticate");
+
+        let error = serde_json::to_string(&ServerMessage::error(
+            ErrorCode::Unauthorized,
+            "missing or unknown token",
+        ))?;
+        ws_stream.send(Message::Text(error.into())).await?;
+        ws_stream.close().await?;
+
+        return Ok(());
+    }
 
     let (send, recv) = oneshot::channel();
 
-    queue_sender
-        .send(QueueMessages::GetQueue(send))
-        .expect("request songs");
+    queue_sender.send(QueueMessages::GetQueue(send))?;
 
     let Ok(queue) = recv.await else {
         return Err(anyhow::anyhow!("Could not get queue"));
@@ -56,9 +68,7 @@ async fn handle_connection(
 
     tracing::info!("Sending Queue to Peer {peer}");
 
-    let Ok(queue) = serde_json::to_string(&queue) else {
-        panic!("Could not parse queue to string");
-    };
+    let queue = serde_json::to_string(&queue)?;
 
     if let Err(e) = ws_stream.send(Message::Text(queue.into())).await {
         tracing::error!("WebSocket server:: {e}");
@@ -67,3 +77,21 @@ async fn handle_connection(
 
     Ok(())
 }
+
+/// Waits for the client's `auth` message, any valid token can read the queue
+async fn authenticate(ws_stream: &mut WebSocket, ws_auth: &WsAuth) -> bool {
+    let auth = tokio::time::timeout(AUTH_TIMEOUT, async {
+        while let Some(Ok(msg)) = ws_stream.next().await {
+            if let Message::Text(msg) = msg {
+                return serde_json::from_str::<ClientMessage>(msg.as_str()).ok();
+            }
+        }
+
+        None
+    });
+
+    match auth.await {
+        Ok(Some(ClientMessage::Auth { token })) => ws_auth.capability(&token).is_some(),
+        _ => false,
+    }
+}
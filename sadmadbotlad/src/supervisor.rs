@@ -0,0 +1,201 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::{task::JoinSet, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Delay before the first restart, doubled after every consecutive failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// A task that stayed up this long before failing starts its backoff over
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Consecutive failures before a task is given up on
+const MAX_RESTARTS: u32 = 10;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum TaskStatus {
+    Running {
+        since: DateTime<Utc>,
+        restarts: u32,
+    },
+    Restarting {
+        attempt: u32,
+        last_error: String,
+        retry_at: DateTime<Utc>,
+    },
+    Failed {
+        last_error: String,
+    },
+    Finished,
+}
+
+impl std::fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskStatus::Running { restarts: 0, .. } => write!(f, "running"),
+            TaskStatus::Running { restarts, .. } => write!(f, "running ({restarts} restarts)"),
+            TaskStatus::Restarting {
+                attempt,
+                last_error,
+                ..
+            } => write!(f, "restarting (attempt {attempt}: {last_error})"),
+            TaskStatus::Failed { last_error } => write!(f, "failed ({last_error})"),
+            TaskStatus::Finished => write!(f, "finished"),
+        }
+    }
+}
+
+/// Status of every supervised task, shared with `/health` and `!status`
+#[derive(Debug, Clone, Default)]
+pub struct Health(Arc<RwLock<BTreeMap<&'static str, TaskStatus>>>);
+
+impl Health {
+    fn set(&self, name: &'static str, status: TaskStatus) {
+        self.0.write().expect("health lock").insert(name, status);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, TaskStatus> {
+        self.0.read().expect("health lock").clone()
+    }
+
+    pub fn any_failed(&self) -> bool {
+        self.0
+            .read()
+            .expect("health lock")
+            .values()
+            .any(|status| matches!(status, TaskStatus::Failed { .. }))
+    }
+
+    /// One line per chat message, e.g. `irc: running | obs: restarting (attempt 2: ...)`
+    pub fn summary(&self) -> String {
+        self.snapshot()
+            .iter()
+            .map(|(name, status)| format!("{name}: {status}"))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+}
+
+/// Runs tasks and restarts them with exponential backoff when they fail or panic,
+/// nothing is restarted once `shutdown` is cancelled
+pub struct Supervisor {
+    health: Health,
+    shutdown: CancellationToken,
+    tasks: JoinSet<()>,
+}
+
+impl Supervisor {
+    pub fn new(health: Health, shutdown: CancellationToken) -> Self {
+        Self {
+            health,
+            shutdown,
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// `task` is called again for every restart, a task that returns `Ok` is not restarted
+    pub fn spawn<F, Fut>(&mut self, name: &'static str, mut task: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let health = self.health.clone();
+        let shutdown = self.shutdown.clone();
+
+        self.tasks.spawn(async move {
+            let mut failures = 0;
+            let mut restarts = 0;
+
+            loop {
+                let started = Instant::now();
+
+                health.set(
+                    name,
+                    TaskStatus::Running {
+                        since: Utc::now(),
+                        restarts,
+                    },
+                );
+
+                // spawned on its own so a panic is just another failure
+                let last_error = match tokio::spawn(task()).await {
+                    Ok(Ok(())) => {
+                        tracing::info!("{name} finished");
+                        health.set(name, TaskStatus::Finished);
+                        return;
+                    }
+                    Ok(Err(e)) => format!("{e:#}"),
+                    Err(e) => e.to_string(),
+                };
+
+                if shutdown.is_cancelled() {
+                    tracing::error!("{name} failed while shutting down: {last_error}");
+                    health.set(name, TaskStatus::Failed { last_error });
+                    return;
+                }
+
+                if started.elapsed() >= STABLE_AFTER {
+                    failures = 0;
+                }
+
+                failures += 1;
+
+                if failures > MAX_RESTARTS {
+                    tracing::error!(
+                        "{name} failed {MAX_RESTARTS} times in a row, giving up: {last_error}"
+                    );
+                    health.set(name, TaskStatus::Failed { last_error });
+                    return;
+                }
+
+                let backoff = backoff(failures);
+
+                tracing::error!(
+                    "{name} failed, restarting in {}s: {last_error}",
+                    backoff.as_secs()
+                );
+
+                health.set(
+                    name,
+                    TaskStatus::Restarting {
+                        attempt: failures,
+                        last_error,
+                        retry_at: Utc::now()
+                            + chrono::Duration::from_std(backoff).expect("backoff fits"),
+                    },
+                );
+
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = shutdown.cancelled() => {
+                        health.set(name, TaskStatus::Finished);
+                        return;
+                    }
+                }
+
+                restarts += 1;
+            }
+        });
+    }
+
+    /// Waits until every task has finished or been given up on
+    pub async fn wait(mut self) {
+        while self.tasks.join_next().await.is_some() {}
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
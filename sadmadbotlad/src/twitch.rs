@@ -1,18 +1,65 @@
-use chrono::{Duration, Utc};
-use futures_util::StreamExt;
-use reqwest::{Client, StatusCode};
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Query, State},
+    response::Redirect,
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::{Rng, distributions::Alphanumeric};
+use reqwest::{Client, Response, StatusCode, Url};
 use serde::{Deserialize, Serialize};
-use serde_json::{Value, json};
+use serde_json::json;
 use tokio::{
-    net::TcpListener,
-    sync::{mpsc, oneshot},
+    sync::{broadcast, mpsc, oneshot},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    APP,
+    db::{DatabaseError, Db},
 };
-use tokio_tungstenite::{accept_async, tungstenite};
 
-use crate::{APP, ApiInfo};
+const AUTHORIZE_URL: &str = "https://id.twitch.tv/oauth2/authorize";
+
+const DEVICE_URL: &str = "https://id.twitch.tv/oauth2/device";
+
+const TOKEN_URL: &str = "https://id.twitch.tv/oauth2/token";
+
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+const VALIDATE_URL: &str = "https://id.twitch.tv/oauth2/validate";
 
-// TODO: move AUTH_LINK to config file instead
-const AUTH_LINK: &'static str = include_str!("../auth_link.txt");
+/// Twitch wants tokens validated at least this often
+const VALIDATE_INTERVAL: Duration = Duration::hours(1);
+
+/// How long before it expires a token gets refreshed
+const REFRESH_MARGIN: Duration = Duration::minutes(5);
+
+/// How long to wait before trying again after a failed validation or refresh
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How long a browser login gets before it's given up on
+const BROWSER_LOGIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+const BOT_SCOPES: [&str; 2] = ["chat:read", "chat:edit"];
+
+const BROADCASTER_SCOPES: [&str; 14] = [
+    "moderator:read:followers",
+    "moderation:read",
+    "channel:manage:broadcast",
+    "channel:manage:redemptions",
+    "channel:edit:commercial",
+    "channel:read:subscriptions",
+    "channel:read:vips",
+    "bits:read",
+    "moderator:manage:banned_users",
+    "moderator:manage:chat_messages",
+    "moderator:manage:announcements",
+    "moderator:manage:shoutouts",
+    "moderator:manage:chat_settings",
+    "clips:edit",
+];
 
 #[derive(thiserror::Error, Debug)]
 pub enum TwitchError {
@@ -29,434 +76,819 @@ pub enum TwitchError {
         message: String,
     },
 
-    #[error("{0}")]
-    ReqwestError(#[from] reqwest::Error),
+    #[error("logged in to twitch as {got}, expected {expected}")]
+    WrongAccount { expected: String, got: String },
 
-    #[error("{0}")]
-    TomlDeError(#[from] toml::de::Error),
+    #[error("twitch login for {0} timed out")]
+    LoginTimedOut(String),
+
+    #[error("shut down while waiting for a twitch login")]
+    LoginCancelled,
 
     #[error("{0}")]
-    TomlSerError(#[from] toml::ser::Error),
+    ReqwestError(#[from] reqwest::Error),
 
     #[error("{0}")]
     IoError(#[from] std::io::Error),
 
+    #[error(transparent)]
+    DatabaseError(#[from] DatabaseError),
+
     #[error("{0}")]
     SendError(#[from] tokio::sync::mpsc::error::SendError<TwitchTokenMessages>),
 }
 
-#[allow(unused)]
-#[derive(Deserialize)]
-pub struct TwitchApiResponse {
-    pub data: Vec<TwitchChannelInfo>,
-    pagination: Option<Pagination>,
+/// Which account a token is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenRole {
+    /// logs in to chat, so everything the bot says comes from it
+    Bot,
+    /// makes the Helix calls only the channel's owner can
+    Broadcaster,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Pagination {
-    cursor: Option<String>,
+impl TokenRole {
+    const ALL: [TokenRole; 2] = [TokenRole::Bot, TokenRole::Broadcaster];
+
+    fn scopes(self) -> &'static [&'static str] {
+        match self {
+            TokenRole::Bot => &BOT_SCOPES,
+            TokenRole::Broadcaster => &BROADCASTER_SCOPES,
+        }
+    }
 }
 
-#[allow(unused)]
+/// What twitch answers a code exchange or a refresh with
 #[derive(Deserialize)]
-pub struct TwitchChannelInfo {
-    id: String,
-    pub user_id: String,
-    pub title: String,
-    pub game_name: String,
-    pub started_at: String,
-
-    user_login: String,
-    user_name: String,
-    r#type: String,
-    viewer_count: u32,
-    game_id: String,
-    language: String,
-    thumbnail_url: String,
-    tag_ids: Option<Vec<String>>,
-    is_mature: bool,
+struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct TwitchAd {
-    pub data: Vec<AdData>,
+/// What twitch knows about a token
+#[derive(Deserialize)]
+struct ValidateResponse {
+    login: String,
+    user_id: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+    /// seconds
+    expires_in: i64,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct AdData {
-    pub length: i32,
-    pub message: String,
-    pub retry_after: i64,
+/// What twitch answers the start of a device code login with
+#[derive(Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    /// seconds
+    expires_in: u64,
+    /// seconds between polls
+    interval: u64,
 }
 
-pub async fn set_title(
-    new_title: &str,
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-) -> Result<(), TwitchError> {
-    // request twitch patch change title
-
-    let http_client = Client::new();
-
-    let (one_shot_sender, one_shot_receiver) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(one_shot_sender))?;
-
-    let Ok(api_info) = one_shot_receiver.await else {
-        return Err(TwitchError::TokenError);
-    };
-
-    let res = http_client
-        .patch("https://api.twitch.tv/helix/channels?broadcaster_id=143306668")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "title": new_title,
-        }))
-        .send()
-        .await?;
-
-    if !res.status().is_success() {
-        return Err(TwitchError::TwitchApiError {
-            request_name: String::from("set_title"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        });
-    }
-
-    Ok(())
+/// Twitch's 400 body while polling for a device code token
+#[derive(Deserialize)]
+struct DeviceTokenError {
+    message: String,
 }
 
-pub async fn get_title(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-) -> Result<String, TwitchError> {
-    let http_client = Client::new();
-
-    let (one_shot_sender, one_shot_receiver) = oneshot::channel();
-
-    token_sender.send(TwitchTokenMessages::GetToken(one_shot_sender))?;
-
-    let Ok(api_info) = one_shot_receiver.await else {
-        return Err(TwitchError::TokenError);
-    };
-
-    let res = http_client
-        .get("https://api.twitch.tv/helix/channels?broadcaster_id=143306668")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .send()
-        .await?;
-
-    if !res.status().is_success() {
-        return Err(TwitchError::TwitchApiError {
-            request_name: String::from("get_title"),
-            status: res.status(),
-            message: res.text().await.expect("response text"),
-        });
-    }
-
-    let res = res.json::<Value>().await?;
-
-    Ok(res["data"][0]["title"].as_str().unwrap().to_string())
+fn authorize_url(api_info: &TwitchApiInfo, scopes: &[&str], state: &str) -> Url {
+    Url::parse_with_params(
+        AUTHORIZE_URL,
+        &[
+            ("response_type", "code"),
+            ("client_id", api_info.client_id.as_str()),
+            ("redirect_uri", &api_info.redirect_uri()),
+            ("scope", &scopes.join(" ")),
+            ("state", state),
+            // so the browser can switch between the bot's and the broadcaster's accounts
+            ("force_verify", "true"),
+        ],
+    )
+    .expect("authorize url")
 }
 
-pub async fn get_access_token_from_code(
+async fn get_access_token_from_code(
     code: &str,
-    api_info: &mut TwitchApiInfo,
-) -> anyhow::Result<()> {
+    api_info: &TwitchApiInfo,
+) -> Result<TokenResponse, TwitchError> {
     let http_client = Client::new();
 
     let res = http_client
-        .post("https://id.twitch.tv/oauth2/token")
+        .post(TOKEN_URL)
         .form(&json!({
             "client_id": api_info.client_id,
             "client_secret": api_info.client_secret,
             "code": code,
             "grant_type": "authorization_code",
-            "redirect_uri": "http://localhost:8080/code",
+            "redirect_uri": api_info.redirect_uri(),
         }))
         .send()
         .await?;
 
-    if !res.status().is_success() {
-        return Err(anyhow::anyhow!(
-            "get access token:: {}::{}",
-            res.status(),
-            res.text().await?
-        ));
-    }
+    let res = check_response("get_access_token_from_code", res).await?;
 
-    let res = res.json::<Value>().await?;
+    Ok(res.json::<TokenResponse>().await?)
+}
+
+/// Sends the browser to twitch's login page and waits for the code its redirect carries
+async fn browser_authorize(
+    api_info: &TwitchApiInfo,
+    login: &str,
+    scopes: &[&str],
+    state: &str,
+    code_receiver: oneshot::Receiver<AuthCode>,
+    shutdown: &CancellationToken,
+) -> Result<TokenResponse, TwitchError> {
+    let url = authorize_url(api_info, scopes, state);
+
+    tracing::info!("Log in to twitch as {login} to authorize the bot: {url}");
+
+    // the link is in the log for when there's no browser to open it in
+    if let Err(e) = open::that(url.as_str()) {
+        tracing::warn!("Could not open a browser: {e}");
+    }
 
     tracing::info!(
-        "twitch token scopes: {:#?}\r",
-        res["scope"].as_array().unwrap()
+        "Waiting for twitch to redirect to {}",
+        api_info.redirect_uri()
     );
 
-    api_info.twitch_refresh_token = res["refresh_token"].as_str().unwrap().to_string();
-    api_info.twitch_access_token = res["access_token"].as_str().unwrap().to_string();
+    let auth_code = tokio::select! {
+        auth_code = code_receiver => auth_code,
+        _ = tokio::time::sleep(BROWSER_LOGIN_TIMEOUT) => {
+            return Err(TwitchError::LoginTimedOut(login.to_string()));
+        }
+        _ = shutdown.cancelled() => return Err(TwitchError::LoginCancelled),
+    };
+
+    let Ok(auth_code) = auth_code else {
+        tracing::error!("twitch token actor is gone");
+        return Err(TwitchError::TokenError);
+    };
 
-    Ok(())
+    get_access_token_from_code(&auth_code.code, api_info).await
 }
 
-pub async fn get_user_id(
-    login_name: impl Into<String>,
+/// Has whoever runs the bot enter a code on twitch.tv/activate, polling
+/// until they do
+async fn device_authorize(
+    client: &Client,
     api_info: &TwitchApiInfo,
-) -> anyhow::Result<String> {
-    let http_client = reqwest::Client::new();
-
-    let res = http_client
-        .get(format!(
-            "https://api.twitch.tv/helix/users?login={}",
-            login_name.into()
-        ))
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
+    login: &str,
+    scopes: &[&str],
+    shutdown: &CancellationToken,
+) -> Result<TokenResponse, TwitchError> {
+    let scopes = scopes.join(" ");
+
+    let res = client
+        .post(DEVICE_URL)
+        .form(&[
+            ("client_id", api_info.client_id.as_str()),
+            ("scopes", scopes.as_str()),
+        ])
         .send()
-        .await?
-        .json::<Value>()
         .await?;
 
-    Ok(res["data"][0]["id"].as_str().expect("user id").to_string())
-}
-
-pub async fn is_vip(
-    login_name: impl Into<String>,
-    api_info: &TwitchApiInfo,
-) -> anyhow::Result<bool> {
-    let http_client = reqwest::Client::new();
-    let user_id = get_user_id(login_name, api_info).await?;
-
-    let res = http_client
-        .get(format!(
-            "https://api.twitch.tv/helix/channels/vips?broadcaster_id=143306668&user_id={}&first=1",
-            user_id
-        ))
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .send()
+    let device = check_response("device_code", res)
         .await?
-        .json::<Value>()
+        .json::<DeviceCodeResponse>()
         .await?;
 
-    Ok(match res["data"][0]["user_id"].as_str() {
-        Some(id) => user_id == id,
-        None => false,
-    })
-}
+    tracing::info!(
+        "Log in to twitch as {login} at {} and enter {} to authorize the bot",
+        device.verification_uri,
+        device.user_code
+    );
 
-pub async fn run_ads(
-    token_sender: mpsc::UnboundedSender<TwitchTokenMessages>,
-) -> anyhow::Result<i64> {
-    let http_client = Client::new();
+    let expires_at = Instant::now() + std::time::Duration::from_secs(device.expires_in);
+    let mut interval = std::time::Duration::from_secs(device.interval.max(1));
+
+    let poll = async {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let res = client
+                .post(TOKEN_URL)
+                .form(&[
+                    ("client_id", api_info.client_id.as_str()),
+                    ("scopes", scopes.as_str()),
+                    ("device_code", device.device_code.as_str()),
+                    ("grant_type", DEVICE_CODE_GRANT_TYPE),
+                ])
+                .send()
+                .await?;
 
-    let (one_shot_sender, one_shot_receiver) = oneshot::channel();
+            if res.status() != StatusCode::BAD_REQUEST {
+                let res = check_response("device_code_token", res).await?;
 
-    token_sender.send(TwitchTokenMessages::GetToken(one_shot_sender))?;
+                return Ok(res.json::<TokenResponse>().await?);
+            }
 
-    let Ok(api_info) = one_shot_receiver.await else {
-        return Err(anyhow::anyhow!("Failed to get token"));
+            let error = res.json::<DeviceTokenError>().await?;
+
+            match error.message.as_str() {
+                "authorization_pending" => {}
+                "slow_down" => interval += std::time::Duration::from_secs(5),
+                _ => {
+                    return Err(TwitchError::TwitchApiError {
+                        request_name: String::from("device_code_token"),
+                        status: StatusCode::BAD_REQUEST,
+                        message: error.message,
+                    });
+                }
+            }
+        }
     };
 
-    let res = http_client
-        .post("https://api.twitch.tv/helix/channels/commercial")
-        .bearer_auth(api_info.twitch_access_token.clone())
-        .header("Client-Id", api_info.client_id.clone())
-        .json(&json!({
-            "broadcaster_id": "143306668",
-            "length": "90",
-        }))
-        .send()
-        .await?;
+    tokio::select! {
+        result = poll => result,
+        _ = tokio::time::sleep_until(expires_at) => {
+            tracing::error!("twitch device code for {login} expired before it was entered");
+            Err(TwitchError::LoginTimedOut(login.to_string()))
+        }
+        _ = shutdown.cancelled() => Err(TwitchError::LoginCancelled),
+    }
+}
 
+async fn check_response(request_name: &str, res: Response) -> Result<Response, TwitchError> {
     if !res.status().is_success() {
-        return Err(anyhow::anyhow!(
-            "{} :: message: {}",
-            res.status(),
-            res.text().await?
-        ));
+        return Err(TwitchError::TwitchApiError {
+            request_name: request_name.to_string(),
+            status: res.status(),
+            message: res.text().await?,
+        });
     }
 
-    let res = res.json::<TwitchAd>().await?;
-
-    tracing::debug!("{:#?}", res);
-
-    Ok(res.data[0].retry_after)
+    Ok(res)
 }
 
-pub async fn access_token(api_info: &mut TwitchApiInfo) -> anyhow::Result<()> {
-    open::that(AUTH_LINK)?;
-
-    let port = 4040;
-
-    tracing::info!("Auth service running on port {port}");
-
-    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
-
-    let Ok((stream, _)) = listener.accept().await else {
-        return Err(anyhow::anyhow!("Code Websocket failed"));
-    };
-
-    let peer = stream
-        .peer_addr()
-        .expect("connected streams should have a peer address");
-
-    tracing::debug!("Peer address: {}", peer);
-
-    let mut ws = accept_async(stream).await?;
+/// What twitch's redirect to `/auth/callback` carries
+#[derive(Deserialize, Debug)]
+pub struct AuthCode {
+    code: String,
+    /// the one the login was started with, anything else is someone else's
+    state: String,
+}
 
-    if let Some(msg) = ws.next().await {
-        match msg {
-            Ok(tungstenite::Message::Text(code)) => {
-                get_access_token_from_code(&code, api_info).await?;
-                tracing::info!("received twitch auth token");
-            }
-            _ => tracing::error!("could not get code from frontend"),
-        }
+/// Twitch redirects here with the authorization code once the bot is approved
+pub async fn auth_callback(
+    State(code_sender): State<mpsc::UnboundedSender<AuthCode>>,
+    Query(auth_code): Query<AuthCode>,
+) -> Redirect {
+    if code_sender.send(auth_code).is_err() {
+        tracing::error!("nothing is waiting for a twitch auth code");
     }
 
-    Ok(())
+    Redirect::to("/activity")
+}
+
+/// How the bot gets a token for an account that has none
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthFlow {
+    /// twitch's login page, which redirects back to `/auth/callback`
+    #[default]
+    Browser,
+    /// a code to enter on twitch.tv/activate, for when the bot runs somewhere
+    /// without a browser
+    DeviceCode,
 }
 
-// TODO: no need to store all of these in this struct
-// make it ONLY for twitch token
+/// The twitch app, from config.toml
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TwitchApiInfo {
+    /// the bot's account
     pub user: String,
+    /// the channel's account, when it isn't the bot's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcaster: Option<String>,
     pub client_id: String,
     pub client_secret: String,
+    #[serde(default)]
+    pub auth_flow: AuthFlow,
+    /// where twitch sends the browser back to, has to be one of the app's
+    /// OAuth redirect URLs. `http://localhost:<frontend port>/auth/callback` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_uri: Option<String>,
+    /// only read to move the tokens of older configs into the database
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub twitch_access_token: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub twitch_refresh_token: String,
-    #[serde(default)]
-    pub expires_at: chrono::DateTime<Utc>,
 }
 
+impl TwitchApiInfo {
+    pub fn redirect_uri(&self) -> String {
+        self.redirect_uri.clone().unwrap_or_else(|| {
+            format!(
+                "http://localhost:{}/auth/callback",
+                APP.config.frontend_port
+            )
+        })
+    }
+
+    pub fn login(&self, role: TokenRole) -> &str {
+        match role {
+            TokenRole::Bot => &self.user,
+            TokenRole::Broadcaster => self.broadcaster.as_deref().unwrap_or(&self.user),
+        }
+    }
+}
+
+/// A user access token, as kept in the database
+#[derive(Debug, Clone)]
+pub struct UserToken {
+    pub login: String,
+    pub user_id: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub scopes: Vec<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct TokenSlot {
+    token: Option<UserToken>,
+    /// when twitch last said the token was good
+    validated_at: Option<DateTime<Utc>>,
+}
+
+/// Owns the bot's and the broadcaster's tokens, validating and refreshing
+/// them before anyone gets handed an expired one
 pub struct TwitchToken {
     receiver: mpsc::UnboundedReceiver<TwitchTokenMessages>,
+    code_receiver: mpsc::UnboundedReceiver<AuthCode>,
+    changed_sender: broadcast::Sender<(TokenRole, UserToken)>,
     client: reqwest::Client,
     api_info: TwitchApiInfo,
+    db: Db,
+    /// by lowercased login, so an account doing both jobs has a single token
+    slots: HashMap<String, TokenSlot>,
+    /// logins going through the auth flow in their own task, with whoever is
+    /// waiting on their token
+    authorizing: HashMap<String, Vec<oneshot::Sender<UserToken>>>,
+    /// browser logins waiting on twitch's redirect, by the state they were started with
+    auth_states: HashMap<String, oneshot::Sender<AuthCode>>,
+    auth_sender: mpsc::UnboundedSender<AuthResult>,
+    auth_receiver: mpsc::UnboundedReceiver<AuthResult>,
+    /// what `handle_messages` was started with, so auth flow tasks stop with it
+    shutdown: CancellationToken,
 }
 
+/// What an auth flow task reports back, for the login it was started for
+type AuthResult = (String, Result<TokenResponse, TwitchError>);
+
 #[derive(Debug)]
 pub enum TwitchTokenMessages {
-    GetToken(oneshot::Sender<TwitchApiInfo>),
+    GetToken(TokenRole, oneshot::Sender<UserToken>),
+    /// the token was rejected before it expired, get a new one regardless
+    RefreshToken(TokenRole, oneshot::Sender<UserToken>),
 }
 
 impl TwitchToken {
     pub fn new(
         api_info: TwitchApiInfo,
+        db: Db,
         receiver: mpsc::UnboundedReceiver<TwitchTokenMessages>,
+        code_receiver: mpsc::UnboundedReceiver<AuthCode>,
+        changed_sender: broadcast::Sender<(TokenRole, UserToken)>,
     ) -> Self {
         let client = reqwest::Client::new();
+        let (auth_sender, auth_receiver) = mpsc::unbounded_channel();
         Self {
             client,
             api_info,
+            db,
             receiver,
+            code_receiver,
+            changed_sender,
+            slots: HashMap::new(),
+            authorizing: HashMap::new(),
+            auth_states: HashMap::new(),
+            auth_sender,
+            auth_receiver,
+            shutdown: CancellationToken::new(),
         }
     }
 
-    pub async fn handle_messages(mut self) -> Result<(), TwitchError> {
-        while let Some(message) = self.receiver.recv().await {
+    pub async fn handle_messages(
+        &mut self,
+        shutdown: CancellationToken,
+    ) -> Result<(), TwitchError> {
+        self.shutdown = shutdown.clone();
+
+        for role in TokenRole::ALL {
+            let login = self.login(role);
+
+            if !self.slots.contains_key(&login) {
+                let token = self.load_token(&login).await?;
+
+                self.slots.insert(
+                    login,
+                    TokenSlot {
+                        token,
+                        validated_at: None,
+                    },
+                );
+            }
+        }
+
+        loop {
+            let next_check = self.next_check();
+
+            let message = tokio::select! {
+                Some(message) = self.receiver.recv() => message,
+                _ = tokio::time::sleep(next_check) => {
+                    // accounts without a token are authorized once something needs
+                    // them, instead of opening the browser on a timer
+                    let logins = self
+                        .slots
+                        .iter()
+                        .filter(|(login, slot)| {
+                            slot.token.is_some() && !self.authorizing.contains_key(*login)
+                        })
+                        .map(|(login, _)| login.clone())
+                        .collect::<Vec<_>>();
+
+                    for login in logins {
+                        if let Err(e) = self.check_token(&login).await {
+                            tracing::error!("Failed to update twitch token for {login}: {e:#?}");
+                        }
+                    }
+                    continue;
+                }
+                Some(auth_code) = self.code_receiver.recv() => {
+                    match self.auth_states.remove(&auth_code.state) {
+                        Some(code_sender) => {
+                            code_sender.send(auth_code).ok();
+                        }
+                        None => tracing::warn!("ignoring a twitch auth code from another login"),
+                    }
+                    continue;
+                }
+                Some((login, result)) = self.auth_receiver.recv() => {
+                    self.finish_authorize(&login, result).await;
+                    continue;
+                }
+                _ = shutdown.cancelled() => break,
+            };
+
             match message {
-                TwitchTokenMessages::GetToken(response) => {
-                    if let Err(e) = self.update_token().await {
-                        tracing::error!("Failed to update twitch token: {e:#?}");
+                TwitchTokenMessages::GetToken(role, response) => {
+                    let login = self.login(role);
+
+                    if let Err(e) = self.check_token(&login).await {
+                        tracing::error!("Failed to update twitch token for {login}: {e:#?}");
                     }
 
-                    response
-                        .send(self.api_info.clone())
-                        .expect("Could not send token");
+                    self.respond_or_wait(&login, response);
+                }
+                TwitchTokenMessages::RefreshToken(role, response) => {
+                    let login = self.login(role);
+
+                    if let Err(e) = self.renew_token(&login).await {
+                        tracing::error!("Failed to refresh twitch token for {login}: {e:#?}");
+                    }
+
+                    self.respond_or_wait(&login, response);
                 }
             }
         }
         Ok(())
     }
 
-    pub async fn update_token(&mut self) -> Result<(), TwitchError> {
+    fn login(&self, role: TokenRole) -> String {
+        self.api_info.login(role).to_lowercase()
+    }
+
+    /// Every scope `login` needs, for each of the roles it has
+    fn scopes(&self, login: &str) -> Vec<&'static str> {
+        TokenRole::ALL
+            .into_iter()
+            .filter(|role| self.login(*role) == login)
+            .flat_map(|role| role.scopes().iter().copied())
+            .collect()
+    }
+
+    fn slot(&mut self, login: &str) -> &mut TokenSlot {
+        self.slots.entry(login.to_string()).or_default()
+    }
+
+    /// Without a token the response is dropped, which callers see as a token error
+    fn respond(&self, login: &str, response: oneshot::Sender<UserToken>) {
+        if let Some(token) = self.slots.get(login).and_then(|slot| slot.token.as_ref()) {
+            response.send(token.clone()).ok();
+        }
+    }
+
+    /// Holds on to the response while `login` is in the auth flow, rather
+    /// than handing out the token it's replacing
+    fn respond_or_wait(&mut self, login: &str, response: oneshot::Sender<UserToken>) {
+        match self.authorizing.get_mut(login) {
+            Some(waiting) => waiting.push(response),
+            None => self.respond(login, response),
+        }
+    }
+
+    /// The stored token, or the one in config.toml from before tokens were
+    /// stored, which was always the bot's
+    async fn load_token(&self, login: &str) -> Result<Option<UserToken>, TwitchError> {
+        if let Some(token) = self.db.get_twitch_token(login.to_string()).await? {
+            return Ok(Some(token));
+        }
+
+        if login != self.login(TokenRole::Bot) || self.api_info.twitch_refresh_token.is_empty() {
+            return Ok(None);
+        }
+
+        tracing::info!("Moving the twitch token in config.toml to the database");
+
+        Ok(Some(UserToken {
+            login: login.to_string(),
+            user_id: String::new(),
+            access_token: self.api_info.twitch_access_token.clone(),
+            refresh_token: self.api_info.twitch_refresh_token.clone(),
+            scopes: Vec::new(),
+            // refreshed straight away, which also fills in the rest
+            expires_at: Utc::now(),
+        }))
+    }
+
+    /// How long until a token is due to be validated or refreshed
+    fn next_check(&self) -> std::time::Duration {
         let now = Utc::now();
 
-        if self.api_info.expires_at > now {
-            return Ok(());
+        let check_at =
+            self.slots
+                .values()
+                .filter_map(|slot| {
+                    let token = slot.token.as_ref()?;
+
+                    Some(match slot.validated_at {
+                        Some(validated_at) => (token.expires_at - REFRESH_MARGIN)
+                            .min(validated_at + VALIDATE_INTERVAL),
+                        None => now,
+                    })
+                })
+                .min()
+                .unwrap_or(now + VALIDATE_INTERVAL);
+
+        (check_at - now)
+            .to_std()
+            .unwrap_or_default()
+            .max(RETRY_INTERVAL)
+    }
+
+    /// Makes sure `login` has a token that's good for a while yet
+    async fn check_token(&mut self, login: &str) -> Result<(), TwitchError> {
+        let now = Utc::now();
+
+        let slot = self.slot(login);
+
+        let renew = slot
+            .token
+            .as_ref()
+            .is_none_or(|token| token.expires_at - REFRESH_MARGIN <= now);
+        let validate = slot
+            .validated_at
+            .is_none_or(|validated_at| validated_at + VALIDATE_INTERVAL <= now);
+
+        if renew {
+            self.renew_token(login).await
+        } else if validate {
+            self.validate_token(login).await
+        } else {
+            Ok(())
         }
+    }
 
-        let res = self
-            .client
-            .get("https://id.twitch.tv/oauth2/validate")
-            .header(
-                "Authorization",
-                format!("OAuth {}", self.api_info.twitch_access_token),
-            )
-            .send()
-            .await?;
+    /// Twitch's hourly check, which also says how long the token has left
+    async fn validate_token(&mut self, login: &str) -> Result<(), TwitchError> {
+        let Some(access_token) = self
+            .slot(login)
+            .token
+            .as_ref()
+            .map(|token| token.access_token.clone())
+        else {
+            return self.renew_token(login).await;
+        };
+
+        let Some(validated) = self.validate(&access_token).await? else {
+            tracing::info!("Twitch access token for {login} expired");
+            return self.renew_token(login).await;
+        };
 
-        if !res.status().is_success() {
-            tracing::info!("Twitch access token expired");
-
-            if let Err(e) = self.refresh_access_token().await {
-                match e {
-                    TwitchError::TwitchApiError {
-                        request_name: _,
-                        status: _,
-                        message: _,
-                    } => {
-                        access_token(&mut self.api_info)
-                            .await
-                            .map_err(|_e| TwitchError::TokenError)?;
-                    }
-                    e => {
-                        return Err(e);
-                    }
+        let now = Utc::now();
+
+        let token = {
+            let slot = self.slot(login);
+            slot.validated_at = Some(now);
+
+            let Some(token) = slot.token.as_mut() else {
+                return Ok(());
+            };
+
+            token.expires_at = now + Duration::seconds(validated.expires_in);
+            token.scopes = validated.scopes;
+            token.clone()
+        };
+
+        self.db.save_twitch_token(token).await?;
+
+        Ok(())
+    }
+
+    /// Refreshes `login`'s token, or starts the auth flow again if there's
+    /// none yet or twitch won't take the refresh token
+    async fn renew_token(&mut self, login: &str) -> Result<(), TwitchError> {
+        let refresh_token = self
+            .slot(login)
+            .token
+            .as_ref()
+            .map(|token| token.refresh_token.clone());
+
+        let response = match refresh_token {
+            Some(refresh_token) => match self.refresh_access_token(&refresh_token).await {
+                Err(TwitchError::TwitchApiError { status, .. }) if status.is_client_error() => {
+                    self.start_authorize(login);
+                    return Ok(());
                 }
+                result => result?,
+            },
+            None => {
+                self.start_authorize(login);
+                return Ok(());
             }
+        };
 
-            tracing::info!("Finished refreshing token");
-        }
+        self.set_token(login, response).await?;
 
-        self.api_info.expires_at = now.checked_add_signed(Duration::seconds(3600)).unwrap();
+        tracing::info!("Finished refreshing token for {login}");
 
         Ok(())
     }
 
-    pub async fn refresh_access_token(&mut self) -> Result<(), TwitchError> {
-        tracing::info!("Refreshing twitch access token...");
+    /// Sends whoever runs the bot through twitch's auth flow as `login`, in
+    /// its own task so the other account's token can still be handed out
+    fn start_authorize(&mut self, login: &str) {
+        if self.authorizing.contains_key(login) {
+            return;
+        }
+
+        self.authorizing.insert(login.to_string(), Vec::new());
 
-        let http_client = Client::new();
+        // browser logins that timed out never got their code
+        self.auth_states
+            .retain(|_, code_sender| !code_sender.is_closed());
 
-        let res = http_client
-            .post("https://id.twitch.tv/oauth2/token")
+        let login = login.to_string();
+        let scopes = self.scopes(&login);
+        let api_info = self.api_info.clone();
+        let client = self.client.clone();
+        let auth_sender = self.auth_sender.clone();
+        let shutdown = self.shutdown.clone();
+
+        let code_receiver = match api_info.auth_flow {
+            AuthFlow::Browser => {
+                let state = rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(32)
+                    .map(char::from)
+                    .collect::<String>();
+
+                let (code_sender, code_receiver) = oneshot::channel();
+                self.auth_states.insert(state.clone(), code_sender);
+
+                Some((state, code_receiver))
+            }
+            AuthFlow::DeviceCode => None,
+        };
+
+        tokio::spawn(async move {
+            let result = match code_receiver {
+                Some((state, code_receiver)) => {
+                    browser_authorize(&api_info, &login, &scopes, &state, code_receiver, &shutdown)
+                        .await
+                }
+                None => device_authorize(&client, &api_info, &login, &scopes, &shutdown).await,
+            };
+
+            auth_sender.send((login, result)).ok();
+        });
+    }
+
+    /// Takes the token an auth flow task got, and answers everyone who was waiting on it
+    async fn finish_authorize(&mut self, login: &str, result: Result<TokenResponse, TwitchError>) {
+        let waiting = self.authorizing.remove(login).unwrap_or_default();
+
+        match result {
+            Ok(response) => {
+                tracing::info!("received twitch auth token");
+
+                if let Err(e) = self.set_token(login, response).await {
+                    tracing::error!("Failed to set twitch token for {login}: {e:#?}");
+                }
+            }
+            Err(e) => tracing::error!("Failed to authorize {login} with twitch: {e:#?}"),
+        }
+
+        for response in waiting {
+            self.respond(login, response);
+        }
+    }
+
+    async fn refresh_access_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<TokenResponse, TwitchError> {
+        tracing::info!("Refreshing twitch access token...");
+
+        let res = self
+            .client
+            .post(TOKEN_URL)
             .json(&json!({
                 "client_id": self.api_info.client_id,
                 "client_secret": self.api_info.client_secret,
                 "grant_type": "refresh_token",
-                "refresh_token": self.api_info.twitch_refresh_token,
+                "refresh_token": refresh_token,
             }))
             .send()
             .await?;
 
-        if !res.status().is_success() {
-            return Err(TwitchError::TwitchApiError {
-                request_name: String::from("refresh_access_token"),
-                status: res.status(),
-                message: res.text().await.expect("response text"),
-            });
+        let res = check_response("refresh_access_token", res).await?;
+
+        Ok(res.json::<TokenResponse>().await?)
+    }
+
+    /// `None` if twitch doesn't take the token anymore
+    async fn validate(&self, access_token: &str) -> Result<Option<ValidateResponse>, TwitchError> {
+        let res = self
+            .client
+            .get(VALIDATE_URL)
+            .header("Authorization", format!("OAuth {access_token}"))
+            .send()
+            .await?;
+
+        if res.status() == StatusCode::UNAUTHORIZED {
+            return Ok(None);
         }
 
-        let res = res.json::<Value>().await?;
+        let res = check_response("validate_token", res).await?;
+
+        Ok(Some(res.json::<ValidateResponse>().await?))
+    }
 
-        let config_str = std::fs::read_to_string(&APP.config.config_path)?;
+    /// Takes a new token once twitch says it's `login`'s, then stores it
+    /// and lets everyone holding the old one know
+    async fn set_token(&mut self, login: &str, response: TokenResponse) -> Result<(), TwitchError> {
+        let Some(validated) = self.validate(&response.access_token).await? else {
+            return Err(TwitchError::TokenError);
+        };
+
+        if !validated.login.eq_ignore_ascii_case(login) {
+            return Err(TwitchError::WrongAccount {
+                expected: login.to_string(),
+                got: validated.login,
+            });
+        }
 
-        let mut configs = toml::from_str::<ApiInfo>(&config_str)?;
+        let missing = self
+            .scopes(login)
+            .into_iter()
+            .filter(|scope| !validated.scopes.iter().any(|granted| granted == scope))
+            .collect::<Vec<_>>();
 
-        configs.twitch.twitch_refresh_token = res["refresh_token"].as_str().unwrap().to_string();
-        configs.twitch.twitch_access_token = res["access_token"].as_str().unwrap().to_string();
+        if !missing.is_empty() {
+            tracing::warn!("twitch token for {login} is missing scopes: {missing:?}");
+        }
 
-        std::fs::write(
-            &APP.config.config_path,
-            toml::to_string(&configs).expect("parse api info struct to string"),
-        )
-        .ok();
+        let now = Utc::now();
 
-        self.api_info.twitch_refresh_token = configs.twitch.twitch_refresh_token;
-        self.api_info.twitch_access_token = configs.twitch.twitch_access_token;
+        let token = UserToken {
+            login: validated.login,
+            user_id: validated.user_id,
+            access_token: response.access_token,
+            refresh_token: response.refresh_token,
+            scopes: validated.scopes,
+            expires_at: now + Duration::seconds(validated.expires_in),
+        };
+
+        *self.slot(login) = TokenSlot {
+            token: Some(token.clone()),
+            validated_at: Some(now),
+        };
+
+        self.db.save_twitch_token(token.clone()).await?;
+
+        for role in TokenRole::ALL {
+            if self.login(role) == login && self.changed_sender.send((role, token.clone())).is_err()
+            {
+                tracing::debug!("no one is holding on to the {role:?} twitch token");
+            }
+        }
 
         Ok(())
     }
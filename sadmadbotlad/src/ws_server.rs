@@ -1,107 +1,152 @@
+use axum::{
+    extract::{
+        ConnectInfo, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+};
 use futures_util::{SinkExt, StreamExt, stream::SplitSink};
-use std::{
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
-    time::Duration,
+use sadmadbotlad_protocol::{
+    Capability, ChatEvent, ChatLogRequest, ClientMessage, Credits, EmoteEvent, ErrorCode,
+    EventCategory, HistoryRequest, PROTOCOL_VERSION, ServerMessage,
 };
-use tokio::net::{TcpListener, TcpStream};
-use tokio_tungstenite::{WebSocketStream, accept_async, tungstenite::Message};
-
-use crate::{APP, Alert, db::DBMessage};
+use std::{collections::HashSet, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
 
-pub async fn ws_server(
-    alerts_sender: tokio::sync::broadcast::Sender<Alert>,
-    db_tx: std::sync::mpsc::Sender<DBMessage>,
-) -> anyhow::Result<()> {
-    let port = APP.config.port + 1000;
-    tracing::info!("Starting WebSocket Server on port {port}");
+use crate::{Alert, WsAuth, db::Db};
 
-    let ip_address = Ipv4Addr::new(127, 0, 0, 1);
-    let address = SocketAddrV4::new(ip_address, port);
-    let listener = TcpListener::bind(address).await?;
+/// Upper bound for a single history page, whatever the client asks for
+const MAX_HISTORY_PAGE: u32 = 100;
 
-    while let Ok((stream, _)) = listener.accept().await {
-        let peer = stream
-            .peer_addr()
-            .expect("connected streams should have a peer address");
+/// Everything an `/ws/alerts` connection needs
+#[derive(Clone)]
+pub struct AlertsWsState {
+    pub alerts_sender: tokio::sync::broadcast::Sender<Alert>,
+    pub credits_sender: tokio::sync::broadcast::Sender<Credits>,
+    pub chat_sender: tokio::sync::broadcast::Sender<ChatEvent>,
+    pub emotes_sender: tokio::sync::broadcast::Sender<EmoteEvent>,
+    pub db: Db,
+    pub ws_auth: Arc<WsAuth>,
+    pub shutdown: CancellationToken,
+}
 
-        tracing::debug!("Peer address: {}", peer);
-        let db_tx = db_tx.clone();
-        let alerts_sender = alerts_sender.clone();
+/// What the send half needs to know about the client
+#[derive(Debug, Clone)]
+struct ClientState {
+    /// `None` until the client authenticates
+    capability: Option<Capability>,
+    categories: HashSet<EventCategory>,
+}
 
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(alerts_sender, peer, stream, db_tx).await {
-                tracing::error!("Error processing connection: {:?}", e);
-            }
-        });
+impl ClientState {
+    fn receives(&self, category: EventCategory) -> bool {
+        self.capability.is_some() && self.categories.contains(&category)
     }
+}
 
-    Ok(())
+pub async fn alerts_ws(
+    ws: WebSocketUpgrade,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    State(state): State<AlertsWsState>,
+) -> Response {
+    tracing::debug!("Peer address: {}", peer);
+
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = handle_connection(state, peer, socket).await {
+            tracing::error!("Error processing connection: {:?}", e);
+        }
+    })
 }
 
 async fn handle_connection(
-    alerts_sender: tokio::sync::broadcast::Sender<Alert>,
+    AlertsWsState {
+        alerts_sender,
+        credits_sender,
+        chat_sender,
+        emotes_sender,
+        db,
+        ws_auth,
+        shutdown,
+    }: AlertsWsState,
     peer: SocketAddr,
-    stream: TcpStream,
-    db_tx: std::sync::mpsc::Sender<DBMessage>,
+    ws_stream: WebSocket,
 ) -> anyhow::Result<()> {
+    let credits_receiver = credits_sender.subscribe();
     let alerts_receiver = alerts_sender.subscribe();
-
-    let ws_stream = accept_async(stream).await.expect("Failed to accept");
+    let chat_receiver = chat_sender.subscribe();
+    let emotes_receiver = emotes_sender.subscribe();
 
     let (ws_sender, mut ws_receiver) = ws_stream.split();
 
     let (ws_sender_tx, ws_sender_rx) = tokio::sync::mpsc::channel(5);
 
+    let (state_tx, state_rx) = watch::channel(ClientState {
+        capability: ws_auth.initial_capability(&peer),
+        // clients get every broadcast until they say otherwise
+        categories: EventCategory::ALL.into_iter().collect(),
+    });
+
     let t_handle = tokio::spawn(async move {
-        handle_websocket_send(alerts_receiver, ws_sender, ws_sender_rx, peer).await;
+        handle_websocket_send(
+            alerts_receiver,
+            credits_receiver,
+            chat_receiver,
+            emotes_receiver,
+            state_rx,
+            ws_sender,
+            ws_sender_rx,
+            peer,
+            shutdown,
+        )
+        .await;
     });
 
+    ws_sender_tx
+        .send(ServerMessage::Hello {
+            version: PROTOCOL_VERSION,
+        })
+        .await?;
+
+    let capability = state_tx.borrow().capability;
+    if let Some(capability) = capability {
+        ws_sender_tx
+            .send(ServerMessage::Authenticated { capability })
+            .await?;
+    }
+
     while let Some(msg) = ws_receiver.next().await {
         match msg {
             Ok(Message::Close(msg)) => {
                 tracing::debug!("close message: {msg:?}");
-                // ws_sender.lock().await.close().await?;
                 break;
             }
             Ok(Message::Text(msg)) => {
-                if msg.starts_with("db") {
-                    tracing::debug!("db was requested ");
-                    let (tx, rx) = crate::oneshot();
-                    db_tx.send(DBMessage::GetEvents(tx)).unwrap();
-                    let Ok(events) = rx.recv() else {
-                        continue;
-                    };
-
-                    let events = serde_json::to_string(
-                        &events
-                            .into_iter()
-                            .rev()
-                            .map(|a| Alert {
-                                new: false,
-                                r#type: a.alert_type,
-                            })
-                            .collect::<Vec<_>>(),
-                    )?;
-
-                    tracing::debug!("events: {events}");
-
-                    ws_sender_tx
-                        .send(Message::Text(format!("db::{}", events).into()))
-                        .await?;
-
-                    continue;
-                }
+                tracing::debug!("text message: {} - from client {peer}", msg.as_str());
+
+                let reply = match serde_json::from_str::<ClientMessage>(msg.as_str()) {
+                    Ok(message) => {
+                        handle_client_message(message, &alerts_sender, &state_tx, &ws_auth, &db)
+                            .await
+                    }
+                    Err(e) => {
+                        tracing::warn!("malformed message from client {peer}: {e}");
+                        Some(ServerMessage::error(
+                            ErrorCode::MalformedMessage,
+                            e.to_string(),
+                        ))
+                    }
+                };
 
-                tracing::debug!("text message: {msg} - from client {peer}");
-                let alert = serde_json::from_str::<Alert>(&msg).expect("alert");
-                alerts_sender.send(alert).expect("send alert");
+                if let Some(reply) = reply {
+                    ws_sender_tx.send(reply).await?;
+                }
             }
             Ok(Message::Pong(_ping)) => {
                 // tracing::debug!("Events Ws:: Pong {ping:?} - from client {peer}");
             }
             Ok(msg) => {
                 tracing::debug!("message: {msg:?} - from client {peer}");
-                // ws_sender.lock().await.send(msg).await?;
             }
             Err(e) => {
                 tracing::error!("client error: {e}");
@@ -116,35 +161,209 @@ async fn handle_connection(
     Ok(())
 }
 
+async fn handle_client_message(
+    message: ClientMessage,
+    alerts_sender: &tokio::sync::broadcast::Sender<Alert>,
+    state_tx: &watch::Sender<ClientState>,
+    ws_auth: &WsAuth,
+    db: &Db,
+) -> Option<ServerMessage> {
+    let required = match &message {
+        ClientMessage::Hello { .. } | ClientMessage::Auth { .. } => None,
+        ClientMessage::History(_) | ClientMessage::Subscribe { .. } | ClientMessage::Stats => {
+            Some(Capability::Read)
+        }
+        ClientMessage::Replay { .. }
+        | ClientMessage::Alert(_)
+        | ClientMessage::DeleteEvent { .. }
+        | ClientMessage::HideEvent { .. }
+        | ClientMessage::ChatLog(_) => Some(Capability::Control),
+    };
+
+    if let Some(required) = required {
+        match state_tx.borrow().capability {
+            None => {
+                return Some(ServerMessage::error(
+                    ErrorCode::Unauthorized,
+                    "authenticate first",
+                ));
+            }
+            Some(capability) if capability < required => {
+                return Some(ServerMessage::error(
+                    ErrorCode::Forbidden,
+                    "this token is read-only",
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+
+    match message {
+        ClientMessage::Hello { version } if version == PROTOCOL_VERSION => {
+            Some(ServerMessage::Hello {
+                version: PROTOCOL_VERSION,
+            })
+        }
+        ClientMessage::Hello { version } => Some(ServerMessage::error(
+            ErrorCode::UnsupportedVersion,
+            format!("client speaks version {version}, server speaks version {PROTOCOL_VERSION}"),
+        )),
+        ClientMessage::Auth { token } => match ws_auth.capability(&token) {
+            Some(capability) => {
+                state_tx.send_modify(|state| state.capability = Some(capability));
+                Some(ServerMessage::Authenticated { capability })
+            }
+            None => Some(ServerMessage::error(
+                ErrorCode::Unauthorized,
+                "unknown token",
+            )),
+        },
+        ClientMessage::History(request) => {
+            let request = HistoryRequest {
+                limit: request.limit.clamp(1, MAX_HISTORY_PAGE),
+                ..request
+            };
+
+            Some(match db.get_events_page(request).await {
+                Ok(page) => ServerMessage::History(page),
+                Err(e) => {
+                    tracing::error!("failed to read the history: {e}");
+                    ServerMessage::error(ErrorCode::Internal, "could not read the history")
+                }
+            })
+        }
+        ClientMessage::Replay { id } => {
+            match db.get_event(id).await {
+                Ok(Some(event)) => {
+                    // not new, so the activity feed doesn't list it twice
+                    let _ = alerts_sender.send(Alert {
+                        new: false,
+                        r#type: event.alert_type,
+                    });
+                    None
+                }
+                Ok(None) => Some(ServerMessage::error(
+                    ErrorCode::NotFound,
+                    format!("no event with id {id}"),
+                )),
+                Err(e) => {
+                    tracing::error!("failed to read event {id}: {e}");
+                    Some(ServerMessage::error(
+                        ErrorCode::Internal,
+                        "could not read the event",
+                    ))
+                }
+            }
+        }
+        ClientMessage::Subscribe { categories } => {
+            state_tx.send_modify(|state| state.categories = categories.into_iter().collect());
+            None
+        }
+        ClientMessage::Stats => Some(match db.get_stream_stats().await {
+            Ok(stats) => ServerMessage::Stats(Box::new(stats)),
+            Err(e) => {
+                tracing::error!("failed to read the stats: {e}");
+                ServerMessage::error(ErrorCode::Internal, "could not read the stats")
+            }
+        }),
+        ClientMessage::Alert(alert) => {
+            let _ = alerts_sender.send(alert);
+            None
+        }
+        ClientMessage::DeleteEvent { id } => Some(match db.delete_event(id).await {
+            Ok(true) => ServerMessage::EventDeleted { id },
+            Ok(false) => {
+                ServerMessage::error(ErrorCode::NotFound, format!("no event with id {id}"))
+            }
+            Err(e) => {
+                tracing::error!("failed to delete event {id}: {e}");
+                ServerMessage::error(ErrorCode::Internal, "could not delete the event")
+            }
+        }),
+        ClientMessage::HideEvent { id, hidden } => {
+            Some(match db.set_event_hidden(id, hidden).await {
+                Ok(Some(event)) => ServerMessage::EventUpdated(event),
+                Ok(None) => {
+                    ServerMessage::error(ErrorCode::NotFound, format!("no event with id {id}"))
+                }
+                Err(e) => {
+                    tracing::error!("failed to hide event {id}: {e}");
+                    ServerMessage::error(ErrorCode::Internal, "could not update the event")
+                }
+            })
+        }
+        ClientMessage::ChatLog(request) => {
+            let request = ChatLogRequest {
+                limit: request.limit.clamp(1, MAX_HISTORY_PAGE),
+                ..request
+            };
+
+            Some(match db.get_chat_log(request).await {
+                Ok(page) => ServerMessage::ChatLog(page),
+                Err(e) => {
+                    tracing::error!("failed to read the chat log: {e}");
+                    ServerMessage::error(ErrorCode::Internal, "could not read the chat log")
+                }
+            })
+        }
+    }
+}
+
 async fn handle_websocket_send(
     mut front_end_event_receiver: tokio::sync::broadcast::Receiver<Alert>,
-    mut ws_sender: SplitSink<WebSocketStream<TcpStream>, Message>,
-    mut ws_sender_rx: tokio::sync::mpsc::Receiver<Message>,
+    mut credits_receiver: tokio::sync::broadcast::Receiver<Credits>,
+    mut chat_receiver: tokio::sync::broadcast::Receiver<ChatEvent>,
+    mut emotes_receiver: tokio::sync::broadcast::Receiver<EmoteEvent>,
+    state_rx: watch::Receiver<ClientState>,
+    mut ws_sender: SplitSink<WebSocket, Message>,
+    mut ws_sender_rx: tokio::sync::mpsc::Receiver<ServerMessage>,
     _peer: SocketAddr,
+    shutdown: CancellationToken,
 ) {
     let mut interval = tokio::time::interval(Duration::from_secs(5));
 
     loop {
-        tokio::select! {
-            Ok(msg) = front_end_event_receiver.recv() => {
-                tracing::debug!("Sending Ws:: {msg:?}");
-
-                let alert = serde_json::to_string(&msg).expect("alert");
-
-                if let Err(e) = ws_sender.send(Message::Text(alert.into())).await {
-                    tracing::error!("websocket sender: {e}");
-                }
-            }
-            Some(send) = ws_sender_rx.recv() => {
-                if let Err(e) = ws_sender.send(send).await {
+        let message = tokio::select! {
+            Ok(alert) = front_end_event_receiver.recv() => ServerMessage::Alert(alert),
+            Ok(credits) = credits_receiver.recv() => ServerMessage::Credits(credits),
+            Ok(chat) = chat_receiver.recv() => ServerMessage::Chat(chat),
+            Ok(emotes) = emotes_receiver.recv() => ServerMessage::Emotes(emotes),
+            Some(reply) = ws_sender_rx.recv() => reply,
+            _ = shutdown.cancelled() => {
+                if let Err(e) = ws_sender.send(Message::Close(None)).await {
                     tracing::error!("websocket sender: {e}");
                 }
+                return;
             }
             _ = interval.tick() => {
-                if let Err(e) = ws_sender.send(Message::Ping(tokio_tungstenite::tungstenite::Bytes::new())).await {
+                if let Err(e) = ws_sender.send(Message::Ping(Default::default())).await {
                     tracing::error!("websocket sender: {e}");
                 }
+                continue;
             }
+        };
+
+        // the borrow can't be held across the awaits below
+        let filtered = message
+            .category()
+            .is_some_and(|category| !state_rx.borrow().receives(category));
+
+        if filtered {
+            continue;
+        }
+
+        tracing::debug!("Sending Ws:: {message:?}");
+
+        let text = match serde_json::to_string(&message) {
+            Ok(text) => text,
+            Err(e) => {
+                tracing::error!("could not serialize {message:?}: {e}");
+                continue;
+            }
+        };
+
+        if let Err(e) = ws_sender.send(Message::Text(text.into())).await {
+            tracing::error!("websocket sender: {e}");
         }
     }
 }
@@ -1,9 +1,22 @@
+import json
+import sys
+
 import websocket
 
 ws = websocket.WebSocket()
 
-ws.connect("ws://localhost:3000")
+ws.connect("ws://localhost:8080/ws/alerts")
+
+print(ws.recv())
+
+# a control token from the [ws_auth] table in config.toml, if any are configured
+if len(sys.argv) > 1:
+    ws.send(json.dumps({"op": "auth", "data": {"token": sys.argv[1]}}))
+    print(ws.recv())
 
-ws.send("Alert { new: true, type: Raid { from: \"lmao\", viewers: 9999 } }")
+ws.send(json.dumps({
+    "op": "alert",
+    "data": {"new": True, "type": {"Raid": {"from": "lmao", "viewers": 9999}}},
+}))
 
 print(ws.recv())